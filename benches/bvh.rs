@@ -0,0 +1,170 @@
+//! Compares tracing rays through the flattened `BvhNode` against the boxed `BvhTree` it's built
+//! from, using the example scene files (which are the built in scenes). Both are built with the
+//! surface area heuristic, so `MedianSplit` keeps the old random-axis median split around as a
+//! baseline for them.
+//!
+//! Run with `cargo bench --bench bvh`.
+
+use std::{cmp::Ordering, path::Path};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use raytracer::{
+    aabb::Aabb,
+    bvh_node::{BvhNode, BvhTree},
+    camera::Camera,
+    hittable::{HitRecord, Hittable},
+    ray::Ray,
+    scene_file::SceneDescription,
+};
+
+const SCENES: &[&str] = &[
+    "three-body",
+    "many-balls",
+    "checkers-colliding",
+    "earth-globe",
+    "cornel-box",
+    "final-scene",
+    "mesh-demo",
+];
+
+/// The BVH as it was before the surface area heuristic: sort along a random axis and split the
+/// objects in half.
+#[derive(Debug)]
+struct MedianSplit {
+    left: Box<dyn Hittable>,
+    right_maybe: Option<Box<dyn Hittable>>,
+    abox: Aabb,
+}
+
+impl MedianSplit {
+    fn new(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> MedianSplit {
+        // Seeded so every run benchmarks the same tree.
+        Self::build(objects, time0, time1, &mut SmallRng::seed_from_u64(0))
+    }
+
+    fn build(
+        mut objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        rng: &mut SmallRng,
+    ) -> MedianSplit {
+        let axis = rng.gen_range(0, 3);
+        let comparator = |a: &dyn Hittable, b: &dyn Hittable| {
+            let box_a = a
+                .bounding_box(0.0, 0.0)
+                .expect("A must have a bounding box");
+            let box_b = b
+                .bounding_box(0.0, 0.0)
+                .expect("B must have a bounding box");
+            box_a.min()[axis]
+                .partial_cmp(&box_b.min()[axis])
+                .unwrap_or(Ordering::Equal)
+        };
+
+        assert!(!objects.is_empty());
+        let (left, right): (Box<dyn Hittable>, Option<Box<dyn Hittable>>) = if objects.len() == 1 {
+            (objects.pop().unwrap(), None)
+        } else if objects.len() == 2 {
+            objects.sort_by(|a, b| comparator(a.as_ref(), b.as_ref()));
+            let second = objects.pop().unwrap();
+            (objects.pop().unwrap(), Some(second))
+        } else {
+            objects.sort_by(|a, b| comparator(a.as_ref(), b.as_ref()));
+            let half2 = objects.split_off(objects.len() / 2);
+            (
+                Box::new(Self::build(objects, time0, time1, rng)),
+                Some(Box::new(Self::build(half2, time0, time1, rng))),
+            )
+        };
+
+        let left_box = left.bounding_box(time0, time1).unwrap();
+        let abox = match right {
+            Some(ref right) => {
+                Aabb::surrounding_box(left_box, right.bounding_box(time0, time1).unwrap())
+            }
+            None => left_box,
+        };
+
+        MedianSplit {
+            left,
+            right_maybe: right,
+            abox,
+        }
+    }
+}
+
+impl Hittable for MedianSplit {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if !self.abox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let hit_right = self.right_maybe.as_ref().and_then(|right| {
+            let hit_left_time = hit_left.as_ref().map(|h| h.t).unwrap_or(t_max);
+            right.hit(r, t_min, hit_left_time)
+        });
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.abox)
+    }
+}
+
+/// Primary rays for a small image of the scene, so every run traces the same rays.
+fn camera_rays(desc: &SceneDescription) -> Vec<Ray> {
+    let (width, height) = (64, 36);
+    let cam = Camera::new(desc.camera, width as f64 / height as f64);
+    let mut rays = Vec::with_capacity(width * height);
+    for j in 0..height {
+        for i in 0..width {
+            let u = i as f64 / (width - 1) as f64;
+            let v = j as f64 / (height - 1) as f64;
+            rays.push(cam.get_ray(u, v));
+        }
+    }
+    rays
+}
+
+fn trace_all(world: &dyn Hittable, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|r| world.hit(**r, 0.001, f64::INFINITY).is_some())
+        .count()
+}
+
+fn bvh_layouts(c: &mut Criterion) {
+    let scenes_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+    let mut group = c.benchmark_group("bvh");
+
+    for &scene in SCENES {
+        let desc = SceneDescription::load(scenes_dir.join(scene).with_extension("ron"))
+            .expect("example scene should load");
+        let (time0, time1) = (desc.camera.time0, desc.camera.time1);
+        let build = || {
+            desc.build_objects(&scenes_dir)
+                .expect("example scene should build")
+        };
+        let tree = BvhTree::new(build(), time0, time1);
+        let flat = BvhNode::new(build(), time0, time1);
+        let median = MedianSplit::new(build(), time0, time1);
+        let rays = camera_rays(&desc);
+
+        group.bench_with_input(BenchmarkId::new("tree", scene), &rays, |b, rays| {
+            b.iter(|| trace_all(&tree, black_box(rays)))
+        });
+        group.bench_with_input(BenchmarkId::new("flat", scene), &rays, |b, rays| {
+            b.iter(|| trace_all(&flat, black_box(rays)))
+        });
+        group.bench_with_input(BenchmarkId::new("median-split", scene), &rays, |b, rays| {
+            b.iter(|| trace_all(&median, black_box(rays)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bvh_layouts);
+criterion_main!(benches);
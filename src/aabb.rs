@@ -3,21 +3,21 @@ use crate::{ray::Ray, vec3::Point3};
 use derive_more::Constructor;
 
 #[derive(Debug, Clone, Copy, Constructor)]
-pub(crate) struct Aabb {
+pub struct Aabb {
     pub minimum: Point3,
     pub maximum: Point3,
 }
 
 impl Aabb {
-    pub(crate) fn min(&self) -> Point3 {
+    pub fn min(&self) -> Point3 {
         self.minimum
     }
 
-    pub(crate) fn max(&self) -> Point3 {
+    pub fn max(&self) -> Point3 {
         self.maximum
     }
 
-    pub(crate) fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+    pub fn hit(&self, r: Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
             let t0 = f64::min(
                 (self.minimum[a] - r.origin()[a]) / r.direction()[a],
@@ -27,8 +27,8 @@ impl Aabb {
                 (self.minimum[a] - r.origin()[a]) / r.direction()[a],
                 (self.maximum[a] - r.origin()[a]) / r.direction()[a],
             );
-            let t_min = f64::max(t0, t_min);
-            let t_max = f64::min(t1, t_max);
+            t_min = f64::max(t0, t_min);
+            t_max = f64::min(t1, t_max);
             if t_max <= t_min {
                 return false;
             }
@@ -36,7 +36,16 @@ impl Aabb {
         true
     }
 
-    pub(crate) fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
         let small = Point3::new(
             f64::min(box0.min().x, box1.min().x),
             f64::min(box0.min().y, box1.min().y),
@@ -50,3 +59,20 @@ impl Aabb {
         Aabb::new(small, big)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn hit_needs_overlap_on_all_axes_at_once() {
+        let unit = Aabb::new(Point3::zero(), Point3::one());
+        let through = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), None);
+        assert!(unit.hit(through, 0.0, f64::INFINITY));
+
+        // passes through the x slab at t in [1, 2] and the y slab at t in [3, 4], so never inside
+        let past = Ray::new(Point3::new(-1.0, -3.0, 0.5), Vec3::new(1.0, 1.0, 0.0), None);
+        assert!(!unit.hit(past, 0.0, f64::INFINITY));
+    }
+}
@@ -5,11 +5,12 @@ use crate::{
     hittable::{HitRecord, Hittable},
     material::Material,
     ray::Ray,
+    util::random_pair,
     vec3::{Point3, Vec3},
 };
 
 #[derive(Debug)]
-pub(crate) struct XyRect {
+pub struct XyRect {
     x0: f64,
     x1: f64,
     y0: f64,
@@ -19,14 +20,7 @@ pub(crate) struct XyRect {
 }
 
 impl XyRect {
-    pub(crate) fn new(
-        x0: f64,
-        x1: f64,
-        y0: f64,
-        y1: f64,
-        k: f64,
-        material: Box<dyn Material>,
-    ) -> Self {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Box<dyn Material>) -> Self {
         Self {
             x0,
             x1,
@@ -39,7 +33,7 @@ impl XyRect {
 }
 
 impl Hittable for XyRect {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin().z) / r.direction().z;
         if t < t_min || t > t_max {
             return None;
@@ -70,10 +64,25 @@ impl Hittable for XyRect {
             Point3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        rect_pdf_value(self, r, area)
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64) -> Vec3 {
+        let (r1, r2) = random_pair();
+        let random_point = Point3::new(
+            self.x0 + r1 * (self.x1 - self.x0),
+            self.y0 + r2 * (self.y1 - self.y0),
+            self.k,
+        );
+        random_point - origin
+    }
 }
 
 #[derive(Debug, Constructor)]
-pub(crate) struct XzRect {
+pub struct XzRect {
     x0: f64,
     x1: f64,
     z0: f64,
@@ -83,7 +92,7 @@ pub(crate) struct XzRect {
 }
 
 impl Hittable for XzRect {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin().y) / r.direction().y;
         if t < t_min || t > t_max {
             return None;
@@ -114,10 +123,25 @@ impl Hittable for XzRect {
             Point3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        rect_pdf_value(self, r, area)
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64) -> Vec3 {
+        let (r1, r2) = random_pair();
+        let random_point = Point3::new(
+            self.x0 + r1 * (self.x1 - self.x0),
+            self.k,
+            self.z0 + r2 * (self.z1 - self.z0),
+        );
+        random_point - origin
+    }
 }
 
 #[derive(Debug, Constructor)]
-pub(crate) struct YzRect {
+pub struct YzRect {
     y0: f64,
     y1: f64,
     z0: f64,
@@ -127,7 +151,7 @@ pub(crate) struct YzRect {
 }
 
 impl Hittable for YzRect {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin().x) / r.direction().x;
         if t < t_min || t > t_max {
             return None;
@@ -158,4 +182,32 @@ impl Hittable for YzRect {
             Point3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        rect_pdf_value(self, r, area)
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64) -> Vec3 {
+        let (r1, r2) = random_pair();
+        let random_point = Point3::new(
+            self.k,
+            self.y0 + r1 * (self.y1 - self.y0),
+            self.z0 + r2 * (self.z1 - self.z0),
+        );
+        random_point - origin
+    }
+}
+
+/// Density of picking the direction of `r` by choosing a uniformly random point on a rect with the
+/// given `area`.
+fn rect_pdf_value(rect: &dyn Hittable, r: Ray, area: f64) -> f64 {
+    match rect.hit(r, 0.001, f64::INFINITY) {
+        Some(rec) => {
+            let distance_squared = rec.t * rec.t * r.direction().length_squared();
+            let cosine = (r.direction().dot(rec.normal) / r.direction().length()).abs();
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
 }
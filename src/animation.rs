@@ -0,0 +1,327 @@
+//! Motion blur for any object: an [`AnimatedTransform`] moves, turns and scales the object it
+//! wraps between [`Keyframe`]s, following the time of each ray.
+
+use std::f64::consts::PI;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable, Placement},
+    matrix::Quaternion,
+    ray::Ray,
+    vec3::{lerp, Point3, Vec3},
+};
+
+/// How far (in radians) an object can turn between the placements that are sampled to find its
+/// bounding box over the shutter interval.
+const MAX_ROTATION_STEP: f64 = PI / 180.0;
+
+/// Where an object is at a point in time: scaled, then rotated, then moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    /// A keyframe at `time` that leaves the object where it is.
+    pub fn at(time: f64) -> Self {
+        Self {
+            time,
+            translation: Vec3::zero(),
+            rotation: Quaternion::IDENTITY,
+            scale: Vec3::one(),
+        }
+    }
+
+    pub fn translation(self, translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..self
+        }
+    }
+
+    /// Rotation by `angle` degrees around `axis`, anticlockwise when looking back along it.
+    pub fn rotation(self, axis: Vec3, angle: f64) -> Self {
+        self.rotation_quaternion(Quaternion::from_axis_angle(axis, angle))
+    }
+
+    pub fn rotation_quaternion(self, rotation: Quaternion) -> Self {
+        Self { rotation, ..self }
+    }
+
+    /// None of the factors can be zero.
+    pub fn scale(self, scale: Vec3) -> Self {
+        Self { scale, ..self }
+    }
+
+    fn placement(&self) -> Placement {
+        Placement::IDENTITY
+            .scale(self.scale)
+            .rotate_quaternion(self.rotation)
+            .translate(self.translation)
+    }
+
+    /// The keyframe a fraction `t` of the way from `self` to `other`.
+    fn interpolate(&self, other: &Keyframe, t: f64) -> Keyframe {
+        Keyframe {
+            time: self.time + t * (other.time - self.time),
+            translation: lerp(t, self.translation, other.translation),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: lerp(t, self.scale, other.scale),
+        }
+    }
+
+    /// The angle in radians that the object turns through going from `self` to `other`.
+    fn rotation_angle(&self, other: &Keyframe) -> f64 {
+        let cos_half = self
+            .rotation
+            .normalized()
+            .dot(other.rotation.normalized())
+            .abs();
+        2.0 * cos_half.min(1.0).acos()
+    }
+}
+
+/// An object that moves between keyframes: translations and scales are interpolated linearly and
+/// rotations with [`Quaternion::slerp`]. Before the first keyframe and after the last, the object
+/// stays where they put it.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform<H: Hittable> {
+    obj: H,
+    /// Sorted by time, with no two at the same time
+    keyframes: Vec<Keyframe>,
+}
+
+impl<H: Hittable> AnimatedTransform<H> {
+    /// None if there are no keyframes, any of them isn't at a finite time, two of them are at the
+    /// same time, or the scale would be zero at some point: either a keyframe scales by zero, or a
+    /// factor changes sign between one keyframe and the next.
+    pub fn new(obj: H, mut keyframes: Vec<Keyframe>) -> Option<Self> {
+        if keyframes.is_empty()
+            || keyframes
+                .iter()
+                .any(|k| !k.time.is_finite() || (0..3).any(|axis| k.scale[axis] == 0.0))
+        {
+            return None;
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if keyframes.windows(2).any(|pair| {
+            pair[0].time == pair[1].time
+                || (0..3).any(|axis| {
+                    pair[0].scale[axis].is_sign_negative() != pair[1].scale[axis].is_sign_negative()
+                })
+        }) {
+            return None;
+        }
+        Some(Self { obj, keyframes })
+    }
+
+    /// Where the object is at `time`.
+    pub fn keyframe_at(&self, time: f64) -> Keyframe {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            self.keyframes[0]
+        } else if next == self.keyframes.len() {
+            self.keyframes[next - 1]
+        } else {
+            let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+            a.interpolate(b, (time - a.time) / (b.time - a.time))
+        }
+    }
+
+    fn placement_at(&self, time: f64) -> Placement {
+        self.keyframe_at(time).placement()
+    }
+}
+
+impl<H: Hittable> Hittable for AnimatedTransform<H> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.placement_at(r.time()).hit(&self.obj, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let bbox = self.obj.bounding_box(time0, time1)?;
+        let mut result = self.placement_at(time0).bounding_box(bbox);
+        result = Aabb::surrounding_box(result, self.placement_at(time1).bounding_box(bbox));
+
+        // translations and scales move the corners of the box in straight lines, so between
+        // keyframes it's enough to look at the ends; rotations swing them round in arcs, so
+        // sample the placement often enough that the arcs can't stray far past the boxes
+        let corners = (0..8).map(|corner| {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) == 0 {
+                    bbox.min()[axis]
+                } else {
+                    bbox.max()[axis]
+                }
+            };
+            Point3::new(pick(0), pick(1), pick(2))
+        });
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let (start, end) = (a.time.max(time0), b.time.min(time1));
+            if start > end {
+                continue;
+            }
+            let angle = a.rotation_angle(b) * (end - start) / (b.time - a.time);
+            let steps = (angle / MAX_ROTATION_STEP).ceil().max(1.0) as usize;
+            // a corner at `radius` from the center of rotation strays at most this far from the
+            // straight line between where it is at consecutive samples
+            let radius = corners
+                .clone()
+                .flat_map(|c| {
+                    [a.scale, b.scale].map(|s| Vec3::new(s.x * c.x, s.y * c.y, s.z * c.z))
+                })
+                .map(|c| c.length())
+                .fold(0.0, f64::max);
+            let padding = Vec3::one() * (radius * angle / steps as f64 / 2.0);
+            for step in 0..=steps {
+                let time = start + (end - start) * step as f64 / steps as f64;
+                let placed = self.placement_at(time).bounding_box(bbox);
+                result = Aabb::surrounding_box(
+                    result,
+                    Aabb::new(placed.min() - padding, placed.max() + padding),
+                );
+            }
+        }
+        Some(result)
+    }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.placement_at(r.time()).pdf_value(&self.obj, r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.placement_at(time)
+            .random_direction(&self.obj, origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.placement_at(r.time())
+            .hit_surface(&self.obj, r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.placement_at(r.time())
+            .transmittance(&self.obj, r, t_min, t_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{box3d::Box3D, material::DiffuseLambertian, util, vec3::Color};
+
+    fn spinning_bar() -> AnimatedTransform<Box3D> {
+        let bar = Box3D::new(
+            Point3::new(-2.0, -0.1, -0.1),
+            Point3::new(2.0, 0.1, 0.1),
+            DiffuseLambertian::new(Color::one()),
+        );
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        AnimatedTransform::new(
+            bar,
+            vec![
+                Keyframe::at(1.0)
+                    .rotation(y, 90.0)
+                    .translation(Vec3::new(10.0, 0.0, 0.0))
+                    .scale(Vec3::new(0.5, 1.0, 1.0)),
+                Keyframe::at(0.0),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn hits_follow_the_time_of_the_ray() {
+        let bar = spinning_bar();
+        let down = |x: f64, z: f64, time: f64| {
+            bar.hit(
+                Ray::new(
+                    Point3::new(x, 5.0, z),
+                    Vec3::new(0.0, -1.0, 0.0),
+                    Some(time),
+                ),
+                0.001,
+                f64::INFINITY,
+            )
+        };
+        assert!(down(1.5, 0.0, 0.0).is_some());
+        assert!(down(1.5, 0.0, 1.0).is_none());
+        // at the end it's half as long, and turned to lie along z
+        assert!(down(10.0, 0.9, 1.0).is_some());
+        assert!(down(10.0, 1.1, 1.0).is_none());
+        assert!(down(10.5, 0.0, 1.0).is_none());
+        // and stays there afterwards
+        assert!(down(10.0, -0.9, 2.0).is_some());
+        let halfway = bar.keyframe_at(0.5);
+        assert_eq!(halfway.translation, Vec3::new(5.0, 0.0, 0.0));
+        assert_eq!(halfway.scale, Vec3::new(0.75, 1.0, 1.0));
+    }
+
+    #[test]
+    fn bounding_box_covers_the_whole_shutter_interval() {
+        let bar = spinning_bar();
+        let (time0, time1) = (0.2, 0.7);
+        let bbox = bar.bounding_box(time0, time1).unwrap();
+        let inside = |p: Point3| (0..3).all(|a| bbox.min()[a] <= p[a] && p[a] <= bbox.max()[a]);
+        util::with_seed(7, || {
+            for _ in 0..10_000 {
+                let time = util::random_double(time0, time1);
+                let matrix = *bar.placement_at(time).matrix();
+                let p = Point3::new(
+                    util::random_double(-2.0, 2.0),
+                    util::random_double(-0.1, 0.1),
+                    util::random_double(-0.1, 0.1),
+                );
+                assert!(inside(matrix.transform_point(p)), "{} at {}", p, time);
+            }
+        });
+        // and isn't too much bigger than it needs to be, even though the bar swings round
+        assert!(bbox.max().z - bbox.min().z < 4.0);
+        let whole = bar.bounding_box(0.0, 1.0).unwrap();
+        assert!(whole.min().x < -1.9 && whole.max().x > 10.0);
+    }
+
+    #[test]
+    fn keyframes_need_different_finite_times() {
+        let ball = || {
+            Box3D::new(
+                Point3::zero(),
+                Point3::one(),
+                DiffuseLambertian::new(Color::one()),
+            )
+        };
+        assert!(AnimatedTransform::new(ball(), vec![]).is_none());
+        assert!(
+            AnimatedTransform::new(ball(), vec![Keyframe::at(1.0), Keyframe::at(1.0)]).is_none()
+        );
+        assert!(
+            AnimatedTransform::new(ball(), vec![Keyframe::at(0.0), Keyframe::at(f64::NAN)])
+                .is_none()
+        );
+        assert!(AnimatedTransform::new(ball(), vec![Keyframe::at(f64::INFINITY)]).is_none());
+    }
+
+    #[test]
+    fn scale_can_never_reach_zero() {
+        let ball = || {
+            Box3D::new(
+                Point3::zero(),
+                Point3::one(),
+                DiffuseLambertian::new(Color::one()),
+            )
+        };
+        let flat = Keyframe::at(0.0).scale(Vec3::new(1.0, 0.0, 1.0));
+        assert!(AnimatedTransform::new(ball(), vec![flat]).is_none());
+
+        // going from 1 to -1 passes through zero halfway
+        let flipped = Keyframe::at(1.0).scale(Vec3::new(-1.0, 1.0, 1.0));
+        assert!(AnimatedTransform::new(ball(), vec![Keyframe::at(0.0), flipped]).is_none());
+        // but a mirror image that stays mirrored is fine
+        let mirrored = Keyframe::at(0.0).scale(Vec3::new(-2.0, 1.0, 1.0));
+        assert!(AnimatedTransform::new(ball(), vec![mirrored, flipped]).is_some());
+    }
+}
@@ -0,0 +1,249 @@
+//! Arbitrary output variables (AOVs): extra images rendered alongside the beauty image, such as
+//! the albedo and normal of the first surface each pixel sees, for compositing and for guiding a
+//! denoiser.
+
+use crate::{
+    hittable::HitRecord,
+    ray::Ray,
+    vec3::{Color, Point3, Vec3},
+};
+
+/// Number of bounces that get their own emission layer; light emitted after more bounces than
+/// this is added to the last layer, so the layers always add up to the beauty image.
+pub const EMISSION_BOUNCES: usize = 4;
+
+/// An auxiliary image that a render can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Aov {
+    /// Color of the first surface seen, without any lighting
+    Albedo,
+    /// Shading normal of the first surface seen, facing the camera
+    Normal,
+    /// World space position of the first surface seen
+    Position,
+    /// Distance from the camera to the first surface seen, or infinity where there isn't one
+    Depth,
+    /// Number of the object that the first surface belongs to, or 0 for the background. Objects
+    /// are numbered in the order the scene lists them, so the numbers stay the same from one
+    /// render to the next.
+    ObjectId,
+    /// Number of the first surface's material, or 0 for the background. Materials are numbered in
+    /// the order the scene makes them (see [`crate::material::numbered_materials`]).
+    MaterialId,
+    /// Light that reached the camera, in one layer per bounce that it made after being emitted
+    Emission,
+    /// How noisy each pixel is: the estimated variance of its mean luminance
+    Variance,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Emission,
+        Aov::Variance,
+    ];
+
+    /// Name of the AOV, as used on the command line and for its image layers.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+            Aov::Emission => "emission",
+            Aov::Variance => "variance",
+        }
+    }
+}
+
+impl std::str::FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .iter()
+            .copied()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| {
+                let names = Aov::ALL.iter().map(|aov| aov.name()).collect::<Vec<_>>();
+                format!("unknown AOV '{}', expected one of: {}", s, names.join(", "))
+            })
+    }
+}
+
+/// One image layer of an AOV, e.g. the second bounce of the emission AOV.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovLayer {
+    /// Which AOV the layer belongs to
+    pub aov: Aov,
+    /// Name of the layer, like `albedo` or `emission1`
+    pub name: String,
+    /// Names of the layer's channels, like `R`, `G` and `B`
+    pub channels: &'static [&'static str],
+    /// The values are whole numbers identifying something, which mustn't be filtered or rounded
+    pub ids: bool,
+    /// `channels.len()` values per pixel, row by row starting from the top left corner
+    pub values: Vec<f32>,
+}
+
+impl AovLayer {
+    /// A layer like `like` but for `pixel_count` pixels, all zero.
+    pub fn empty_like(like: &AovLayer, pixel_count: usize) -> Self {
+        Self {
+            aov: like.aov,
+            name: like.name.clone(),
+            channels: like.channels,
+            ids: like.ids,
+            values: vec![0.0; pixel_count * like.channels.len()],
+        }
+    }
+
+    /// The value of `channel` for pixel number `index`.
+    pub fn value(&self, index: usize, channel: usize) -> f32 {
+        self.values[index * self.channels.len() + channel]
+    }
+}
+
+const RGB_CHANNELS: &[&str] = &["R", "G", "B"];
+const XYZ_CHANNELS: &[&str] = &["X", "Y", "Z"];
+const DEPTH_CHANNELS: &[&str] = &["Z"];
+const ID_CHANNELS: &[&str] = &["id"];
+const LUMINANCE_CHANNELS: &[&str] = &["Y"];
+
+/// The AOV values of one sample (or the average of a pixel's samples).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub depth: f64,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub emission: [Color; EMISSION_BOUNCES],
+    /// Filled in from the pixel's samples once they're averaged
+    pub variance: f64,
+}
+
+impl AovSample {
+    /// Note that camera ray `r` first hit `first_hit`, or nothing; the emission is kept.
+    pub fn record_first_hit(&mut self, r: Ray, first_hit: Option<&HitRecord>) {
+        let emission = self.emission;
+        *self = match first_hit {
+            Some(rec) => Self {
+                albedo: rec.mat_ptr.albedo(rec),
+                normal: rec.normal,
+                position: rec.p,
+                depth: rec.t * r.direction().length(),
+                object_id: rec.object_id,
+                material_id: rec.mat_ptr.material_id(),
+                emission,
+                variance: 0.0,
+            },
+            None => Self {
+                depth: f64::INFINITY,
+                emission,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Running totals of the AOVs of a pixel's samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct AovSums {
+    sum: AovSample,
+    /// Depth and IDs can't be averaged (an edge between two objects isn't a third object), so
+    /// they come from the first sample
+    first: Option<AovSample>,
+}
+
+impl AovSums {
+    pub fn add(&mut self, sample: &AovSample) {
+        self.sum.albedo += sample.albedo;
+        self.sum.normal += sample.normal;
+        self.sum.position += sample.position;
+        for (sum, &emission) in self.sum.emission.iter_mut().zip(&sample.emission) {
+            *sum += emission;
+        }
+        self.first.get_or_insert(*sample);
+    }
+
+    /// The average of the `count` samples that have been added.
+    pub fn average(&self, count: u32) -> AovSample {
+        let scale = 1.0 / count.max(1) as f64;
+        let first = self.first.unwrap_or_default();
+        AovSample {
+            albedo: self.sum.albedo * scale,
+            normal: self.sum.normal * scale,
+            position: self.sum.position * scale,
+            depth: first.depth,
+            object_id: first.object_id,
+            material_id: first.material_id,
+            emission: self.sum.emission.map(|e| e * scale),
+            variance: 0.0,
+        }
+    }
+}
+
+/// Gather the layers of `aovs` from every pixel's (averaged) `samples`.
+pub(crate) fn layers(aovs: &[Aov], samples: &[AovSample]) -> Vec<AovLayer> {
+    let layer =
+        |aov, name: &str, channels: &'static [&'static str], value: &dyn Fn(&AovSample) -> Vec3| {
+            AovLayer {
+                aov,
+                name: name.to_owned(),
+                channels,
+                ids: false,
+                values: samples
+                    .iter()
+                    .flat_map(|sample| {
+                        let v = value(sample);
+                        [v.x as f32, v.y as f32, v.z as f32]
+                    })
+                    .collect(),
+            }
+        };
+    let scalar_layer = |aov: Aov, channels, ids, value: &dyn Fn(&AovSample) -> f32| AovLayer {
+        aov,
+        name: aov.name().to_owned(),
+        channels,
+        ids,
+        values: samples.iter().map(value).collect(),
+    };
+
+    let mut layers = Vec::new();
+    for &aov in aovs {
+        let name = aov.name();
+        match aov {
+            Aov::Albedo => layers.push(layer(aov, name, RGB_CHANNELS, &|s| s.albedo)),
+            Aov::Normal => layers.push(layer(aov, name, XYZ_CHANNELS, &|s| s.normal)),
+            Aov::Position => layers.push(layer(aov, name, XYZ_CHANNELS, &|s| s.position)),
+            Aov::Depth => layers.push(scalar_layer(aov, DEPTH_CHANNELS, false, &|s| {
+                s.depth as f32
+            })),
+            Aov::ObjectId => layers.push(scalar_layer(aov, ID_CHANNELS, true, &|s| {
+                s.object_id as f32
+            })),
+            Aov::MaterialId => layers.push(scalar_layer(aov, ID_CHANNELS, true, &|s| {
+                s.material_id as f32
+            })),
+            Aov::Variance => layers.push(scalar_layer(aov, LUMINANCE_CHANNELS, false, &|s| {
+                s.variance as f32
+            })),
+            Aov::Emission => {
+                for bounce in 0..EMISSION_BOUNCES {
+                    let name = format!("{}{}", name, bounce);
+                    layers.push(layer(aov, &name, RGB_CHANNELS, &|s| s.emission[bounce]));
+                }
+            }
+        }
+    }
+    layers
+}
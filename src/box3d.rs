@@ -8,19 +8,18 @@ use crate::{
 };
 
 #[derive(Debug)]
-pub(crate) struct Box3D {
+pub struct Box3D {
     min: Point3,
     max: Point3,
     sides: HittableList,
-    material: Arc<dyn Material>,
 }
 
 impl Box3D {
-    pub(crate) fn new<M>(min: Point3, max: Point3, material: M) -> Self
+    pub fn new<M>(min: Point3, max: Point3, material: M) -> Self
     where
         M: 'static + Material,
     {
-        let mat = std::sync::Arc::<dyn Material>::from(Box::new(material) as Box<dyn Material>);
+        let mat = Arc::<dyn Material>::from(Box::new(material) as Box<dyn Material>);
 
         Self {
             min,
@@ -81,7 +80,6 @@ impl Box3D {
 
                 sides
             },
-            material: mat,
         }
     }
 }
@@ -92,11 +90,27 @@ impl Hittable for Box3D {
         r: crate::ray::Ray,
         t_min: f64,
         t_max: f64,
-    ) -> Option<crate::hittable::HitRecord> {
-        self.sides.hit(r, t_min, t_max)
+    ) -> Option<crate::hittable::HitRecord<'_>> {
+        let mut rec = self.sides.hit(r, t_min, t_max)?;
+        // the sides all face along their axis, so the ones at the minimum face into the box
+        let axis = (0..3)
+            .max_by(|&a, &b| rec.normal[a].abs().total_cmp(&rec.normal[b].abs()))
+            .unwrap();
+        if (rec.p[axis] - self.min[axis]).abs() < (rec.p[axis] - self.max[axis]).abs() {
+            rec.front_face = !rec.front_face;
+        }
+        Some(rec)
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<crate::aabb::Aabb> {
         Some(crate::aabb::Aabb::new(self.min, self.max))
     }
+
+    fn pdf_value(&self, r: crate::ray::Ray) -> f64 {
+        self.sides.pdf_value(r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> crate::vec3::Vec3 {
+        self.sides.random_direction(origin, time)
+    }
 }
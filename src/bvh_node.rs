@@ -1,121 +1,646 @@
-use std::cmp::Ordering;
-
 use crate::{
     aabb::Aabb,
     hittable::{HitRecord, Hittable},
     ray::Ray,
-    util::random_int,
+    vec3::{Point3, Vec3},
 };
 
+/// Number of buckets that object centroids are sorted into when looking for the best split.
+const SAH_BUCKETS: usize = 16;
+/// Cost of visiting a node, relative to the cost of intersecting one object. Even a sphere or
+/// rect costs several times as much as a box test once its hit record is filled in.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Leaves with more objects than this get split even if the heuristic says it's not worth it.
+const MAX_LEAF_OBJECTS: usize = 4;
+/// Nodes deeper than this are split evenly instead of using the heuristic, which limits how deep
+/// the traversal stack of a [`BvhNode`] needs to be.
+const MAX_SAH_DEPTH: usize = 32;
+/// Enough to traverse any tree of up to `2^32` objects.
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
+/// A bounding volume hierarchy, built using the surface area heuristic (SAH) and stored as a flat
+/// array of nodes so that traversing it doesn't need to chase pointers or make virtual calls.
 #[derive(Debug)]
-pub(crate) struct BvhNode {
-    left: Box<dyn Hittable>,
-    right_maybe: Option<Box<dyn Hittable>>,
+pub struct BvhNode {
+    /// Nodes in depth first order, so the first child of an interior node comes right after it
+    nodes: Vec<LinearNode>,
+    /// The exact bounds of the root node, which the node itself only has rounded to `f32`
     abox: Aabb,
+    /// Objects ordered so that each leaf refers to a contiguous range of them
+    objects: Vec<Box<dyn Hittable>>,
+    /// The object ID of each of `objects`: where it was in the list the hierarchy was built from,
+    /// counting from 1, which stays the same however the hierarchy is arranged
+    object_ids: Vec<u32>,
 }
 
-impl BvhNode {
-    pub(crate) fn new(mut objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> BvhNode {
-        let axis = random_int(0, 2);
-        let comparator = match axis {
-            0 => Self::box_compare_x,
-            1 => Self::box_compare_y,
-            _ => Self::box_compare_z,
-        };
+/// One node of a [`BvhNode`], packed into 32 bytes so that two of them fit in a cache line. The
+/// bounds are rounded outwards to `f32`, so they can only grow.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(32))]
+struct LinearNode {
+    min: [f32; 3],
+    /// The first object of a leaf, or the second child of an interior node; the first child
+    /// (lower coordinates along `axis`) immediately follows the node
+    offset: u32,
+    max: [f32; 3],
+    /// Number of objects in a leaf, or 0 for an interior node
+    object_count: u16,
+    axis: u8,
+}
 
-        let left;
-        let right;
-        assert!(!objects.is_empty());
-        if objects.len() == 1 {
-            left = objects.pop().unwrap();
-            right = None;
-        } else if objects.len() == 2 {
-            let second = objects.pop().unwrap();
-            let first = objects.pop().unwrap();
-
-            if comparator(first.as_ref(), second.as_ref()) == Ordering::Less {
-                left = first;
-                right = Some(second);
+impl LinearNode {
+    fn new(abox: Aabb, offset: u32, object_count: u16, axis: u8) -> Self {
+        let round_down = |x: f64| {
+            let y = x as f32;
+            if y as f64 > x {
+                y.next_down()
             } else {
-                left = second;
-                right = Some(first);
+                y
             }
-        } else {
-            objects.sort_by(|a, b| comparator(a.as_ref(), b.as_ref()));
+        };
+        let round_up = |x: f64| {
+            let y = x as f32;
+            if (y as f64) < x {
+                y.next_up()
+            } else {
+                y
+            }
+        };
+        Self {
+            min: [0, 1, 2].map(|a| round_down(abox.min()[a])),
+            offset,
+            max: [0, 1, 2].map(|a| round_up(abox.max()[a])),
+            object_count,
+            axis,
+        }
+    }
 
-            let mid = objects.len() / 2;
-            let half2 = objects.split_off(mid);
+    fn abox(&self) -> Aabb {
+        let point = |p: [f32; 3]| Point3::new(p[0] as f64, p[1] as f64, p[2] as f64);
+        Aabb::new(point(self.min), point(self.max))
+    }
 
-            left = Box::new(BvhNode::new(objects, time0, time1));
-            right = Some(Box::new(BvhNode::new(half2, time0, time1)));
+    /// The slab test of [`Aabb::hit`], for a ray with the given origin and inverse direction.
+    #[inline]
+    fn hit(&self, origin: Point3, inv_direction: Vec3, mut t_min: f64, mut t_max: f64) -> bool {
+        for a in 0..3 {
+            let t0 = (self.min[a] as f64 - origin[a]) * inv_direction[a];
+            let t1 = (self.max[a] as f64 - origin[a]) * inv_direction[a];
+            let (t0, t1) = if inv_direction[a] < 0.0 {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+            // plain comparisons rather than `f64::max` and `min`, which are slower because of how
+            // they treat NaN; a NaN (from a ray in the plane of a face) is ignored either way
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
         }
+        t_min < t_max
+    }
+}
 
-        let abox = if let Some(ref right) = right {
-            Aabb::surrounding_box(
-                left.bounding_box(time0, time1).unwrap(),
-                right.bounding_box(time0, time1).unwrap(),
-            )
-        } else {
-            left.bounding_box(time0, time1).unwrap()
+impl BvhNode {
+    /// Panics if `objects` is empty or any of them don't have a bounding box.
+    pub fn new(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> BvhNode {
+        BvhTree::new(objects, time0, time1).into()
+    }
+
+    fn flatten(&mut self, tree: BvhTree) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode::new(tree.abox, 0, 0, 0));
+
+        self.nodes[index] = match tree.contents {
+            BvhContents::Leaf(objects) => {
+                let first_object = self.objects.len() as u32;
+                let object_count =
+                    u16::try_from(objects.len()).expect("leaves hold only a few objects");
+                for o in objects {
+                    self.objects.push(o.object);
+                    self.object_ids.push(o.id);
+                }
+                LinearNode::new(tree.abox, first_object, object_count, 0)
+            }
+            BvhContents::Split { left, right, axis } => {
+                self.flatten(*left);
+                let second_child = self.flatten(*right) as u32;
+                LinearNode::new(tree.abox, second_child, 0, axis as u8)
+            }
         };
+        index
+    }
 
-        BvhNode {
-            left,
-            right_maybe: right,
-            abox,
+    /// Describe the shape of the hierarchy, e.g. to check how good a job the builder did.
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            node_count: self.nodes.len(),
+            object_count: self.objects.len(),
+            ..Default::default()
+        };
+        let root_area = self.nodes[0].abox().surface_area();
+
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let relative_area = if root_area > 0.0 {
+                node.abox().surface_area() / root_area
+            } else {
+                1.0
+            };
+            stats.depth = stats.depth.max(depth);
+
+            if node.object_count > 0 {
+                stats.leaf_count += 1;
+                stats.sah_cost += relative_area * node.object_count as f64;
+            } else {
+                stats.sah_cost += relative_area * TRAVERSAL_COST;
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset as usize, depth + 1));
+            }
         }
+        stats
+    }
+}
+
+impl From<BvhTree> for BvhNode {
+    fn from(tree: BvhTree) -> Self {
+        let mut bvh = BvhNode {
+            nodes: Vec::new(),
+            abox: tree.abox,
+            objects: Vec::new(),
+            object_ids: Vec::new(),
+        };
+        bvh.flatten(tree);
+        bvh
     }
+}
+
+impl BvhNode {
+    /// The closest of the hits that `hit` finds on the objects `r` might reach, which it's given
+    /// along with the furthest a hit can be.
+    fn closest_hit<'a>(
+        &'a self,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+        hit: impl Fn(&'a dyn Hittable, f64) -> Option<HitRecord<'a>>,
+    ) -> Option<HitRecord<'a>> {
+        let origin = r.origin();
+        let inv_direction = inverse(r.direction());
+        let mut closest_so_far = t_max;
+        let mut hit_record = None;
+
+        let mut to_visit = [0u32; TRAVERSAL_STACK_SIZE];
+        let mut to_visit_len = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.hit(origin, inv_direction, t_min, closest_so_far) {
+                if node.object_count > 0 {
+                    let first = node.offset as usize;
+                    let last = first + node.object_count as usize;
+                    for (object, &id) in self.objects[first..last]
+                        .iter()
+                        .zip(&self.object_ids[first..last])
+                    {
+                        if let Some(mut rec) = hit(object.as_ref(), closest_so_far) {
+                            closest_so_far = rec.t;
+                            // the outermost hierarchy numbers last, so its numbering wins
+                            rec.object_id = id;
+                            hit_record = Some(rec);
+                        }
+                    }
+                } else {
+                    // visit the nearer child first, so that hits there can cull the farther one
+                    let (first_child, second_child) = (current as u32 + 1, node.offset);
+                    let (near, far) = if inv_direction[node.axis as usize] < 0.0 {
+                        (second_child, first_child)
+                    } else {
+                        (first_child, second_child)
+                    };
+                    to_visit[to_visit_len] = far;
+                    to_visit_len += 1;
+                    current = near as usize;
+                    continue;
+                }
+            }
 
-    fn box_compare_x(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-        Self::box_compare(a, b, 0)
+            if to_visit_len == 0 {
+                break;
+            }
+            to_visit_len -= 1;
+            current = to_visit[to_visit_len] as usize;
+        }
+
+        hit_record
     }
-    fn box_compare_y(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-        Self::box_compare(a, b, 1)
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max, |object, t_max| object.hit(r, t_min, t_max))
     }
-    fn box_compare_z(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-        Self::box_compare(a, b, 2)
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.abox)
     }
 
-    fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
-        let box_a = a
-            .bounding_box(0.0, 0.0)
-            .expect("A must have a bounding box");
-        let box_b = b
-            .bounding_box(0.0, 0.0)
-            .expect("B must have a bounding box");
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max, |object, t_max| {
+            object.hit_surface(r, t_min, t_max)
+        })
+    }
 
-        box_a.min()[axis].partial_cmp(&box_b.min()[axis]).unwrap()
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let origin = r.origin();
+        let inv_direction = inverse(r.direction());
+        let mut transmittance = 1.0;
+        let mut to_visit = [0u32; TRAVERSAL_STACK_SIZE];
+        let mut to_visit_len = 1;
+        while to_visit_len > 0 {
+            to_visit_len -= 1;
+            let current = to_visit[to_visit_len] as usize;
+            let node = &self.nodes[current];
+            if !node.hit(origin, inv_direction, t_min, t_max) {
+                continue;
+            }
+            if node.object_count > 0 {
+                let first = node.offset as usize;
+                for object in &self.objects[first..first + node.object_count as usize] {
+                    transmittance *= object.transmittance(r, t_min, t_max);
+                }
+            } else {
+                to_visit[to_visit_len] = current as u32 + 1;
+                to_visit[to_visit_len + 1] = node.offset;
+                to_visit_len += 2;
+            }
+        }
+        transmittance
     }
 }
 
-impl Hittable for BvhNode {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.abox.hit(r, t_min, t_max) {
-            return None;
+/// The hierarchy as a tree of boxed nodes, which is how it's built before being flattened into a
+/// [`BvhNode`]. It's a [`Hittable`] too, mostly so that the two layouts can be benchmarked.
+#[derive(Debug)]
+pub struct BvhTree {
+    abox: Aabb,
+    contents: BvhContents,
+}
+
+#[derive(Debug)]
+enum BvhContents {
+    Leaf(Vec<BuildObject>),
+    /// The left node holds the objects with lower coordinates along `axis`
+    Split {
+        left: Box<BvhTree>,
+        right: Box<BvhTree>,
+        axis: usize,
+    },
+}
+
+/// An object waiting to be placed in the hierarchy, along with its cached bounds.
+#[derive(Debug)]
+struct BuildObject {
+    object: Box<dyn Hittable>,
+    /// Where the object was in the list the hierarchy is being built from, counting from 1
+    id: u32,
+    abox: Aabb,
+    centroid: Point3,
+}
+
+/// Where to divide a set of objects: everything in a bucket below `bucket` goes left.
+#[derive(Debug, Clone, Copy)]
+struct SahSplit {
+    axis: usize,
+    bucket: usize,
+    cost: f64,
+}
+
+impl BvhTree {
+    /// Panics if `objects` is empty or any of them don't have a bounding box.
+    pub fn new(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> BvhTree {
+        assert!(!objects.is_empty());
+        let objects = objects
+            .into_iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let abox = object
+                    .bounding_box(time0, time1)
+                    .expect("objects in a BVH must have a bounding box");
+                BuildObject {
+                    object,
+                    id: index as u32 + 1,
+                    abox,
+                    centroid: abox.centroid(),
+                }
+            })
+            .collect();
+        Self::build(objects, 1)
+    }
+
+    fn build(mut objects: Vec<BuildObject>, depth: usize) -> BvhTree {
+        let abox = objects
+            .iter()
+            .map(|o| o.abox)
+            .reduce(Aabb::surrounding_box)
+            .unwrap();
+        let centroid_bounds = objects
+            .iter()
+            .map(|o| Aabb::new(o.centroid, o.centroid))
+            .reduce(Aabb::surrounding_box)
+            .unwrap();
+
+        let leaf_cost = objects.len() as f64;
+        let must_split = objects.len() > MAX_LEAF_OBJECTS;
+        let split = if depth < MAX_SAH_DEPTH {
+            Self::find_split(&objects, abox, centroid_bounds)
+        } else {
+            None
+        };
+        let (left, right, axis): (Vec<_>, Vec<_>, _) = match split {
+            Some(split) if must_split || split.cost < leaf_cost => {
+                let (left, right) = objects.into_iter().partition(|o| {
+                    Self::bucket(o.centroid, centroid_bounds, split.axis) < split.bucket
+                });
+                (left, right, split.axis)
+            }
+            // either every centroid is in the same spot, or the heuristic has made a very lopsided
+            // tree; either way, split evenly along the widest axis to bound the depth
+            None if must_split => {
+                let extent = centroid_bounds.max() - centroid_bounds.min();
+                let axis = (0..3)
+                    .max_by(|&a, &b| extent[a].partial_cmp(&extent[b]).unwrap())
+                    .unwrap();
+                objects.sort_by(|a, b| a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap());
+                let right = objects.split_off(objects.len() / 2);
+                (objects, right, axis)
+            }
+            _ => {
+                return BvhTree {
+                    abox,
+                    contents: BvhContents::Leaf(objects),
+                };
+            }
+        };
+
+        BvhTree {
+            abox,
+            contents: BvhContents::Split {
+                left: Box::new(Self::build(left, depth + 1)),
+                right: Box::new(Self::build(right, depth + 1)),
+                axis,
+            },
         }
+    }
 
-        let hit_left = self.left.hit(r, t_min, t_max);
+    /// Find the cheapest split across all axes, if the centroids can be split at all.
+    ///
+    /// Axes are tried in order and only a strictly cheaper split replaces an earlier one, so the
+    /// same objects always produce the same tree.
+    fn find_split(objects: &[BuildObject], abox: Aabb, centroid_bounds: Aabb) -> Option<SahSplit> {
+        let parent_area = abox.surface_area();
+        let mut best: Option<SahSplit> = None;
 
-        let hit_right = self.right_maybe.as_ref().and_then(|right| {
-            let hit_left_time = hit_left.as_ref().map(|h| h.t).unwrap_or(t_max);
+        for axis in 0..3 {
+            if centroid_bounds.max()[axis] <= centroid_bounds.min()[axis] {
+                continue;
+            }
 
-            right.hit(r, t_min, hit_left_time)
-        });
+            let mut counts = [0usize; SAH_BUCKETS];
+            let mut boxes: [Option<Aabb>; SAH_BUCKETS] = [None; SAH_BUCKETS];
+            for o in objects {
+                let b = Self::bucket(o.centroid, centroid_bounds, axis);
+                counts[b] += 1;
+                boxes[b] = merge(boxes[b], Some(o.abox));
+            }
 
-        match (hit_left, hit_right) {
-            (None, right) => right,
-            (left, None) => left,
-            (Some(left), Some(right)) => {
-                if left.t < right.t {
-                    Some(left)
-                } else {
-                    Some(right)
+            // sweep from the right so that the cost of each split is linear in the bucket count
+            let mut right_areas = [0.0; SAH_BUCKETS];
+            let mut right_box: Option<Aabb> = None;
+            for b in (1..SAH_BUCKETS).rev() {
+                right_box = merge(right_box, boxes[b]);
+                right_areas[b] = right_box.map_or(0.0, |bb| bb.surface_area());
+            }
+
+            let mut left_box: Option<Aabb> = None;
+            let mut left_count = 0;
+            for bucket in 1..SAH_BUCKETS {
+                left_box = merge(left_box, boxes[bucket - 1]);
+                left_count += counts[bucket - 1];
+                let right_count = objects.len() - left_count;
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let left_area = left_box.map_or(0.0, |bb| bb.surface_area());
+                let cost = TRAVERSAL_COST
+                    + if parent_area > 0.0 {
+                        (left_area * left_count as f64 + right_areas[bucket] * right_count as f64)
+                            / parent_area
+                    } else {
+                        objects.len() as f64
+                    };
+                if best.is_none_or(|b| cost < b.cost) {
+                    best = Some(SahSplit { axis, bucket, cost });
                 }
             }
         }
+
+        best
+    }
+
+    fn bucket(centroid: Point3, centroid_bounds: Aabb, axis: usize) -> usize {
+        let min = centroid_bounds.min()[axis];
+        let extent = centroid_bounds.max()[axis] - min;
+        let b = ((centroid[axis] - min) / extent * SAH_BUCKETS as f64) as usize;
+        b.min(SAH_BUCKETS - 1)
+    }
+}
+
+impl BvhTree {
+    /// The closest of the hits that `hit` finds on the objects `r` might reach, which it's given
+    /// along with the furthest a hit can be.
+    fn closest_hit<'a>(
+        &'a self,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+        hit: &impl Fn(&'a dyn Hittable, f64) -> Option<HitRecord<'a>>,
+    ) -> Option<HitRecord<'a>> {
+        if !self.abox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        match &self.contents {
+            BvhContents::Leaf(objects) => {
+                let mut closest_so_far = t_max;
+                let mut hit_record = None;
+                for o in objects {
+                    if let Some(rec) = hit(o.object.as_ref(), closest_so_far) {
+                        closest_so_far = rec.t;
+                        hit_record = Some(rec);
+                    }
+                }
+                hit_record
+            }
+            BvhContents::Split { left, right, axis } => {
+                // visit the nearer child first, so that hits there can cull the farther one
+                let (near, far) = if r.direction()[*axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                let hit_near = near.closest_hit(r, t_min, t_max, hit);
+                let hit_near_time = hit_near.as_ref().map(|h| h.t).unwrap_or(t_max);
+                far.closest_hit(r, t_min, hit_near_time, hit).or(hit_near)
+            }
+        }
+    }
+}
+
+impl Hittable for BvhTree {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max, &|object, t_max| {
+            object.hit(r, t_min, t_max)
+        })
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(self.abox)
     }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max, &|object, t_max| {
+            object.hit_surface(r, t_min, t_max)
+        })
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        if !self.abox.hit(r, t_min, t_max) {
+            return 1.0;
+        }
+        match &self.contents {
+            BvhContents::Leaf(objects) => objects
+                .iter()
+                .map(|o| o.object.transmittance(r, t_min, t_max))
+                .product(),
+            BvhContents::Split { left, right, .. } => {
+                left.transmittance(r, t_min, t_max) * right.transmittance(r, t_min, t_max)
+            }
+        }
+    }
+}
+
+fn inverse(v: Vec3) -> Vec3 {
+    Vec3::new(1.0 / v.x, 1.0 / v.y, 1.0 / v.z)
+}
+
+fn merge(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding_box(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Statistics about a built [`BvhNode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BvhStats {
+    /// Number of nodes on the longest path from the root to a leaf
+    pub depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub object_count: usize,
+    /// Expected cost of tracing a ray through the tree, in units of object intersections
+    pub sah_cost: f64,
+}
+
+impl std::fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} objects in {} nodes ({} leaves), depth {}, SAH cost {:.2}",
+            self.object_count, self.node_count, self.leaf_count, self.depth, self.sah_cost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::DiffuseLambertian,
+        sphere::Sphere,
+        vec3::{Color, Vec3},
+    };
+
+    fn row_of_spheres(count: usize) -> Vec<Box<dyn Hittable>> {
+        (0..count)
+            .map(|i| {
+                Box::new(Sphere::stationary(
+                    Point3::new(i as f64 * 3.0, 0.0, 0.0),
+                    1.0,
+                    Box::new(DiffuseLambertian::new(Color::one())),
+                )) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builds_are_deterministic() {
+        let a = BvhNode::new(row_of_spheres(100), 0.0, 0.0).stats();
+        let b = BvhNode::new(row_of_spheres(100), 0.0, 0.0).stats();
+        assert_eq!(a, b);
+        assert_eq!(a.object_count, 100);
+        assert!(a.depth < 20, "tree is too deep: {}", a);
+    }
+
+    #[test]
+    fn hit_finds_closest_object() {
+        let bvh = BvhNode::new(row_of_spheres(50), 0.0, 0.0);
+        let r = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None);
+
+        let rec = bvh.hit(r, 0.001, f64::INFINITY).expect("ray should hit");
+        assert!((rec.p.x - -1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flat_and_tree_layouts_agree() {
+        let tree = BvhTree::new(row_of_spheres(30), 0.0, 0.0);
+        let flat = BvhNode::new(row_of_spheres(30), 0.0, 0.0);
+
+        for i in 0..100 {
+            // offset in z so that no ray just grazes a sphere, which the flat layout's slightly
+            // bigger boxes would let through where the tree's exact ones don't
+            let origin = Point3::new(i as f64 * 0.9 - 5.0, 5.0, (i % 7) as f64 - 2.95);
+            let r = Ray::new(origin, Vec3::new((i % 3) as f64 - 1.0, -5.0, 3.0), None);
+            let expected = tree.hit(r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(flat.hit(r, 0.001, f64::INFINITY).map(|rec| rec.t), expected);
+        }
+    }
+
+    #[test]
+    fn nodes_are_half_a_cache_line() {
+        assert_eq!(std::mem::size_of::<LinearNode>(), 32);
+    }
+
+    #[test]
+    fn coincident_objects_still_split() {
+        let objects = (0..10)
+            .map(|_| {
+                Box::new(Sphere::stationary(
+                    Point3::zero(),
+                    1.0,
+                    Box::new(DiffuseLambertian::new(Color::one())),
+                )) as Box<dyn Hittable>
+            })
+            .collect();
+        let stats = BvhNode::new(objects, 0.0, 0.0).stats();
+        assert_eq!(stats.object_count, 10);
+        assert!(stats.leaf_count > 1);
+    }
 }
@@ -4,8 +4,8 @@ use crate::{
     vec3::{Point3, Vec3},
 };
 
-#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
-pub(crate) struct CameraSettings {
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraSettings {
     pub look_from: Point3,
     pub look_at: Point3,
     pub vup: Vec3,
@@ -18,23 +18,23 @@ pub(crate) struct CameraSettings {
 
 #[allow(dead_code)]
 impl CameraSettings {
-    pub(crate) fn look_from(self, look_from: Point3) -> Self {
+    pub fn look_from(self, look_from: Point3) -> Self {
         Self { look_from, ..self }
     }
 
-    pub(crate) fn look_at(self, look_at: Point3) -> Self {
+    pub fn look_at(self, look_at: Point3) -> Self {
         Self { look_at, ..self }
     }
 
-    pub(crate) fn vup(self, vup: Vec3) -> Self {
+    pub fn vup(self, vup: Vec3) -> Self {
         Self { vup, ..self }
     }
 
-    pub(crate) fn vfov(self, vfov: f64) -> Self {
+    pub fn vfov(self, vfov: f64) -> Self {
         Self { vfov, ..self }
     }
 
-    pub(crate) fn focusing_on(self, focus_dist: f64, aperture: f64) -> Self {
+    pub fn focusing_on(self, focus_dist: f64, aperture: f64) -> Self {
         Self {
             focus_dist,
             aperture,
@@ -42,7 +42,7 @@ impl CameraSettings {
         }
     }
 
-    pub(crate) fn time_range(self, time0: f64, time1: f64) -> Self {
+    pub fn time_range(self, time0: f64, time1: f64) -> Self {
         Self {
             time0,
             time1,
@@ -67,7 +67,7 @@ impl Default for CameraSettings {
 }
 
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct Camera {
+pub struct Camera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
     pub horizontal: Vec3,
@@ -84,13 +84,6 @@ pub(crate) struct Camera {
 
 impl Camera {
     pub fn new(settings: CameraSettings, aspect_ratio: f64) -> Camera {
-        println!(
-            "Looking from {from} to {at}, with up = {vup}",
-            from = settings.look_from,
-            at = settings.look_at,
-            vup = settings.vup
-        );
-
         let theta = degrees_to_radians(settings.vfov);
         let h = (theta / 2.0).tan();
         let viewport_height: f64 = 2.0 * h;
@@ -99,18 +92,6 @@ impl Camera {
         let w = (settings.look_from - settings.look_at).to_unit();
         let u = settings.vup.cross(w).to_unit();
         let v = w.cross(u);
-        println!(
-            "Camera viewport is {height}x{width} with FOV of {fov}",
-            height = viewport_height,
-            width = viewport_width,
-            fov = settings.vfov,
-        );
-        println!(
-            "FOV is {fov}, aperture is {aperture}, focus distance is {focus_dist}",
-            fov = settings.vfov,
-            aperture = settings.aperture,
-            focus_dist = settings.focus_dist,
-        );
 
         let origin: Point3 = settings.look_from;
         let horizontal = settings.focus_dist * viewport_width * u;
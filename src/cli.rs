@@ -0,0 +1,498 @@
+use structopt::StructOpt;
+
+use raytracer::{
+    aov::Aov,
+    camera::CameraSettings,
+    denoise::{Denoising, MAX_ITERATIONS},
+    image_file::ExrPrecision,
+    sampler::SamplerKind,
+    scene_file::SceneFileError,
+    scenes::RenderScene,
+    sky::{SkySettings, SunPosition},
+    tone_map::{ToneMapOperator, ToneMapping},
+    vec3::{Color, Point3, Vec3},
+    AdaptiveSampling, RayColorMode, RenderConfig,
+};
+
+use crate::{
+    ui::{TerminalSettings, UiData},
+    RenderCommand, RenderResult,
+};
+
+/// Exit code used when the scene could not be loaded or the render thread went away before the
+/// image was completed.
+pub(crate) const EXIT_RENDER_FAILED: i32 = 1;
+/// Exit code used when the image was rendered but could not be written to disk.
+const EXIT_SAVE_FAILED: i32 = 2;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "raytracer",
+    about = "The All Seeing Crab: a raytracer with a GUI and a headless mode"
+)]
+pub(crate) struct CliOptions {
+    /// Render a single image without starting the GUI, then exit. Without this, any other options
+    /// given replace the settings the GUI starts with
+    #[structopt(long)]
+    pub headless: bool,
+
+    /// Scene to render, e.g. three-body, many-balls, cornel-box, final-scene
+    #[structopt(long, default_value = "three-body")]
+    scene: RenderScene,
+
+    /// Scene description file to render instead of a built in scene (see the scenes/ directory)
+    #[structopt(long)]
+    scene_file: Option<String>,
+
+    /// Width of the output image in pixels
+    #[structopt(long, default_value = "400")]
+    width: usize,
+
+    /// Height of the output image in pixels
+    #[structopt(long, default_value = "225")]
+    height: usize,
+
+    /// Number of samples to take per pixel
+    #[structopt(long, default_value = "100")]
+    samples: u32,
+
+    /// Seed for the random numbers used to build the scene and sample pixels; the same seed gives
+    /// the same image
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// How to spread out each pixel's samples; the low discrepancy samplers (stratified, halton and
+    /// sobol) converge faster than independent random samples
+    #[structopt(
+        long,
+        default_value = "independent",
+        possible_values = &["independent", "stratified", "halton", "sobol"]
+    )]
+    sampler: SamplerKind,
+
+    /// Render the whole image one sample per pixel at a time instead of line by line
+    #[structopt(long)]
+    progressive: bool,
+
+    /// Take more samples in noisy pixels, stopping once they are below the noise threshold
+    #[structopt(long)]
+    adaptive: bool,
+
+    /// Noise level (relative to a pixel's brightness) at which adaptive sampling stops [default: 0.05]
+    #[structopt(long)]
+    noise_threshold: Option<f64>,
+
+    /// Samples each pixel gets before adaptive sampling measures its noise [default: 16]
+    #[structopt(long)]
+    min_samples: Option<u32>,
+
+    /// Most samples any pixel gets with adaptive sampling [default: 400]
+    #[structopt(long)]
+    max_samples: Option<u32>,
+
+    /// Save an image of how many samples each pixel took with adaptive sampling instead
+    #[structopt(long)]
+    show_sample_counts: bool,
+
+    /// Filter the noise out of the finished image, guided by the albedo, normal, depth and
+    /// variance AOVs
+    #[structopt(long)]
+    denoise: bool,
+
+    /// Number of denoising filter passes from 1 to 10, each blurring twice as far as the last
+    /// [default: 3]
+    #[structopt(long, parse(try_from_str = parse_denoise_iterations))]
+    denoise_iterations: Option<u32>,
+
+    /// Light the scene with a physically based sun and sky instead of its own background
+    #[structopt(long)]
+    sky: bool,
+
+    /// How hazy the sky is, from 1.7 (very clear) to 10 (hazy) [default: 3]
+    #[structopt(long)]
+    turbidity: Option<f64>,
+
+    /// Degrees that the sun is above the horizon [default: 45]
+    #[structopt(long, allow_hyphen_values = true)]
+    sun_elevation: Option<f64>,
+
+    /// Compass direction of the sun in degrees clockwise from north, where north is -z and east
+    /// is +x [default: 135]
+    #[structopt(long)]
+    sun_azimuth: Option<f64>,
+
+    /// How to color rays
+    #[structopt(
+        long,
+        default_value = "material",
+        possible_values = &["material", "importance", "spectral", "normals", "depth", "block-color"]
+    )]
+    mode: RenderMode,
+
+    /// Maximum number of bounces per ray in material, importance and spectral modes
+    #[structopt(long, default_value = "50")]
+    depth: i32,
+
+    /// Distance at which depth mode fades to black
+    #[structopt(long, default_value = "1.0")]
+    max_t: f64,
+
+    /// Color to use in block-color mode, as r,g,b
+    #[structopt(long, default_value = "255,0,0", parse(try_from_str = parse_vec3))]
+    block_color: Color,
+
+    /// Camera position as x,y,z (defaults to the scene's camera)
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    look_from: Option<Point3>,
+
+    /// Point the camera looks at as x,y,z (defaults to the scene's camera)
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    look_at: Option<Point3>,
+
+    /// Camera up direction as x,y,z (defaults to the scene's camera)
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[structopt(long)]
+    vfov: Option<f64>,
+
+    /// Distance to the plane in perfect focus
+    #[structopt(long)]
+    focus_dist: Option<f64>,
+
+    /// Size of the camera's aperture
+    #[structopt(long)]
+    aperture: Option<f64>,
+
+    /// Shutter open time
+    #[structopt(long)]
+    time0: Option<f64>,
+
+    /// Shutter close time
+    #[structopt(long)]
+    time1: Option<f64>,
+
+    /// How to map the rendered brightness range to the displayable range
+    #[structopt(
+        long,
+        default_value = "clamp",
+        possible_values = &["clamp", "reinhard", "filmic", "aces"]
+    )]
+    tone_map: ToneMapOperator,
+
+    /// Brightness adjustment in stops (each +1 doubles the brightness) applied before tone mapping
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    exposure: f64,
+
+    /// Where to save the rendered image; .png files are tone mapped, while .exr, .pfm and .hdr
+    /// files keep the linear high dynamic range data
+    #[structopt(long, short, default_value = "target/output.png")]
+    output: String,
+
+    /// Store .exr output as 16 bit half floats rather than 32 bit floats
+    #[structopt(long)]
+    exr_half: bool,
+
+    /// Auxiliary image to render along with the image, which can be given more than once. They're
+    /// saved as extra layers of .exr output, or as .pfm files next to the output otherwise
+    #[structopt(
+        long = "aov",
+        number_of_values = 1,
+        possible_values = &["albedo", "normal", "position", "depth", "object-id", "material-id", "emission", "variance"]
+    )]
+    aovs: Vec<Aov>,
+
+    /// Don't show render progress in the terminal
+    #[structopt(long)]
+    no_progress: bool,
+}
+
+/// The ways `--mode` can color rays, which other options fill in to make a [`RayColorMode`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RenderMode {
+    Material,
+    Importance,
+    Spectral,
+    Normals,
+    Depth,
+    BlockColor,
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "material" => Ok(RenderMode::Material),
+            "importance" => Ok(RenderMode::Importance),
+            "spectral" => Ok(RenderMode::Spectral),
+            "normals" => Ok(RenderMode::Normals),
+            "depth" => Ok(RenderMode::Depth),
+            "block-color" => Ok(RenderMode::BlockColor),
+            _ => Err(format!(
+                "unknown render mode '{}', expected one of: material, importance, spectral, normals, depth, block-color",
+                s
+            )),
+        }
+    }
+}
+
+impl CliOptions {
+    fn render_config(&self) -> RenderConfig {
+        RenderConfig {
+            image_width: self.width,
+            image_height: self.height,
+            samples_per_pixel: self.samples,
+            render_mode: self.render_mode(),
+            scene: self.scene(),
+            output_filename: self.output.clone(),
+            progressive: self.progressive,
+            adaptive_sampling: self.adaptive_sampling(),
+            seed: self.seed,
+            sampler: self.sampler,
+            tone_mapping: ToneMapping {
+                operator: self.tone_map,
+                exposure: self.exposure,
+            },
+            exr_precision: if self.exr_half {
+                ExrPrecision::Half
+            } else {
+                ExrPrecision::Float
+            },
+            aovs: self.aovs.clone(),
+            denoising: self.denoising(),
+            sky: self.sky(),
+            ..Default::default()
+        }
+    }
+
+    fn adaptive_sampling(&self) -> Option<AdaptiveSampling> {
+        if !self.adaptive {
+            return None;
+        }
+        let defaults = AdaptiveSampling::default();
+        Some(AdaptiveSampling {
+            noise_threshold: self.noise_threshold.unwrap_or(defaults.noise_threshold),
+            min_samples: self.min_samples.unwrap_or(defaults.min_samples),
+            max_samples: self.max_samples.unwrap_or(defaults.max_samples),
+            show_sample_counts: self.show_sample_counts,
+        })
+    }
+
+    fn denoising(&self) -> Option<Denoising> {
+        if !self.denoise {
+            return None;
+        }
+        let defaults = Denoising::default();
+        Some(Denoising {
+            iterations: self.denoise_iterations.unwrap_or(defaults.iterations),
+            ..defaults
+        })
+    }
+
+    fn sky(&self) -> Option<SkySettings> {
+        if !self.sky {
+            return None;
+        }
+        let defaults = SkySettings::default();
+        let (elevation, azimuth) = defaults.sun.angles();
+        Some(SkySettings {
+            turbidity: self.turbidity.unwrap_or(defaults.turbidity),
+            sun: SunPosition::Angles {
+                elevation: self.sun_elevation.unwrap_or(elevation),
+                azimuth: self.sun_azimuth.unwrap_or(azimuth),
+            },
+            ..defaults
+        })
+    }
+
+    fn scene(&self) -> RenderScene {
+        match self.scene_file {
+            Some(ref path) => RenderScene::File(path.clone()),
+            None => self.scene.clone(),
+        }
+    }
+
+    fn render_mode(&self) -> RayColorMode {
+        match self.mode {
+            RenderMode::Material => RayColorMode::Material { depth: self.depth },
+            RenderMode::Importance => RayColorMode::ImportanceSampled { depth: self.depth },
+            RenderMode::Spectral => RayColorMode::Spectral { depth: self.depth },
+            RenderMode::Normals => RayColorMode::ShadeNormal,
+            RenderMode::Depth => RayColorMode::Depth { max_t: self.max_t },
+            RenderMode::BlockColor => RayColorMode::BlockColor {
+                color: self.block_color,
+            },
+        }
+    }
+
+    /// The settings for the GUI to start with, replacing the ones it saved last time.
+    pub(crate) fn gui_settings(&self) -> Result<(RenderConfig, CameraSettings), SceneFileError> {
+        Ok((self.render_config(), self.camera_settings()?))
+    }
+
+    fn camera_settings(&self) -> Result<CameraSettings, SceneFileError> {
+        let defaults = self.scene().default_camera_settings()?;
+        Ok(CameraSettings {
+            look_from: self.look_from.unwrap_or(defaults.look_from),
+            look_at: self.look_at.unwrap_or(defaults.look_at),
+            vup: self.vup.unwrap_or(defaults.vup),
+            vfov: self.vfov.unwrap_or(defaults.vfov),
+            focus_dist: self.focus_dist.unwrap_or(defaults.focus_dist),
+            aperture: self.aperture.unwrap_or(defaults.aperture),
+            time0: self.time0.unwrap_or(defaults.time0),
+            time1: self.time1.unwrap_or(defaults.time1),
+        })
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts = s
+        .split(',')
+        .map(|p| p.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts.as_slice() {
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("expected 3 comma separated numbers, got '{}'", s)),
+    }
+}
+
+fn parse_denoise_iterations(s: &str) -> Result<u32, String> {
+    let iterations = s.parse::<u32>().map_err(|e| e.to_string())?;
+    if (1..=MAX_ITERATIONS).contains(&iterations) {
+        Ok(iterations)
+    } else {
+        Err(format!(
+            "expected between 1 and {} passes, got {}",
+            MAX_ITERATIONS, iterations
+        ))
+    }
+}
+
+/// Renders the image described by `options` without any GUI, returning the exit code to quit with.
+pub(crate) fn run_headless(options: CliOptions) -> i32 {
+    let config = options.render_config();
+    if config.image_width == 0 || config.image_height == 0 {
+        eprintln!("Image width and height must both be greater than zero");
+        return EXIT_RENDER_FAILED;
+    }
+    let cam_settings = match options.camera_settings() {
+        Ok(cam_settings) => cam_settings,
+        Err(e) => {
+            eprintln!("Failed to load scene: {}", e);
+            return EXIT_RENDER_FAILED;
+        }
+    };
+
+    let (command_tx, command_rx) = flume::unbounded::<RenderCommand>();
+    let (result_tx, result_rx) = flume::unbounded::<RenderResult>();
+    crate::spawn_render_thread(command_rx, result_tx);
+
+    println!(
+        "Rendering {width}x{height} image of {scene:?} with {samples} samples per pixel (seed {seed})",
+        width = config.image_width,
+        height = config.image_height,
+        scene = config.scene,
+        samples = match config.adaptive_sampling {
+            Some(adaptive) => format!("{}-{}", adaptive.min_samples, adaptive.max_samples),
+            None => config.samples_per_pixel.to_string(),
+        },
+        seed = config.seed,
+    );
+    println!(
+        "Looking from {from} to {at}, with up = {vup}",
+        from = cam_settings.look_from,
+        at = cam_settings.look_at,
+        vup = cam_settings.vup
+    );
+    println!(
+        "FOV is {fov}, aperture is {aperture}, focus distance is {focus_dist}",
+        fov = cam_settings.vfov,
+        aperture = cam_settings.aperture,
+        focus_dist = cam_settings.focus_dist,
+    );
+    command_tx
+        .send(RenderCommand::Render {
+            cam_settings,
+            config: config.clone(),
+        })
+        .ok()
+        .expect("render command send should succeed");
+
+    let terminal_display = if options.no_progress {
+        None
+    } else {
+        Some(TerminalSettings::default())
+    };
+
+    let mut data: Option<UiData> = None;
+    loop {
+        match result_rx.recv() {
+            Ok(RenderResult::Reset {
+                image_width,
+                image_height,
+            }) => {
+                data = Some(UiData::new(
+                    image_width,
+                    image_height,
+                    config.tone_mapping,
+                    config.denoising,
+                ))
+            }
+            Ok(RenderResult::Built { bvh_stats }) => println!("Built BVH: {}", bvh_stats),
+            Ok(RenderResult::ImageLine { line_num, line }) => {
+                let data = data
+                    .as_mut()
+                    .expect("render should be reset before lines are received");
+                data.store_pixel_line(line_num, line);
+
+                if let Some(settings) = terminal_display {
+                    data.render_terminal_progress_indicator(&settings, line_num);
+                }
+
+                if data.complete() {
+                    return save_output(data, &config);
+                }
+            }
+            Ok(RenderResult::Frame {
+                image,
+                denoised,
+                passes_completed,
+                total_passes,
+            }) => {
+                let data = data
+                    .as_mut()
+                    .expect("render should be reset before frames are received");
+                data.store_frame(image, denoised, passes_completed, total_passes);
+
+                if let Some(settings) = terminal_display {
+                    data.render_terminal_frame(&settings);
+                }
+
+                if data.complete() {
+                    return save_output(data, &config);
+                }
+            }
+            Ok(RenderResult::Failed { message }) => {
+                eprintln!("{}", message);
+                return EXIT_RENDER_FAILED;
+            }
+            Err(flume::RecvError::Disconnected) => {
+                eprintln!("Rendering thread exited before the image was complete");
+                return EXIT_RENDER_FAILED;
+            }
+        }
+    }
+}
+
+/// Save the finished image, returning the exit code to quit with.
+fn save_output(data: &UiData, config: &RenderConfig) -> i32 {
+    match data.save_output_to_file(config) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to save image: {}", e);
+            EXIT_SAVE_FAILED
+        }
+    }
+}
@@ -1,26 +1,42 @@
-use rgb::{ComponentMap, RGB8};
+use rgb::{ComponentMap, RGB, RGB8};
 
 use crate::vec3::Color;
 
-pub(crate) fn color_as_rgb8(pixel_color: Color, samples_per_pixel: u32) -> RGB8 {
-    let mut r = pixel_color.x;
-    let mut g = pixel_color.y;
-    let mut b = pixel_color.z;
+/// Convert a color to the single precision form that images store.
+pub fn color_to_rgb(c: Color) -> RGB<f32> {
+    RGB::new(c.x as f32, c.y as f32, c.z as f32)
+}
 
-    let scale = 1.0 / samples_per_pixel as f64;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
+/// Convert a pixel stored in an image back to a [`Color`].
+pub fn rgb_to_color(p: RGB<f32>) -> Color {
+    Color::new(p.r as f64, p.g as f64, p.b as f64)
+}
 
-    RGB8 {
-        r: (256.0f64 * r.clamp(0.0, 0.999)) as u8,
-        g: (256.0f64 * g.clamp(0.0, 0.999)) as u8,
-        b: (256.0f64 * b.clamp(0.0, 0.999)) as u8,
+/// Encode a linear value between 0 and 1 with the sRGB transfer function, as displays expect.
+pub fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
     }
 }
 
-pub(crate) fn rgb8_as_terminal_char(col: RGB8) -> String {
-    let uniform = col.map(|c| (c as f64 / 255.999) as f64);
+/// Decode a value between 0 and 1 that was encoded with the sRGB transfer function.
+pub fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Perceived brightness of a linear color.
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+pub fn rgb8_as_terminal_char(col: RGB8) -> String {
+    let uniform = col.map(|c| c as f64 / 255.999);
     let char_index = ((uniform.r + uniform.g + uniform.b) / 3.0 * 16.0) as u32;
 
     let c = std::char::from_digit(char_index, 16)
@@ -1,25 +1,24 @@
 use crate::{
     hittable::{HitRecord, Hittable},
-    material::Material,
+    material::{next_material_id, Material, ScatterRecord},
     ray::Ray,
     texture::Texture,
     util::random_double,
     vec3::{Color, Vec3},
 };
 
+/// How far past a crossing of a medium's boundary to look for the next one.
+const CROSSING_EPSILON: f64 = 0.0001;
+
 #[derive(Debug)]
-pub(crate) struct ConstantMedium {
+pub struct ConstantMedium {
     boundary: Box<dyn Hittable>,
     phase_function: Box<dyn Material>,
     neg_inv_density: f64,
 }
 
 impl ConstantMedium {
-    pub(crate) fn new(
-        boundary: Box<dyn Hittable>,
-        texture: Box<dyn Texture>,
-        density: f64,
-    ) -> Self {
+    pub fn new(boundary: Box<dyn Hittable>, texture: Box<dyn Texture>, density: f64) -> Self {
         Self {
             boundary,
             phase_function: Box::new(Isotropic::new(texture)),
@@ -29,34 +28,24 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut rec1 = self.boundary.hit(r, -std::f64::MAX, std::f64::MAX)?;
-        let mut rec2 = self.boundary.hit(r, rec1.t + 0.0001, std::f64::MAX)?;
-
-        if rec1.t < t_min {
-            rec1.t = t_min;
-        }
-        if rec2.t > t_max {
-            rec2.t = t_max;
-        }
-        if rec1.t >= rec2.t {
-            return None;
-        }
-        if rec1.t < 0.0 {
-            rec1.t = 0.0;
-        }
-
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let ray_length = r.direction().length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double(0.0, 1.0).ln();
-
-        if hit_distance > distance_inside_boundary {
-            return None;
-        }
+        // how far the ray gets through the medium before it hits a particle, which is the same
+        // however the medium is split up
+        let mut hit_distance = self.neg_inv_density * random_double(0.0, 1.0).ln();
+        let t = find_inside(self.boundary.as_ref(), r, t_min, t_max, |start, end| {
+            let distance_inside = (end - start) * ray_length;
+            if hit_distance <= distance_inside {
+                Some(start + hit_distance / ray_length)
+            } else {
+                hit_distance -= distance_inside;
+                None
+            }
+        })?;
 
         Some(HitRecord::new(
-            rec1.t + hit_distance / ray_length,
-            (rec1.u, rec1.v),
+            t,
+            entry_uv(self.boundary.as_ref(), r, t),
             r,
             Vec3::new(1.0, 0.0, 0.0), // arbitrarily chosen normal
             self.phase_function.as_ref(),
@@ -66,16 +55,75 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<crate::aabb::Aabb> {
         self.boundary.bounding_box(time0, time1)
     }
+
+    fn hit_surface(&self, _r: Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// Exactly, by the Beer-Lambert law.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut distance_inside = 0.0;
+        find_inside::<()>(self.boundary.as_ref(), r, t_min, t_max, |start, end| {
+            distance_inside += (end - start) * r.direction().length();
+            None
+        });
+        (distance_inside / self.neg_inv_density).exp()
+    }
+}
+
+/// Call `f` with the start and end of each stretch of `r` between `t_min` and `t_max` that's inside
+/// `boundary`, in order, until it returns something.
+///
+/// The boundary can be any closed shape, concave or not, with its normals facing outwards; where
+/// the ray starts inside it, the first stretch starts at `t_min`.
+pub(crate) fn find_inside<T>(
+    boundary: &dyn Hittable,
+    r: Ray,
+    t_min: f64,
+    t_max: f64,
+    mut f: impl FnMut(f64, f64) -> Option<T>,
+) -> Option<T> {
+    let mut start = t_min;
+    loop {
+        // the ray is inside up to the next crossing if it leaves there
+        let (crossing, inside) = match boundary.hit(r, start, f64::INFINITY) {
+            Some(rec) => (rec.t, !rec.front_face),
+            None => (f64::INFINITY, false),
+        };
+        if inside {
+            if let Some(result) = f(start, crossing.min(t_max)) {
+                return Some(result);
+            }
+        }
+        if crossing >= t_max {
+            return None;
+        }
+        start = crossing + CROSSING_EPSILON;
+    }
+}
+
+/// Texture coordinates of `boundary` where `r` came into it on its way to `t`, which is inside it;
+/// the textures of media are looked up there.
+pub(crate) fn entry_uv(boundary: &dyn Hittable, r: Ray, t: f64) -> (f64, f64) {
+    let back = Ray::new(r.at(t), -r.direction(), Some(r.time()));
+    boundary
+        .hit(back, 0.0, f64::INFINITY)
+        .map_or((0.0, 0.0), |rec| (rec.u, rec.v))
 }
 
+/// Scatters light equally in all directions, like the particles in fog and smoke.
 #[derive(Debug)]
-struct Isotropic {
+pub(crate) struct Isotropic {
     albedo: Box<dyn Texture>,
+    material_id: u32,
 }
 
 impl Isotropic {
-    fn new(albedo: Box<dyn Texture>) -> Self {
-        Self { albedo }
+    pub(crate) fn new(albedo: Box<dyn Texture>) -> Self {
+        Self {
+            albedo,
+            material_id: next_material_id(),
+        }
     }
 }
 
@@ -83,7 +131,75 @@ impl Material for Isotropic {
     fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Color, Ray)> {
         Some((
             self.albedo.value(hit.u, hit.v, hit.p),
-            Ray::new(hit.p, Vec3::random_in_unit_sphere(), Some(r_in.time())),
+            r_in.bounce(hit.p, Vec3::random_in_unit_sphere()),
         ))
     }
+
+    /// Scatters uniformly in all directions, so the phase function and the density are the same.
+    fn scatter_with_pdf(&self, r_in: Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            scattered: r_in.bounce(hit.p, Vec3::random_unit_vector()),
+            attenuation: self.albedo.value(hit.u, hit.v, hit.p),
+            pdf: Some(UNIFORM_SPHERE_PDF),
+        })
+    }
+
+    fn scattering(&self, _r_in: Ray, hit: &HitRecord, _direction: Vec3) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.p) * UNIFORM_SPHERE_PDF
+    }
+
+    fn scattering_pdf(&self, _r_in: Ray, _hit: &HitRecord, _direction: Vec3) -> f64 {
+        UNIFORM_SPHERE_PDF
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.p)
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
+}
+
+const UNIFORM_SPHERE_PDF: f64 = 1.0 / (4.0 * std::f64::consts::PI);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::DiffuseLambertian, sphere::Sphere, vec3::Point3};
+
+    #[derive(Debug)]
+    struct UvTexture;
+
+    impl Texture for UvTexture {
+        fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+            Color::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn collisions_are_textured_where_the_ray_entered() {
+        let boundary = || {
+            Box::new(Sphere::stationary(
+                Point3::zero(),
+                1.0,
+                Box::new(DiffuseLambertian::new(Color::one())),
+            ))
+        };
+        let fog = ConstantMedium::new(boundary(), Box::new(UvTexture), 1.0);
+        let r = Ray::new(Point3::new(0.3, 0.2, -5.0), Vec3::new(0.0, 0.0, 1.0), None);
+        let sphere = boundary();
+        let entry = sphere.hit(r, 0.001, f64::INFINITY).unwrap();
+
+        crate::util::with_seed(1, || {
+            // from outside, and from inside where the ray entered behind its origin
+            for r in [r, r.bounce(r.at(5.0), r.direction())] {
+                let collision = (0..100)
+                    .find_map(|_| fog.hit(r, 0.001, f64::INFINITY))
+                    .unwrap();
+                assert!((collision.u - entry.u).abs() < 1e-9);
+                assert!((collision.v - entry.v).abs() < 1e-9);
+            }
+        });
+    }
 }
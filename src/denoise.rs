@@ -0,0 +1,335 @@
+//! Denoising renders after the fact, with the edge-avoiding à-trous wavelet filter of Dammertz et
+//! al., "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering" (2010).
+//!
+//! The filter blurs each pixel with its neighbours at increasing distances, but only with
+//! neighbours that look like the same surface: the albedo, normal and depth AOVs stop it from
+//! blurring across edges, and the albedo is divided out first so that textures stay sharp. Like
+//! SVGF (Schied et al., "Spatiotemporal Variance-Guided Filtering", 2017), the variance AOV says
+//! how much of the difference between two pixels' brightness is just noise.
+//!
+//! Participating media stay noisier than surfaces, since the features of the first thing a ray
+//! meets inside them are noisy too.
+
+use rgb::RGB;
+
+use crate::{
+    aov::{Aov, AovLayer},
+    color::{color_to_rgb, luminance, rgb_to_color},
+    vec3::{Color, Vec3},
+    HdrImage,
+};
+
+/// The AOVs that guide the filter; denoised renders collect them even if they aren't saved.
+pub const GUIDE_AOVS: [Aov; 4] = [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Variance];
+
+/// B3 spline weights for offsets of -2 to 2 steps.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Weights for smoothing the variance estimate over offsets of -1 to 1 pixels.
+const VARIANCE_KERNEL: [f64; 3] = [1.0 / 4.0, 1.0 / 2.0, 1.0 / 4.0];
+
+/// Albedo below this is treated as black, and isn't divided out.
+const MIN_ALBEDO: f64 = 0.01;
+
+/// Most passes the filter will run; the last of 10 already reaches 512 pixels away.
+pub const MAX_ITERATIONS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Denoising {
+    /// Number of passes, each reaching twice as far as the one before; 3 passes blur up to 14
+    /// pixels away. Anything above [`MAX_ITERATIONS`] runs that many passes.
+    pub iterations: u32,
+    /// How different two pixels' brightness can be and still be blurred together, in standard
+    /// deviations of the pixel's noise
+    pub luminance_sigma: f64,
+    /// How sharply the filter stops at creases: the weight of a neighbour is the cosine of the
+    /// angle between the normals to this power
+    pub normal_power: f64,
+    /// How different two pixels' depths can be, relative to the depth and distance between them
+    pub depth_sigma: f64,
+    /// How different two pixels' albedos can be
+    pub albedo_sigma: f64,
+}
+
+impl Default for Denoising {
+    fn default() -> Self {
+        Self {
+            iterations: 3,
+            luminance_sigma: 4.0,
+            normal_power: 64.0,
+            depth_sigma: 0.01,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+/// What the filter knows about the first surface seen through a pixel.
+#[derive(Debug, Clone, Copy)]
+struct Features {
+    albedo: Option<Color>,
+    normal: Option<Vec3>,
+    depth: Option<f64>,
+}
+
+impl Features {
+    fn vec3(layer: Option<&AovLayer>, index: usize) -> Option<Vec3> {
+        layer.map(|l| {
+            Vec3::new(
+                l.value(index, 0) as f64,
+                l.value(index, 1) as f64,
+                l.value(index, 2) as f64,
+            )
+        })
+    }
+
+    /// The averaged normal of a pixel at the edge of a surface is shorter than 1, but the filter
+    /// only cares about its direction; pixels that saw no surface have no normal at all.
+    fn normal(layer: Option<&AovLayer>, index: usize) -> Option<Vec3> {
+        Features::vec3(layer, index)
+            .filter(|n| n.length_squared() > 0.0)
+            .map(|n| n.to_unit())
+    }
+
+    /// How similar the surface at `other`, `step` pixels away, is to this one, from 0 to 1.
+    fn similarity(&self, other: &Features, step: f64, settings: &Denoising) -> f64 {
+        let mut weight = 1.0;
+        if let (Some(a), Some(b)) = (self.albedo, other.albedo) {
+            weight *= (-(a - b).length_squared() / settings.albedo_sigma.powi(2)).exp();
+        }
+        if let (Some(a), Some(b)) = (self.normal, other.normal) {
+            weight *= a.dot(b).max(0.0).powf(settings.normal_power);
+        }
+        match (self.depth, other.depth) {
+            (Some(a), Some(b)) if a.is_finite() && b.is_finite() => {
+                let scale = settings.depth_sigma * step * a.max(1e-3);
+                weight *= (-(a - b).abs() / scale).exp();
+            }
+            // the background only blends with more background
+            (Some(a), Some(b)) if a.is_finite() != b.is_finite() => weight = 0.0,
+            _ => (),
+        }
+        weight
+    }
+}
+
+/// The albedo that is divided out of a pixel's color before filtering, and multiplied back in
+/// afterwards.
+fn demodulation(albedo: Option<Color>) -> Color {
+    let albedo = albedo.unwrap_or_else(Color::one);
+    let channel = |a: f64| if a < MIN_ALBEDO { 1.0 } else { a };
+    Color::new(channel(albedo.x), channel(albedo.y), channel(albedo.z))
+}
+
+fn divide(a: Color, b: Color) -> Color {
+    Color::new(a.x / b.x, a.y / b.y, a.z / b.z)
+}
+
+/// Denoise the beauty pass of `image`, guided by whichever of the [`GUIDE_AOVS`] it has. The AOVs
+/// themselves are copied unchanged.
+pub fn denoise(image: &HdrImage, settings: &Denoising) -> HdrImage {
+    use rayon::prelude::*;
+
+    let (width, height) = (image.width, image.height);
+    let layer = |aov| image.aovs.iter().find(|l| l.aov == aov);
+    let (albedo, normal, depth) = (layer(Aov::Albedo), layer(Aov::Normal), layer(Aov::Depth));
+    let features = (0..width * height)
+        .map(|index| Features {
+            albedo: Features::vec3(albedo, index),
+            normal: Features::normal(normal, index),
+            depth: depth.map(|l| l.value(index, 0) as f64),
+        })
+        .collect::<Vec<_>>();
+
+    // without a variance estimate, brightness differences don't stop the blur at all
+    let variance_layer = layer(Aov::Variance);
+    let mut pixels = image
+        .pixels
+        .iter()
+        .zip(&features)
+        .enumerate()
+        .map(|(index, (&p, f))| {
+            let demodulation = demodulation(f.albedo);
+            let variance = variance_layer.map_or(f64::INFINITY, |l| l.value(index, 0) as f64);
+            Sample {
+                irradiance: divide(rgb_to_color(p), demodulation),
+                variance: variance / luminance(demodulation).powi(2),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let neighbours = |x: usize, y: usize, step: isize, radius: isize| {
+        (-radius..=radius).flat_map(move |dy| {
+            (-radius..=radius).filter_map(move |dx| {
+                let (qx, qy) = (x as isize + dx * step, y as isize + dy * step);
+                let inside =
+                    (0..width as isize).contains(&qx) && (0..height as isize).contains(&qy);
+                inside.then(|| {
+                    (
+                        (dx + radius) as usize,
+                        (dy + radius) as usize,
+                        qy as usize * width + qx as usize,
+                    )
+                })
+            })
+        })
+    };
+
+    for iteration in 0..settings.iterations.min(MAX_ITERATIONS) {
+        let step = 1 << iteration;
+        pixels = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                let center = pixels[index];
+                let center_luminance = luminance(center.irradiance);
+
+                // the variance of a single pixel is itself noisy, so smooth it a little first
+                let (mut variance, mut variance_weight) = (0.0, 0.0);
+                for (kx, ky, q) in neighbours(x, y, 1, 1) {
+                    let weight = VARIANCE_KERNEL[kx] * VARIANCE_KERNEL[ky];
+                    variance += weight * pixels[q].variance;
+                    variance_weight += weight;
+                }
+                let noise = (variance / variance_weight).sqrt();
+
+                let mut sum = Sample::default();
+                let mut total_weight = 0.0;
+                for (kx, ky, q) in neighbours(x, y, step, 2) {
+                    let difference = (luminance(pixels[q].irradiance) - center_luminance).abs();
+                    let weight = KERNEL[kx]
+                        * KERNEL[ky]
+                        * (-difference / (settings.luminance_sigma * noise + 1e-6)).exp()
+                        * features[index].similarity(&features[q], step as f64, settings);
+                    if weight == 0.0 {
+                        // an infinite variance would make the sum not a number
+                        continue;
+                    }
+                    sum.irradiance += weight * pixels[q].irradiance;
+                    sum.variance += weight * weight * pixels[q].variance;
+                    total_weight += weight;
+                }
+                // the center pixel always counts fully, so this only happens if it's not a number
+                if total_weight > 0.0 {
+                    Sample {
+                        irradiance: sum.irradiance / total_weight,
+                        variance: sum.variance / (total_weight * total_weight),
+                    }
+                } else {
+                    center
+                }
+            })
+            .collect();
+    }
+
+    HdrImage {
+        width,
+        height,
+        pixels: pixels
+            .iter()
+            .zip(&features)
+            .map(|(p, f)| color_to_rgb(p.irradiance * demodulation(f.albedo)))
+            .collect::<Vec<RGB<f32>>>(),
+        aovs: image.aovs.clone(),
+    }
+}
+
+/// A pixel's irradiance (its color with the albedo divided out) and how noisy that is.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    irradiance: Color,
+    variance: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32x16 image of two walls meeting at a crease down the middle, the left one lit twice as
+    /// brightly as the right, with a fixed pattern of noise on top.
+    fn noisy_crease() -> HdrImage {
+        let (width, height) = (32, 16);
+        let mut image = HdrImage::new(width, height);
+        let mut normals = Vec::new();
+        for index in 0..width * height {
+            let left = index % width < width / 2;
+            let noise = (index * 7919 % 13) as f32 / 13.0 - 0.5;
+            let level = if left { 1.0 } else { 0.5 } + 0.5 * noise;
+            image.pixels[index] = RGB::new(level, level, level);
+            normals.extend(if left {
+                [0.0, 0.0, 1.0]
+            } else {
+                [1.0, 0.0, 0.0]
+            });
+        }
+        let layer = |aov: Aov, channels, values| AovLayer {
+            aov,
+            name: aov.name().to_owned(),
+            channels,
+            ids: false,
+            values,
+        };
+        image.aovs = vec![
+            layer(Aov::Normal, &["X", "Y", "Z"], normals),
+            layer(Aov::Depth, &["Z"], vec![5.0; width * height]),
+            layer(Aov::Variance, &["Y"], vec![0.02; width * height]),
+        ];
+        image
+    }
+
+    fn half_error(image: &HdrImage, left: bool) -> f32 {
+        let expected = if left { 1.0 } else { 0.5 };
+        let pixels = (0..image.pixels.len())
+            .filter(|index| (index % image.width < image.width / 2) == left)
+            .map(|index| (image.pixels[index].r - expected).powi(2))
+            .collect::<Vec<_>>();
+        (pixels.iter().sum::<f32>() / pixels.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn smooths_noise_without_blurring_across_creases() {
+        let noisy = noisy_crease();
+        let denoised = denoise(&noisy, &Denoising::default());
+
+        for left in [true, false] {
+            assert!(
+                half_error(&denoised, left) < 0.5 * half_error(&noisy, left),
+                "error went from {} to {}",
+                half_error(&noisy, left),
+                half_error(&denoised, left)
+            );
+        }
+        // right next to the crease, the brighter wall hasn't bled into the darker one
+        let (x, y) = (noisy.width / 2, noisy.height / 2);
+        assert!((denoised.pixel(x, y).r - 0.5).abs() < 0.15);
+        assert!((denoised.pixel(x - 1, y).r - 1.0).abs() < 0.15);
+        assert_eq!(denoised.aovs, noisy.aovs);
+    }
+
+    #[test]
+    fn works_without_guides() {
+        let mut image = noisy_crease();
+        image.aovs.clear();
+        let denoised = denoise(&image, &Denoising::default());
+
+        assert_eq!(denoised.pixels.len(), image.pixels.len());
+        assert!(denoised.pixels.iter().all(|p| p.r.is_finite()));
+    }
+
+    #[test]
+    fn caps_the_number_of_passes() {
+        let image = noisy_crease();
+        let capped = Denoising {
+            iterations: MAX_ITERATIONS,
+            ..Denoising::default()
+        };
+        let too_many = Denoising {
+            iterations: u32::MAX,
+            ..capped
+        };
+
+        assert_eq!(
+            denoise(&image, &too_many).pixels,
+            denoise(&image, &capped).pixels
+        );
+    }
+}
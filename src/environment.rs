@@ -0,0 +1,302 @@
+//! What rays that leave the scene without hitting anything see: a constant color, a gradient
+//! like the default sky, a high dynamic range photo of surroundings (an environment map) that
+//! lights the scene, or a procedural [`Sky`] with a sun.
+
+use std::f64::consts::PI;
+
+use crate::{
+    color::{luminance, rgb_to_color},
+    sky::Sky,
+    util::{self, degrees_to_radians},
+    vec3::{Color, Vec3},
+    HdrImage,
+};
+
+/// The light arriving from infinitely far away in every direction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Environment {
+    /// The same color in every direction
+    Color(Color),
+    /// Blend from `bottom` (looking straight down) to `top` (looking straight up)
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Default for Environment {
+    /// The white-to-blue sky from Ray Tracing in One Weekend.
+    fn default() -> Self {
+        Environment::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Environment {
+    /// The light arriving from `direction` (which doesn't need to be a unit vector).
+    pub fn color(&self, direction: Vec3) -> Color {
+        match self {
+            Environment::Color(color) => *color,
+            Environment::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.to_unit().y + 1.0);
+                (1.0 - t) * *bottom + t * *top
+            }
+            Environment::Map(map) => map.color(direction),
+            Environment::Sky(sky) => sky.color(direction),
+        }
+    }
+
+    /// Whether the environment is worth sampling directly as a light, like the objects in
+    /// [`crate::World::lights`]. Only maps and skies are, since their light mostly comes from a
+    /// few bright spots (like the sun) that scattering alone rarely finds.
+    pub fn is_sampled(&self) -> bool {
+        match self {
+            Environment::Map(map) => map.distribution.is_some(),
+            Environment::Sky(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Probability density (per unit solid angle) of [`Environment::random_direction`] picking
+    /// `direction`.
+    pub fn pdf_value(&self, direction: Vec3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf_value(direction),
+            Environment::Sky(sky) => sky.pdf_value(direction),
+            _ => 0.0,
+        }
+    }
+
+    /// Pick a random direction, mostly towards where the most light comes from. Only meaningful
+    /// if [`Environment::is_sampled`].
+    pub fn random_direction(&self) -> Vec3 {
+        match self {
+            Environment::Map(map) => map.random_direction(),
+            Environment::Sky(sky) => sky.random_direction(),
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+/// An equirectangular (latitude-longitude) image of the whole sphere of directions: the top row
+/// is straight up, the bottom row straight down, and the middle column faces +x, with +z a
+/// quarter of the way across.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentMap {
+    image: HdrImage,
+    /// Angle in radians that the image is turned anticlockwise around the y axis, seen from above
+    rotation: f64,
+    intensity: f64,
+    /// For picking a pixel in proportion to how much light comes from it, or `None` if the image
+    /// is black
+    distribution: Option<Distribution2D>,
+}
+
+impl EnvironmentMap {
+    /// Wrap `image` around the scene, turned `rotation` degrees around the y axis and with its
+    /// brightness multiplied by `intensity`. The image needs at least one pixel.
+    pub fn new(image: HdrImage, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width, image.height);
+        // rows near the poles cover less of the sphere, so they're picked less often
+        let weights = (0..height)
+            .map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                (0..width)
+                    .map(|x| luminance(rgb_to_color(image.pixel(x, y))).max(0.0) * sin_theta)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        Self {
+            image,
+            rotation: degrees_to_radians(rotation),
+            intensity,
+            distribution: Distribution2D::new(&weights),
+        }
+    }
+
+    /// The texture coordinates of `direction`: the fraction of the way across and down the image.
+    fn uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = direction.to_unit();
+        // around the y axis this is the same as spheres' texture coordinates
+        let phi = (-d.z).atan2(d.x) + PI - self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * PI * u + self.rotation;
+        let theta = PI * v;
+        Vec3::new(
+            -phi.cos() * theta.sin(),
+            theta.cos(),
+            phi.sin() * theta.sin(),
+        )
+    }
+
+    fn pixel_at(&self, u: f64, v: f64) -> (usize, usize) {
+        let (width, height) = (self.image.width, self.image.height);
+        let x = ((u * width as f64) as usize).min(width - 1);
+        let y = ((v * height as f64) as usize).min(height - 1);
+        (x, y)
+    }
+
+    fn color(&self, direction: Vec3) -> Color {
+        let (u, v) = self.uv(direction);
+        let (x, y) = self.pixel_at(u, v);
+        self.intensity * rgb_to_color(self.image.pixel(x, y))
+    }
+
+    fn pdf_value(&self, direction: Vec3) -> f64 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let (u, v) = self.uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel_at(u, v);
+        // the density over the image, divided by how much of the sphere each bit of it covers
+        distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random_direction(&self) -> Vec3 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return Vec3::new(0.0, 1.0, 0.0),
+        };
+        let (x, y) = distribution.sample(util::random_double_unit());
+        let (du, dv) = util::random_pair();
+        let u = (x as f64 + du) / self.image.width as f64;
+        let v = (y as f64 + dv) / self.image.height as f64;
+        self.direction(u, v)
+    }
+}
+
+/// A discrete distribution over the pixels of an image, for picking them in proportion to their
+/// weights.
+#[derive(Debug, Clone, PartialEq)]
+struct Distribution2D {
+    /// Cumulative weights of the rows, ending with the total
+    rows: Vec<f64>,
+    /// Cumulative weights of the pixels in each row, each ending with the row's total
+    columns: Vec<Vec<f64>>,
+}
+
+impl Distribution2D {
+    /// `None` if all the weights are zero, since nothing can be picked.
+    fn new(weights: &[Vec<f64>]) -> Option<Self> {
+        let cumulative = |values: &mut dyn Iterator<Item = f64>| {
+            values
+                .scan(0.0, |total, w| {
+                    *total += w;
+                    Some(*total)
+                })
+                .collect::<Vec<_>>()
+        };
+        let columns = weights
+            .iter()
+            .map(|row| cumulative(&mut row.iter().copied()))
+            .collect::<Vec<_>>();
+        let rows = cumulative(&mut columns.iter().map(|row| *row.last().unwrap_or(&0.0)));
+        match rows.last() {
+            Some(&total) if total > 0.0 => Some(Self { rows, columns }),
+            _ => None,
+        }
+    }
+
+    /// The index of the entry of `cumulative` that `xi` (from 0 to 1) picks.
+    fn pick(cumulative: &[f64], xi: f64) -> usize {
+        let target = xi * cumulative.last().unwrap();
+        cumulative
+            .partition_point(|&c| c <= target)
+            .min(cumulative.len() - 1)
+    }
+
+    /// Pick the `(x, y)` of a pixel with a number from 0 to 1.
+    fn sample(&self, xi: f64) -> (usize, usize) {
+        let y = Self::pick(&self.rows, xi);
+        // reuse where `xi` fell within the row to pick the column
+        let below = if y == 0 { 0.0 } else { self.rows[y - 1] };
+        let total = self.rows.last().unwrap();
+        let within = ((xi * total - below) / (self.rows[y] - below)).clamp(0.0, 1.0);
+        (Self::pick(&self.columns[y], within), y)
+    }
+
+    /// Probability of picking pixel `(x, y)`, times the number of pixels: the density of picking
+    /// a point on the image if it's scaled to fit in a unit square.
+    fn pdf(&self, x: usize, y: usize) -> f64 {
+        let row = &self.columns[y];
+        let weight = row[x] - if x == 0 { 0.0 } else { row[x - 1] };
+        weight / self.rows.last().unwrap() * (row.len() * self.rows.len()) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rgb::RGB;
+
+    /// A dim 8x4 map with one bright pixel.
+    fn map_with_sun() -> EnvironmentMap {
+        let mut image = HdrImage::new(8, 4);
+        for p in &mut image.pixels {
+            *p = RGB::new(0.25, 0.25, 0.25);
+        }
+        image.pixels[8 + 5] = RGB::new(100.0, 90.0, 80.0);
+        EnvironmentMap::new(image, 30.0, 2.0)
+    }
+
+    #[test]
+    fn directions_and_texture_coordinates_match() {
+        let map = map_with_sun();
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.8)] {
+            let (u2, v2) = map.uv(map.direction(u, v));
+            assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+        }
+        let sun = map.direction(5.5 / 8.0, 1.5 / 4.0);
+        assert_eq!(map.color(sun), Color::new(200.0, 180.0, 160.0));
+        assert_eq!(Environment::Map(map).color(-sun), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn sampling_density_integrates_to_one_and_favours_the_sun() {
+        let environment = Environment::Map(map_with_sun());
+        assert!(environment.is_sampled());
+
+        // integrate the density over the sphere by uniformly sampling directions
+        let samples = 200_000;
+        let integral = util::with_seed(1, || {
+            (0..samples)
+                .map(|_| environment.pdf_value(Vec3::random_unit_vector()))
+                .sum::<f64>()
+        }) * 4.0
+            * PI
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.02, "integral is {}", integral);
+
+        let toward_sun = util::with_seed(2, || {
+            (0..1000)
+                .filter(|_| environment.color(environment.random_direction()).x > 1.0)
+                .count()
+        });
+        assert!(toward_sun > 900, "{} samples toward the sun", toward_sun);
+    }
+
+    #[test]
+    fn black_maps_and_plain_colors_are_not_sampled() {
+        let black = EnvironmentMap::new(HdrImage::new(4, 2), 0.0, 1.0);
+        assert!(!Environment::Map(black).is_sampled());
+        assert!(!Environment::default().is_sampled());
+        assert_eq!(
+            Environment::default().color(Vec3::new(0.0, 2.0, 0.0)),
+            Color::new(0.5, 0.7, 1.0)
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Participating media whose density varies from place to place, like clouds, smoke and fog.
+//!
+//! Collisions are found by delta tracking: tentative collisions are sampled as if the medium were
+//! as dense as it gets everywhere, and each is accepted with a probability of how dense the medium
+//! really is there relative to that. Rejected ones are "null" collisions, which the ray carries
+//! straight on through.
+//!
+//! Shadow rays use ratio tracking instead, which measures how much light gets through rather than
+//! whether a ray does (see [`Hittable::transmittance`]).
+
+use crate::{
+    aabb::Aabb,
+    constant_medium::{entry_uv, find_inside, Isotropic},
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    perlin::Perlin,
+    ray::Ray,
+    texture::Texture,
+    util::random_double_unit,
+    vec3::{Point3, Vec3},
+};
+
+/// How dense a medium is at each point.
+pub trait Density: std::fmt::Debug + Send + Sync {
+    /// The density at `p`, as the chance of colliding with a particle per unit distance.
+    fn density(&self, p: Point3) -> f64;
+
+    /// A bound that [`Density::density`] never goes above; the closer it is to the real maximum,
+    /// the fewer null collisions need sampling.
+    fn max_density(&self) -> f64;
+}
+
+/// Densities at the centers of the voxels of a grid filling a box, blended smoothly in between.
+/// Outside the box the medium is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityGrid {
+    min: Point3,
+    max: Point3,
+    resolution: [usize; 3],
+    /// Ordered by x, then y, then z
+    values: Vec<f64>,
+    max_value: f64,
+}
+
+impl DensityGrid {
+    /// A grid with `resolution` voxels along each axis, or `None` if that isn't how many `values`
+    /// there are. The values are ordered by x, then y, then z, so the value for voxel `(x, y, z)`
+    /// is at index `x + resolution[0] * (y + resolution[1] * z)`.
+    pub fn new(min: Point3, max: Point3, resolution: [usize; 3], values: Vec<f64>) -> Option<Self> {
+        if resolution.contains(&0) || values.len() != resolution.iter().product() {
+            return None;
+        }
+        let max_value = values.iter().copied().fold(0.0, f64::max);
+        Some(Self {
+            min,
+            max,
+            resolution,
+            values,
+            max_value,
+        })
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[x + nx * (y + ny * z)].max(0.0)
+    }
+}
+
+impl Density for DensityGrid {
+    fn density(&self, p: Point3) -> f64 {
+        // the voxels either side of p along each axis, and how far p is between their centers
+        let mut cells = [(0, 0, 0.0); 3];
+        for (axis, cell) in cells.iter_mut().enumerate() {
+            let fraction = (p[axis] - self.min[axis]) / (self.max[axis] - self.min[axis]);
+            if !(0.0..=1.0).contains(&fraction) {
+                return 0.0;
+            }
+            let n = self.resolution[axis];
+            let position = (fraction * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let below = (position as usize).min(n - 1);
+            *cell = (below, (below + 1).min(n - 1), position - below as f64);
+        }
+
+        let [(x0, x1, tx), (y0, y1, ty), (z0, z1, tz)] = cells;
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let along_x = |y, z| lerp(tx, self.value(x0, y, z), self.value(x1, y, z));
+        let along_y = |z| lerp(ty, along_x(y0, z), along_x(y1, z));
+        lerp(tz, along_y(z0), along_y(z1))
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_value
+    }
+}
+
+/// Billowing density from Perlin turbulence, for clouds and smoke.
+#[derive(Debug, Clone)]
+pub struct Turbulence {
+    noise: Perlin,
+    density: f64,
+    scale: f64,
+    depth: i32,
+}
+
+impl Turbulence {
+    /// Turbulence with `depth` octaves of noise, the first with features about `1 / scale` across,
+    /// reaching up to `density` where the turbulence is strongest.
+    pub fn new(noise: Perlin, density: f64, scale: f64, depth: i32) -> Self {
+        Self {
+            noise,
+            density,
+            scale,
+            depth,
+        }
+    }
+}
+
+impl Density for Turbulence {
+    fn density(&self, p: Point3) -> f64 {
+        let turbulence = self.noise.sample_turbulence(self.scale * p, self.depth);
+        self.density * turbulence.min(1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+/// Fog lying on the ground, which is `density` thick up to `height` and thins out above it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightFog {
+    density: f64,
+    height: f64,
+    /// How far above `height` the fog gets thinner by a factor of e
+    falloff: f64,
+}
+
+impl HeightFog {
+    pub fn new(density: f64, height: f64, falloff: f64) -> Self {
+        Self {
+            density,
+            height,
+            falloff,
+        }
+    }
+}
+
+impl Density for HeightFog {
+    fn density(&self, p: Point3) -> f64 {
+        let above = (p.y - self.height).max(0.0);
+        self.density * (-above / self.falloff).exp()
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+/// A medium that fills `boundary` with particles that scatter light equally in all directions,
+/// spread out as `density` says.
+#[derive(Debug)]
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable>,
+    density: Box<dyn Density>,
+    phase_function: Box<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        texture: Box<dyn Texture>,
+        density: Box<dyn Density>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function: Box::new(Isotropic::new(texture)),
+        }
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let max_density = self.density.max_density();
+        if max_density <= 0.0 {
+            return None;
+        }
+        // average distance between tentative collisions, in units of t
+        let step = 1.0 / (max_density * r.direction().length());
+        let t = find_inside(self.boundary.as_ref(), r, t_min, t_max, |start, end| {
+            let mut t = start;
+            loop {
+                t -= (1.0 - random_double_unit()).ln() * step;
+                if t >= end {
+                    return None;
+                }
+                if random_double_unit() * max_density < self.density.density(r.at(t)) {
+                    return Some(t);
+                }
+            }
+        })?;
+
+        Some(HitRecord::new(
+            t,
+            entry_uv(self.boundary.as_ref(), r, t),
+            r,
+            Vec3::new(1.0, 0.0, 0.0), // arbitrarily chosen normal
+            self.phase_function.as_ref(),
+        ))
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+
+    fn hit_surface(&self, _r: Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// Estimated by ratio tracking: the estimate is scaled down at each tentative collision by the
+    /// chance that it would have been real. This is unbiased, and less noisy than checking
+    /// whether [`Hittable::hit`] finds a collision.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let max_density = self.density.max_density();
+        if max_density <= 0.0 {
+            return 1.0;
+        }
+        let step = 1.0 / (max_density * r.direction().length());
+        let mut transmittance = 1.0;
+        find_inside::<()>(self.boundary.as_ref(), r, t_min, t_max, |start, end| {
+            let mut t = start;
+            loop {
+                t -= (1.0 - random_double_unit()).ln() * step;
+                if t >= end {
+                    return None;
+                }
+                transmittance *= 1.0 - self.density.density(r.at(t)) / max_density;
+            }
+        });
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        box3d::Box3D,
+        bvh_node::BvhNode,
+        constant_medium::ConstantMedium,
+        hittable::{HittableList, Transform},
+        material::DiffuseLambertian,
+        sphere::Sphere,
+        texture::ColorTexture,
+        util,
+        vec3::Color,
+    };
+
+    fn medium(boundary: Box<dyn Hittable>, density: Box<dyn Density>) -> HeterogeneousMedium {
+        HeterogeneousMedium::new(boundary, Box::new(ColorTexture::new(Color::one())), density)
+    }
+
+    /// Fraction of `r`s that get through `medium` without a collision.
+    fn fraction_through(medium: &HeterogeneousMedium, r: Ray) -> f64 {
+        let samples = 20_000;
+        util::with_seed(1, || {
+            (0..samples)
+                .filter(|_| medium.hit(r, 0.001, f64::INFINITY).is_none())
+                .count()
+        }) as f64
+            / samples as f64
+    }
+
+    #[test]
+    fn light_gets_through_as_much_as_the_density_along_the_way_allows() {
+        // fog that thins out above y = 0, in a box from -1 to 1
+        let fog = medium(
+            Box::new(Box3D::new(
+                Point3::new(-1.0, -1.0, -1.0),
+                Point3::new(1.0, 1.0, 1.0),
+                DiffuseLambertian::new(Color::one()),
+            )),
+            Box::new(HeightFog::new(1.0, 0.0, 0.5)),
+        );
+        let up = Ray::new(Point3::new(0.0, -3.0, 0.0), Vec3::new(0.0, 2.0, 0.0), None);
+        // the optical depth is 1 below the height and ∫ exp(-2y) = 0.5 (1 - exp(-2)) above it
+        let expected = (-1.0 - 0.5 * (1.0 - (-2.0f64).exp())).exp();
+        let through = fraction_through(&fog, up);
+        assert!(
+            (through - expected).abs() < 0.01,
+            "{} got through, expected {}",
+            through,
+            expected
+        );
+
+        let ratio_tracked = util::with_seed(2, || {
+            (0..20_000)
+                .map(|_| fog.transmittance(up, 0.001, f64::INFINITY))
+                .sum::<f64>()
+        }) / 20_000.0;
+        assert!(
+            (ratio_tracked - expected).abs() < 0.01,
+            "ratio tracking gave {}, expected {}",
+            ratio_tracked,
+            expected
+        );
+        // rays that start inside the box only go through what's left of it
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), None);
+        let expected = (-0.5 * (1.0 - (-2.0f64).exp())).exp();
+        assert!((fraction_through(&fog, inside) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn concave_boundaries_only_hold_the_medium_inside_them() {
+        // a hollow shell of smoke, whose inner surface faces inwards
+        let shell = HittableList {
+            objects: vec![
+                Box::new(Sphere::stationary(
+                    Point3::zero(),
+                    2.0,
+                    Box::new(DiffuseLambertian::new(Color::one())),
+                )),
+                Box::new(Sphere::stationary(
+                    Point3::zero(),
+                    -1.0,
+                    Box::new(DiffuseLambertian::new(Color::one())),
+                )),
+            ],
+        };
+        let smoke = medium(
+            Box::new(shell),
+            Box::new(
+                DensityGrid::new(
+                    Point3::one() * -2.0,
+                    Point3::one() * 2.0,
+                    [1, 1, 1],
+                    vec![0.5],
+                )
+                .unwrap(),
+            ),
+        );
+        // straight through the middle there's 2 units of smoke, from the center there's 1
+        let through = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), None);
+        assert!((fraction_through(&smoke, through) - (-1.0f64).exp()).abs() < 0.01);
+        let from_center = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), None);
+        assert!((fraction_through(&smoke, from_center) - (-0.5f64).exp()).abs() < 0.01);
+        // and collisions only happen in the shell
+        util::with_seed(3, || {
+            for _ in 0..1000 {
+                if let Some(rec) = smoke.hit(through, 0.001, f64::INFINITY) {
+                    let distance = rec.p.length();
+                    assert!((1.0..=2.0).contains(&distance), "collision at {}", rec.p);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn shadow_rays_pass_through_media_to_the_surfaces_behind() {
+        let fog = Transform::new(ConstantMedium::new(
+            Box::new(Box3D::new(
+                Point3::new(-1.0, -1.0, -1.0),
+                Point3::new(1.0, 1.0, 1.0),
+                DiffuseLambertian::new(Color::one()),
+            )),
+            Box::new(ColorTexture::new(Color::one())),
+            0.5,
+        ))
+        .translate(Vec3::new(0.0, 0.0, 1.0));
+        let wall = Sphere::stationary(
+            Point3::new(0.0, 0.0, 5.0),
+            1.0,
+            Box::new(DiffuseLambertian::new(Color::one())),
+        );
+        let world = BvhNode::new(vec![Box::new(fog), Box::new(wall)], 0.0, 1.0);
+
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), None);
+        let rec = world.hit_surface(r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+        // 2 units of fog on the way to the wall, and none before it starts
+        let transmittance = world.transmittance(r, 0.001, rec.t);
+        assert!((transmittance - (-1.0f64).exp()).abs() < 1e-4);
+        assert_eq!(world.transmittance(r, 0.001, 3.0), 1.0);
+    }
+
+    #[test]
+    fn density_grids_blend_between_voxel_centers() {
+        let grid = DensityGrid::new(
+            Point3::zero(),
+            Point3::new(2.0, 1.0, 1.0),
+            [2, 1, 1],
+            vec![1.0, 3.0],
+        )
+        .unwrap();
+        assert_eq!(grid.density(Point3::new(0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density(Point3::new(1.0, 0.2, 0.7)), 2.0);
+        // flat past the outermost centers, and empty outside the box
+        assert_eq!(grid.density(Point3::new(1.9, 0.5, 0.5)), 3.0);
+        assert_eq!(grid.density(Point3::new(2.1, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.max_density(), 3.0);
+        assert!(DensityGrid::new(Point3::zero(), Point3::one(), [2, 2, 2], vec![1.0]).is_none());
+    }
+}
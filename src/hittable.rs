@@ -1,13 +1,16 @@
+use std::sync::Arc;
+
 use crate::{
     aabb::Aabb,
     material::Material,
+    matrix::{Matrix4, Quaternion},
     ray::Ray,
-    util::degrees_to_radians,
+    util::{degrees_to_radians, random_int},
     vec3::{Point3, Vec3},
 };
 
 #[derive(Debug, Clone)]
-pub(crate) struct HitRecord<'m> {
+pub struct HitRecord<'m> {
     /// How far along the ray the hit happened
     pub t: f64,
     /// The u texture coordinate of the hit
@@ -19,16 +22,19 @@ pub(crate) struct HitRecord<'m> {
     pub normal: Vec3,
     pub front_face: bool,
     pub mat_ptr: &'m dyn Material,
+    /// Which object was hit, for the object ID pass: [`crate::bvh_node::BvhNode`] numbers its
+    /// objects from 1 in the order it was given them, and 0 means nothing has numbered it
+    pub object_id: u32,
 }
 
 impl HitRecord<'_> {
-    pub(crate) fn new(
+    pub fn new(
         t: f64,
         (u, v): (f64, f64),
         r: Ray,
         outward_normal: Vec3,
         material: &dyn Material,
-    ) -> HitRecord {
+    ) -> HitRecord<'_> {
         let p = r.at(t);
         let front_face = r.direction().dot(outward_normal) < 0.0;
         let normal = if front_face {
@@ -44,43 +50,134 @@ impl HitRecord<'_> {
             front_face,
             normal,
             mat_ptr: material,
+            object_id: 0,
         }
     }
 }
 
-pub(crate) trait Hittable: std::fmt::Debug + Sync + Send {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: std::fmt::Debug + Sync + Send {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Probability density (per unit solid angle) of [`Hittable::random_direction`] picking the
+    /// direction of `r` from the origin of `r`.
+    ///
+    /// Only objects that can be sampled directly as lights need to implement this.
+    fn pdf_value(&self, _r: Ray) -> f64 {
+        0.0
+    }
+
+    /// Pick a random direction from `origin` towards this object at `time`.
+    fn random_direction(&self, _origin: Point3, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Like [`Hittable::hit`], but passing straight through participating media, which
+    /// [`Hittable::transmittance`] accounts for instead. Shadow rays use the two together, so
+    /// that fog and smoke dim the light smoothly rather than blocking it at random.
+    ///
+    /// Objects that contain media need to implement this, along with anything that wraps them.
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hit(r, t_min, t_max)
+    }
+
+    /// Fraction of light that gets through the participating media in this object along `r`
+    /// between `t_min` and `t_max`, ignoring any surfaces on the way.
+    fn transmittance(&self, _r: Ray, _t_min: f64, _t_max: f64) -> f64 {
+        1.0
+    }
+}
+
+impl<H: Hittable + ?Sized> Hittable for Box<H> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.as_ref().pdf_value(r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.as_ref().random_direction(origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit_surface(r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.as_ref().transmittance(r, t_min, t_max)
+    }
+}
+
+impl<H: Hittable + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.as_ref().pdf_value(r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.as_ref().random_direction(origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit_surface(r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.as_ref().transmittance(r, t_min, t_max)
+    }
 }
 
 #[derive(Debug, Default)]
-pub(crate) struct HittableList {
+pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
 }
 
 impl HittableList {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn add(&mut self, object: Box<dyn Hittable>) {
+    pub fn add(&mut self, object: Box<dyn Hittable>) {
         self.objects.push(object)
     }
 }
 
-impl Hittable for HittableList {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl HittableList {
+    fn closest_hit<'a>(
+        &'a self,
+        t_max: f64,
+        hit: impl Fn(&'a dyn Hittable, f64) -> Option<HitRecord<'a>>,
+    ) -> Option<HitRecord<'a>> {
         let mut best_hit: Option<HitRecord> = None;
 
         for object in self.objects.iter() {
             let new_t_max = best_hit.as_ref().map_or(t_max, |h| h.t);
-            if let Some(new_hit) = object.hit(r, t_min, new_t_max) {
+            if let Some(new_hit) = hit(object.as_ref(), new_t_max) {
                 best_hit = Some(new_hit);
             }
         }
 
         best_hit
     }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(t_max, |object, t_max| object.hit(r, t_min, t_max))
+    }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         let mut result = None;
@@ -97,27 +194,63 @@ impl Hittable for HittableList {
         }
         result
     }
+
+    /// Picks one of the objects uniformly at random, so the density is the average of theirs.
+    fn pdf_value(&self, r: Ray) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.objects.iter().map(|o| o.pdf_value(r)).sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        let index = random_int(0, self.objects.len() as i32 - 1) as usize;
+        self.objects[index].random_direction(origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(t_max, |object, t_max| object.hit_surface(r, t_min, t_max))
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.objects
+            .iter()
+            .map(|o| o.transmittance(r, t_min, t_max))
+            .product()
+    }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct Translate<H: Hittable> {
+pub struct Translate<H: Hittable> {
     offset: Vec3,
     obj: H,
 }
 
 impl<H: Hittable> Translate<H> {
-    pub(crate) fn new(offset: Vec3, obj: H) -> Self {
+    pub fn new(offset: Vec3, obj: H) -> Self {
         Self { offset, obj }
     }
 }
 
+impl<H: Hittable> Translate<H> {
+    fn moved_ray(&self, r: Ray) -> Ray {
+        Ray::new(r.origin() - self.offset, r.direction(), Some(r.time()))
+    }
+
+    fn moved_back<'a>(&self, rec: HitRecord<'a>) -> HitRecord<'a> {
+        HitRecord {
+            p: rec.p + self.offset,
+            ..rec
+        }
+    }
+}
+
 impl<H: Hittable> Hittable for Translate<H> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let moved_r = Ray::new(r.origin() - self.offset, r.direction(), Some(r.time()));
-        self.obj.hit(moved_r, t_min, t_max).map(|h| HitRecord {
-            p: h.p + self.offset,
-            ..h
-        })
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.obj
+            .hit(self.moved_ray(r), t_min, t_max)
+            .map(|rec| self.moved_back(rec))
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -125,10 +258,28 @@ impl<H: Hittable> Hittable for Translate<H> {
             .bounding_box(time0, time1)
             .map(|b| Aabb::new(b.min() + self.offset, b.max() + self.offset))
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.obj.pdf_value(self.moved_ray(r))
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.obj.random_direction(origin - self.offset, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.obj
+            .hit_surface(self.moved_ray(r), t_min, t_max)
+            .map(|rec| self.moved_back(rec))
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.obj.transmittance(self.moved_ray(r), t_min, t_max)
+    }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct RotateY<H: Hittable> {
+pub struct RotateY<H: Hittable> {
     obj: H,
     sin_theta: f64,
     cos_theta: f64,
@@ -136,13 +287,13 @@ pub(crate) struct RotateY<H: Hittable> {
 }
 
 impl<H: Hittable> RotateY<H> {
-    pub(crate) fn new(angle: f64, obj: H) -> Self {
+    pub fn new(angle: f64, obj: H) -> Self {
         let radians = degrees_to_radians(angle);
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
 
-        let mut min = Vec3::new(std::f64::MAX, std::f64::MAX, std::f64::MAX);
-        let mut max = Vec3::new(std::f64::MIN, std::f64::MIN, std::f64::MIN);
+        let mut min = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Vec3::new(f64::MIN, f64::MIN, f64::MIN);
 
         Self {
             bounding_box: {
@@ -175,35 +326,451 @@ impl<H: Hittable> RotateY<H> {
     }
 }
 
-impl<H: Hittable> Hittable for RotateY<H> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.origin();
-        let mut direction = r.direction();
-
-        origin.x = self.cos_theta * r.origin().x - self.sin_theta * r.origin().z;
-        origin.z = self.sin_theta * r.origin().x + self.cos_theta * r.origin().z;
-
-        direction.x = self.cos_theta * r.direction().x - self.sin_theta * r.direction().z;
-        direction.z = self.sin_theta * r.direction().x + self.cos_theta * r.direction().z;
-
-        let rotated_r = Ray::new(origin, direction, Some(r.time()));
-
-        self.obj.hit(rotated_r, t_min, t_max).map(|rec| HitRecord {
-            p: Vec3::new(
-                self.cos_theta * rec.p.x + self.sin_theta * rec.p.z,
-                rec.p.y,
-                -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z,
-            ),
-            normal: Vec3::new(
-                self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z,
-                rec.normal.y,
-                -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z,
-            ),
+impl<H: Hittable> RotateY<H> {
+    /// Rotate a world space vector into the object's space.
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    /// Rotate a vector in the object's space back into world space.
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
+
+impl<H: Hittable> RotateY<H> {
+    fn rotated_ray(&self, r: Ray) -> Ray {
+        Ray::new(
+            self.to_object(r.origin()),
+            self.to_object(r.direction()),
+            Some(r.time()),
+        )
+    }
+
+    fn rotated_back<'a>(&self, rec: HitRecord<'a>) -> HitRecord<'a> {
+        HitRecord {
+            p: self.to_world(rec.p),
+            normal: self.to_world(rec.normal),
             ..rec
-        })
+        }
+    }
+}
+
+impl<H: Hittable> Hittable for RotateY<H> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.obj
+            .hit(self.rotated_ray(r), t_min, t_max)
+            .map(|rec| self.rotated_back(rec))
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         self.bounding_box
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.obj.pdf_value(self.rotated_ray(r))
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.to_world(self.obj.random_direction(self.to_object(origin), time))
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.obj
+            .hit_surface(self.rotated_ray(r), t_min, t_max)
+            .map(|rec| self.rotated_back(rec))
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.obj.transmittance(self.rotated_ray(r), t_min, t_max)
+    }
+}
+
+/// An affine transform along with its inverse, for moving rays into an object's space and what
+/// they hit back out of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    /// Moves points from the object's space into world space
+    to_world: Matrix4,
+    /// The inverse of `to_world`, kept up to date alongside it rather than worked out from it
+    to_object: Matrix4,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Placement {
+    /// Leaves everything where it is.
+    pub const IDENTITY: Placement = Placement {
+        to_world: Matrix4::IDENTITY,
+        to_object: Matrix4::IDENTITY,
+    };
+
+    /// Apply `step` after the placement so far, given its inverse.
+    fn then(self, step: Matrix4, inverse: Matrix4) -> Self {
+        Self {
+            to_world: step * self.to_world,
+            to_object: self.to_object * inverse,
+        }
+    }
+
+    /// Then move by `offset`.
+    pub fn translate(self, offset: Vec3) -> Self {
+        self.then(Matrix4::translation(offset), Matrix4::translation(-offset))
+    }
+
+    /// Then rotate `angle` degrees around `axis` (through the origin), anticlockwise when looking
+    /// back along the axis.
+    pub fn rotate(self, axis: Vec3, angle: f64) -> Self {
+        self.rotate_quaternion(Quaternion::from_axis_angle(axis, angle))
+    }
+
+    /// Then rotate by `rotation` (around the origin).
+    pub fn rotate_quaternion(self, rotation: Quaternion) -> Self {
+        let rotation = rotation.normalized();
+        self.then(rotation.into(), rotation.conjugate().into())
+    }
+
+    /// Then scale by `factors` along each axis (from the origin); negative factors mirror. None
+    /// of the factors can be zero.
+    pub fn scale(self, factors: Vec3) -> Self {
+        assert!(
+            factors.x != 0.0 && factors.y != 0.0 && factors.z != 0.0,
+            "can't scale by zero"
+        );
+        let inverse = Vec3::new(1.0 / factors.x, 1.0 / factors.y, 1.0 / factors.z);
+        self.then(Matrix4::scaling(factors), Matrix4::scaling(inverse))
+    }
+
+    /// The matrix that moves points from the object's space into world space.
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.to_world
+    }
+
+    fn ray_to_object(&self, r: Ray) -> Ray {
+        // the direction isn't normalized, so that distances along the ray stay the same
+        Ray::new(
+            self.to_object.transform_point(r.origin()),
+            self.to_object.transform_vector(r.direction()),
+            Some(r.time()),
+        )
+    }
+
+    fn record_to_world<'a>(&self, rec: HitRecord<'a>) -> HitRecord<'a> {
+        HitRecord {
+            p: self.to_world.transform_point(rec.p),
+            // normals stay perpendicular to the surface if they're transformed by the inverse
+            // transpose; this keeps which side they face too
+            normal: self
+                .to_object
+                .transpose()
+                .transform_vector(rec.normal)
+                .to_unit(),
+            ..rec
+        }
+    }
+
+    /// Hit `obj` placed like this.
+    pub fn hit<'a, H: Hittable + ?Sized>(
+        &self,
+        obj: &'a H,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord<'a>> {
+        obj.hit(self.ray_to_object(r), t_min, t_max)
+            .map(|rec| self.record_to_world(rec))
+    }
+
+    /// [`Hittable::hit_surface`] of `obj` placed like this.
+    pub fn hit_surface<'a, H: Hittable + ?Sized>(
+        &self,
+        obj: &'a H,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord<'a>> {
+        obj.hit_surface(self.ray_to_object(r), t_min, t_max)
+            .map(|rec| self.record_to_world(rec))
+    }
+
+    /// [`Hittable::transmittance`] of `obj` placed like this.
+    pub fn transmittance<H: Hittable + ?Sized>(
+        &self,
+        obj: &H,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> f64 {
+        obj.transmittance(self.ray_to_object(r), t_min, t_max)
+    }
+
+    /// The box around `bbox` once it's placed like this.
+    pub fn bounding_box(&self, bbox: Aabb) -> Aabb {
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for corner in 0..8 {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) == 0 {
+                    bbox.min()[axis]
+                } else {
+                    bbox.max()[axis]
+                }
+            };
+            let p = self
+                .to_world
+                .transform_point(Point3::new(pick(0), pick(1), pick(2)));
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        Aabb::new(min, max)
+    }
+
+    /// [`Hittable::pdf_value`] of `obj` placed like this.
+    pub fn pdf_value<H: Hittable + ?Sized>(&self, obj: &H, r: Ray) -> f64 {
+        // stretching space stretches solid angles too, so the density in the object's space has
+        // to be converted: directions near ones that get squashed together are denser
+        let direction = r.direction().to_unit();
+        let object_direction = self.to_object.transform_vector(direction);
+        let jacobian =
+            self.to_object.linear_determinant().abs() / object_direction.length().powi(3);
+        obj.pdf_value(Ray::new(
+            self.to_object.transform_point(r.origin()),
+            object_direction,
+            Some(r.time()),
+        )) * jacobian
+    }
+
+    /// [`Hittable::random_direction`] of `obj` placed like this.
+    pub fn random_direction<H: Hittable + ?Sized>(
+        &self,
+        obj: &H,
+        origin: Point3,
+        time: f64,
+    ) -> Vec3 {
+        self.to_world
+            .transform_vector(obj.random_direction(self.to_object.transform_point(origin), time))
+    }
+}
+
+/// An object placed with an arbitrary affine transform: any combination of rotations (around any
+/// axis), non-uniform scaling, mirroring and translation, built up step by step.
+#[derive(Clone, Debug)]
+pub struct Transform<H: Hittable> {
+    obj: H,
+    placement: Placement,
+}
+
+impl<H: Hittable> Transform<H> {
+    /// `obj`, not transformed at all yet.
+    pub fn new(obj: H) -> Self {
+        Self {
+            obj,
+            placement: Placement::IDENTITY,
+        }
+    }
+
+    /// Then move the object by `offset`.
+    pub fn translate(self, offset: Vec3) -> Self {
+        Self {
+            placement: self.placement.translate(offset),
+            ..self
+        }
+    }
+
+    /// Then rotate the object `angle` degrees around `axis` (through the origin), anticlockwise
+    /// when looking back along the axis.
+    pub fn rotate(self, axis: Vec3, angle: f64) -> Self {
+        Self {
+            placement: self.placement.rotate(axis, angle),
+            ..self
+        }
+    }
+
+    /// Then rotate the object by `rotation` (around the origin).
+    pub fn rotate_quaternion(self, rotation: Quaternion) -> Self {
+        Self {
+            placement: self.placement.rotate_quaternion(rotation),
+            ..self
+        }
+    }
+
+    /// Then scale the object by `factors` along each axis (from the origin); negative factors
+    /// mirror it. None of the factors can be zero.
+    pub fn scale(self, factors: Vec3) -> Self {
+        Self {
+            placement: self.placement.scale(factors),
+            ..self
+        }
+    }
+
+    /// The matrix that moves points from the object's space into world space.
+    pub fn matrix(&self) -> &Matrix4 {
+        self.placement.matrix()
+    }
+}
+
+impl<H: Hittable> Hittable for Transform<H> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.placement.hit(&self.obj, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        Some(
+            self.placement
+                .bounding_box(self.obj.bounding_box(time0, time1)?),
+        )
+    }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.placement.pdf_value(&self.obj, r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.placement.random_direction(&self.obj, origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.placement.hit_surface(&self.obj, r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.placement.transmittance(&self.obj, r, t_min, t_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{box3d::Box3D, material::DiffuseLambertian, sphere::Sphere, util, vec3::Color};
+
+    fn unit_sphere() -> Sphere {
+        Sphere::stationary(
+            Point3::zero(),
+            1.0,
+            Box::new(DiffuseLambertian::new(Color::one())),
+        )
+    }
+
+    fn unit_box() -> Box3D {
+        Box3D::new(
+            Point3::zero(),
+            Point3::one(),
+            Box::new(DiffuseLambertian::new(Color::one())),
+        )
+    }
+
+    #[test]
+    fn matches_nested_translate_and_rotate() {
+        let offset = Vec3::new(2.0, 0.5, -1.0);
+        let nested = Translate::new(offset, RotateY::new(30.0, unit_box()));
+        let transform = Transform::new(unit_box())
+            .rotate(Vec3::new(0.0, 1.0, 0.0), 30.0)
+            .translate(offset);
+        util::with_seed(3, || {
+            for _ in 0..100 {
+                let r = Ray::new(Point3::random(-5.0, 5.0), Vec3::random_unit_vector(), None);
+                let a = nested.hit(r, 0.001, f64::INFINITY);
+                let b = transform.hit(r, 0.001, f64::INFINITY);
+                assert_eq!(a.is_some(), b.is_some());
+                if let (Some(a), Some(b)) = (a, b) {
+                    assert!((a.t - b.t).abs() < 1e-9);
+                    assert!((a.normal - b.normal).length() < 1e-9);
+                    assert_eq!(a.front_face, b.front_face);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn scaled_normals_stay_perpendicular_to_the_surface() {
+        // an ellipsoid x² + (y/3)² + z² = 1, whose gradient (x, y/9, z) is normal to it
+        let ellipsoid = Transform::new(unit_sphere())
+            .scale(Vec3::new(1.0, 3.0, 1.0))
+            .rotate(Vec3::new(1.0, 1.0, 0.0), 45.0);
+        let rotation = Matrix4::rotation(Vec3::new(1.0, 1.0, 0.0), -45.0);
+        util::with_seed(4, || {
+            for _ in 0..100 {
+                let origin = 10.0 * Vec3::random_unit_vector();
+                let r = Ray::new(origin, -origin, None);
+                let rec = ellipsoid.hit(r, 0.001, f64::INFINITY).unwrap();
+                let p = rotation.transform_vector(rec.p);
+                let expected = rotation
+                    .transpose()
+                    .transform_vector(Vec3::new(p.x, p.y / 9.0, p.z))
+                    .to_unit();
+                assert!((rec.normal - expected).length() < 1e-9);
+                assert!(rec.front_face);
+            }
+        });
+    }
+
+    #[test]
+    fn bounding_box_contains_the_transformed_object() {
+        let transform = Transform::new(unit_box())
+            .scale(Vec3::new(2.0, -1.0, 0.5))
+            .rotate_quaternion(Quaternion {
+                w: 1.0,
+                x: 0.3,
+                y: -0.2,
+                z: 0.5,
+            })
+            .translate(Vec3::new(10.0, 0.0, 0.0));
+        let bbox = transform.bounding_box(0.0, 0.0).unwrap();
+        for corner in 0..8 {
+            let pick = |axis: usize| ((corner >> axis) & 1) as f64;
+            let p = transform
+                .matrix()
+                .transform_point(Point3::new(pick(0), pick(1), pick(2)));
+            for axis in 0..3 {
+                assert!(bbox.min()[axis] - 1e-9 <= p[axis] && p[axis] <= bbox.max()[axis] + 1e-9);
+            }
+        }
+        assert!(bbox.min().x > 7.0);
+    }
+
+    #[test]
+    fn sampling_density_of_scaled_lights_integrates_to_one() {
+        let light = Transform::new(unit_sphere())
+            .scale(Vec3::new(1.0, 3.0, 0.5))
+            .translate(Vec3::new(0.0, 0.0, -6.0));
+        let samples = 200_000;
+        let integral = util::with_seed(5, || {
+            (0..samples)
+                .map(|_| {
+                    light.pdf_value(Ray::new(Point3::zero(), Vec3::random_unit_vector(), None))
+                })
+                .sum::<f64>()
+        }) * 4.0
+            * std::f64::consts::PI
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.03, "integral is {}", integral);
+
+        // and the directions it picks do point at it
+        util::with_seed(6, || {
+            for _ in 0..100 {
+                let direction = light.random_direction(Point3::zero(), 0.0);
+                assert!(light
+                    .hit(
+                        Ray::new(Point3::zero(), direction, None),
+                        0.001,
+                        f64::INFINITY
+                    )
+                    .is_some());
+            }
+        });
+    }
 }
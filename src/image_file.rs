@@ -0,0 +1,953 @@
+//! Saving rendered images, either tone mapped to 8 bit PNG or as linear high dynamic range data
+//! (OpenEXR, PFM or Radiance HDR) for compositing. The format is picked from the file extension.
+//!
+//! AOV layers are saved as extra layers of OpenEXR files; for the other formats, each layer gets
+//! its own PFM file next to the image (see [`aov_path`]).
+//!
+//! The same formats can be loaded back with [`load_image`], e.g. for environment maps, though
+//! only the kinds of OpenEXR file that this module writes are understood.
+
+use std::{
+    fs::File,
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use rgb::RGB;
+
+use crate::{aov::AovLayer, color::srgb_to_linear, tone_map::ToneMapping, HdrImage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8 bit sRGB, after tone mapping
+    Png,
+    /// OpenEXR, uncompressed scanlines of half or single precision floats
+    Exr,
+    /// Portable float map: uncompressed single precision RGB
+    Pfm,
+    /// Radiance RGBE, with a shared 8 bit exponent per pixel
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Pick the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Self, ImageFileError> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("exr") => Ok(ImageFormat::Exr),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            _ => Err(ImageFileError::UnknownFormat(path.to_owned())),
+        }
+    }
+}
+
+/// How precisely to store each channel of an OpenEXR file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ExrPrecision {
+    /// 16 bit floats, which is plenty for color and half the size
+    Half,
+    /// 32 bit floats
+    #[default]
+    Float,
+}
+
+#[derive(Debug)]
+pub enum ImageFileError {
+    /// The file extension isn't one of png, exr, pfm or hdr
+    UnknownFormat(PathBuf),
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// Loading failed, either because the file couldn't be read or because it isn't a valid
+    /// image (an error of kind [`ErrorKind::InvalidData`])
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Png {
+        path: PathBuf,
+        error: lodepng::Error,
+    },
+}
+
+impl std::fmt::Display for ImageFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFileError::UnknownFormat(path) => write!(
+                f,
+                "don't know the image format of {}, expected a .png, .exr, .pfm or .hdr extension",
+                path.display()
+            ),
+            ImageFileError::Io { path, error } => {
+                write!(f, "could not write {}: {}", path.display(), error)
+            }
+            ImageFileError::Read { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ImageFileError::Png { path, error } => {
+                write!(f, "could not write {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageFileError {}
+
+/// One channel of an image, e.g. the red channel of the beauty pass or the depth of an AOV pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Full channel name, like `R` or `albedo.R` for a channel in a layer
+    pub name: String,
+    /// One value per pixel, row by row starting from the top left corner
+    pub values: Vec<f32>,
+    /// The values are whole number IDs, which are stored as 32 bit unsigned integers whatever the
+    /// precision so that they stay exact
+    pub ids: bool,
+}
+
+impl Channel {
+    /// The red, green and blue channels of `image`, in the layer called `layer` (if any).
+    pub fn rgb(image: &HdrImage, layer: Option<&str>) -> Vec<Channel> {
+        let name = |c: &str| match layer {
+            Some(layer) => format!("{}.{}", layer, c),
+            None => c.to_owned(),
+        };
+        vec![
+            Channel {
+                name: name("R"),
+                values: image.pixels.iter().map(|p| p.r).collect(),
+                ids: false,
+            },
+            Channel {
+                name: name("G"),
+                values: image.pixels.iter().map(|p| p.g).collect(),
+                ids: false,
+            },
+            Channel {
+                name: name("B"),
+                values: image.pixels.iter().map(|p| p.b).collect(),
+                ids: false,
+            },
+        ]
+    }
+
+    /// The channels of an AOV layer, named like `albedo.R`.
+    pub fn aov(layer: &AovLayer) -> Vec<Channel> {
+        let channel_count = layer.channels.len();
+        layer
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(c, channel)| Channel {
+                name: format!("{}.{}", layer.name, channel),
+                values: layer
+                    .values
+                    .iter()
+                    .skip(c)
+                    .step_by(channel_count)
+                    .copied()
+                    .collect(),
+                ids: layer.ids,
+            })
+            .collect()
+    }
+}
+
+/// Where the AOV layer called `layer` of an image saved to `path` goes, when the image's format
+/// can't hold layers: e.g. `out/render.albedo.pfm` for `out/render.png`.
+pub fn aov_path(path: &Path, layer: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.pfm", stem, layer))
+}
+
+/// Save `image` to `path` in the format its extension asks for, tone mapping it with
+/// `tone_mapping` first if the format can't hold high dynamic range data.
+///
+/// Any AOV layers go in the same file if it's OpenEXR, or each in their own PFM file otherwise.
+pub fn save_image<P: AsRef<Path>>(
+    path: P,
+    image: &HdrImage,
+    tone_mapping: &ToneMapping,
+    exr_precision: ExrPrecision,
+) -> Result<(), ImageFileError> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    match format {
+        ImageFormat::Png => image
+            .tone_map(tone_mapping)
+            .save_png(path)
+            .map_err(|error| ImageFileError::Png {
+                path: path.to_owned(),
+                error,
+            })?,
+        ImageFormat::Exr => {
+            let mut channels = Channel::rgb(image, None);
+            channels.extend(image.aovs.iter().flat_map(Channel::aov));
+            write_exr(path, image.width, image.height, &channels, exr_precision)?
+        }
+        ImageFormat::Pfm => write_file(path, |w| {
+            let values = image
+                .pixels
+                .iter()
+                .flat_map(|p| [p.r, p.g, p.b])
+                .collect::<Vec<_>>();
+            write_pfm(w, image.width, image.height, 3, &values)
+        })?,
+        ImageFormat::Hdr => write_file(path, |w| write_radiance_hdr(w, image))?,
+    }
+
+    if format != ImageFormat::Exr {
+        for layer in &image.aovs {
+            write_file(&aov_path(path, &layer.name), |w| {
+                write_pfm(
+                    w,
+                    image.width,
+                    image.height,
+                    layer.channels.len(),
+                    &layer.values,
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `channels` (which must each have `width * height` values) to an OpenEXR file.
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+    precision: ExrPrecision,
+) -> Result<(), ImageFileError> {
+    write_file(path.as_ref(), |w| {
+        encode_exr(w, width, height, channels, precision)
+    })
+}
+
+fn write_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> Result<(), ImageFileError> {
+    let io_error = |error| ImageFileError::Io {
+        path: path.to_owned(),
+        error,
+    };
+    let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+    write(&mut writer).map_err(io_error)?;
+    writer.flush().map_err(io_error)
+}
+
+fn write_attribute(
+    w: &mut impl Write,
+    name: &str,
+    kind: &str,
+    value: &[u8],
+) -> std::io::Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(kind.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(&(value.len() as i32).to_le_bytes())?;
+    w.write_all(value)
+}
+
+/// Write a single part, uncompressed scanline OpenEXR image.
+fn encode_exr(
+    w: &mut impl Write,
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+    precision: ExrPrecision,
+) -> std::io::Result<()> {
+    for channel in channels {
+        assert_eq!(channel.values.len(), width * height, "{}", channel.name);
+    }
+    // readers expect channels in alphabetical order, both in the header and in the pixel data
+    let mut channels = channels.iter().collect::<Vec<_>>();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    // pixel type (0 is UINT, 1 HALF and 2 FLOAT) and its size in bytes
+    let pixel_type = |channel: &Channel| -> (i32, usize) {
+        match precision {
+            _ if channel.ids => (0, 4),
+            ExrPrecision::Half => (1, 2),
+            ExrPrecision::Float => (2, 4),
+        }
+    };
+    let long_names = channels.iter().any(|c| c.name.len() > 31);
+
+    // the header is built up front, since the offset table after it needs to know its size
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630i32.to_le_bytes());
+    header.extend_from_slice(&(2i32 | if long_names { 0x400 } else { 0 }).to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type(channel).0.to_le_bytes());
+        // pLinear and 3 reserved bytes, then x and y sampling
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list)?;
+    write_attribute(&mut header, "compression", "compression", &[0])?;
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    header.push(0);
+    w.write_all(&header)?;
+
+    // each scanline is its own block, so the offset table has one entry per line
+    let line_data_size = width * channels.iter().map(|c| pixel_type(c).1).sum::<usize>();
+    let block_size = 4 + 4 + line_data_size;
+    let first_block = (header.len() + 8 * height) as u64;
+    for y in 0..height {
+        w.write_all(&(first_block + (y * block_size) as u64).to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_data_size as i32).to_le_bytes())?;
+        for channel in &channels {
+            for &v in &channel.values[y * width..(y + 1) * width] {
+                match pixel_type(channel).0 {
+                    0 => w.write_all(&(v as u32).to_le_bytes())?,
+                    1 => w.write_all(&half::f16::from_f32(v).to_le_bytes())?,
+                    _ => w.write_all(&v.to_le_bytes())?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a little endian portable float map, which stores rows from the bottom up.
+///
+/// `values` has `channel_count` (1 for greyscale or 3 for color) values per pixel, row by row
+/// starting from the top left corner.
+fn write_pfm(
+    w: &mut impl Write,
+    width: usize,
+    height: usize,
+    channel_count: usize,
+    values: &[f32],
+) -> std::io::Result<()> {
+    let kind = if channel_count == 1 { "Pf" } else { "PF" };
+    write!(w, "{}\n{} {}\n-1.0\n", kind, width, height)?;
+    for row in values.chunks((width * channel_count).max(1)).rev() {
+        for v in row {
+            w.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a Radiance HDR file with flat (not run length encoded) scanlines.
+fn write_radiance_hdr(w: &mut impl Write, image: &HdrImage) -> std::io::Result<()> {
+    write!(
+        w,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        image.height, image.width
+    )?;
+    for p in &image.pixels {
+        w.write_all(&rgbe(p.r, p.g, p.b))?;
+    }
+    Ok(())
+}
+
+/// Store a color as 8 bit mantissas sharing the exponent of the brightest channel.
+fn rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 {
+        return [0; 4];
+    }
+    // brightest = mantissa * 2^exponent, with mantissa in [0.5, 1)
+    let exponent = brightest.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    let encode = |v: f32| (v.max(0.0) * scale).min(255.0) as u8;
+    [encode(r), encode(g), encode(b), (exponent + 128) as u8]
+}
+
+/// Load the image at `path`, in the format its extension says. PNG images are converted from sRGB
+/// to linear values. Images without any pixels are an error.
+pub fn load_image<P: AsRef<Path>>(path: P) -> Result<HdrImage, ImageFileError> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    let read_error = |error| ImageFileError::Read {
+        path: path.to_owned(),
+        error,
+    };
+    let bytes = std::fs::read(path).map_err(read_error)?;
+    match format {
+        ImageFormat::Png => decode_png(&bytes),
+        ImageFormat::Exr => decode_exr(&bytes),
+        ImageFormat::Pfm => decode_pfm(&bytes),
+        ImageFormat::Hdr => decode_radiance_hdr(&bytes),
+    }
+    .and_then(|image| {
+        if image.width == 0 || image.height == 0 {
+            Err(invalid_data("the image is empty"))
+        } else {
+            Ok(image)
+        }
+    })
+    .map_err(read_error)
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// Reads through the bytes of a file, failing with an [`ErrorKind::InvalidData`] error if it ends
+/// too soon.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> std::io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("the file ends too soon"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    /// The bytes up to the next `end` byte, skipping over the `end` byte itself.
+    fn until(&mut self, end: u8) -> std::io::Result<&'a [u8]> {
+        let length = self.bytes[self.position..]
+            .iter()
+            .position(|&b| b == end)
+            .ok_or_else(|| invalid_data("the file ends too soon"))?;
+        let taken = self.take(length)?;
+        self.position += 1;
+        Ok(taken)
+    }
+
+    /// The next line of a text header, without its line break.
+    fn line(&mut self) -> std::io::Result<&'a str> {
+        std::str::from_utf8(self.until(b'\n')?).map_err(|_| invalid_data("invalid header"))
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> std::io::Result<HdrImage> {
+    let bitmap = lodepng::decode24(bytes).map_err(|error| invalid_data(error.to_string()))?;
+    let linear = |v: u8| srgb_to_linear(v as f64 / 255.0) as f32;
+    Ok(HdrImage {
+        width: bitmap.width,
+        height: bitmap.height,
+        pixels: bitmap
+            .buffer
+            .iter()
+            .map(|p| RGB::new(linear(p.r), linear(p.g), linear(p.b)))
+            .collect(),
+        aovs: Vec::new(),
+    })
+}
+
+/// Decode a single part, uncompressed scanline OpenEXR image, taking its color from the `R`, `G`
+/// and `B` channels (or a greyscale `Y` channel) outside of any layer.
+fn decode_exr(bytes: &[u8]) -> std::io::Result<HdrImage> {
+    let mut r = Reader::new(bytes);
+    if i32::from_le_bytes(r.array()?) != 20000630 {
+        return Err(invalid_data("not an OpenEXR file"));
+    }
+    let flags = i32::from_le_bytes(r.array()?);
+    // tiles, deep data and multiple parts are flagged by bits 9, 11 and 12
+    if flags & 0xff != 2 || flags & 0x1a00 != 0 {
+        return Err(invalid_data(
+            "only single part, scanline OpenEXR files are supported",
+        ));
+    }
+
+    // (name, pixel type) of each channel, in the order that their values are stored
+    let mut channels = Vec::new();
+    let mut window = None;
+    loop {
+        let name = r.until(0)?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = r.until(0)?;
+        let size = i32::from_le_bytes(r.array()?);
+        let value = r.take(usize::try_from(size).map_err(|_| invalid_data("invalid header"))?)?;
+        match name {
+            b"channels" => {
+                let mut list = Reader::new(value);
+                loop {
+                    let name = list.until(0)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let pixel_type = i32::from_le_bytes(list.array()?);
+                    if !(0..=2).contains(&pixel_type) {
+                        return Err(invalid_data("unknown pixel type"));
+                    }
+                    let _linear_and_reserved: [u8; 4] = list.array()?;
+                    let sampling: [u8; 8] = list.array()?;
+                    if sampling != [1, 0, 0, 0, 1, 0, 0, 0] {
+                        return Err(invalid_data("subsampled channels are not supported"));
+                    }
+                    channels.push((String::from_utf8_lossy(name).into_owned(), pixel_type));
+                }
+            }
+            b"compression" if value != [0] => {
+                return Err(invalid_data(
+                    "compressed OpenEXR files are not supported, save it uncompressed",
+                ))
+            }
+            b"dataWindow" => {
+                let mut values = Reader::new(value);
+                let mut coordinate = || values.array().map(i32::from_le_bytes);
+                window = Some([coordinate()?, coordinate()?, coordinate()?, coordinate()?]);
+            }
+            _ => (),
+        }
+    }
+
+    let [x_min, y_min, x_max, y_max] = window.ok_or_else(|| invalid_data("no data window"))?;
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid_data("the image is empty"));
+    }
+    let (width, height) = ((x_max - x_min + 1) as usize, (y_max - y_min + 1) as usize);
+    let channel_index = |name: &str| channels.iter().position(|(n, _)| n == name);
+    let color_channels = match (channel_index("R"), channel_index("G"), channel_index("B")) {
+        (Some(r), Some(g), Some(b)) => [r, g, b],
+        _ => match channel_index("Y") {
+            Some(y) => [y; 3],
+            None => return Err(invalid_data("no R, G and B or Y channels")),
+        },
+    };
+    let value_size = |pixel_type: i32| if pixel_type == 1 { 2 } else { 4 };
+    let line_size = width * channels.iter().map(|&(_, t)| value_size(t)).sum::<usize>();
+
+    let mut image = HdrImage::new(width, height);
+    let offsets = (0..height)
+        .map(|_| r.array().map(u64::from_le_bytes))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    for offset in offsets {
+        let mut block = Reader::new(bytes);
+        block.position = offset as usize;
+        let y = (i32::from_le_bytes(block.array()?) - y_min) as usize;
+        let size = i32::from_le_bytes(block.array()?);
+        if y >= height || size as usize != line_size {
+            return Err(invalid_data("invalid scanline"));
+        }
+        let mut values = vec![[0.0; 3]; width];
+        for (index, &(_, pixel_type)) in channels.iter().enumerate() {
+            for value in values.iter_mut() {
+                let v = match pixel_type {
+                    0 => u32::from_le_bytes(block.array()?) as f32,
+                    1 => half::f16::from_le_bytes(block.array()?).to_f32(),
+                    _ => f32::from_le_bytes(block.array()?),
+                };
+                for (component, &channel) in color_channels.iter().enumerate() {
+                    if channel == index {
+                        value[component] = v;
+                    }
+                }
+            }
+        }
+        for (pixel, [r, g, b]) in image.pixels[y * width..(y + 1) * width]
+            .iter_mut()
+            .zip(values)
+        {
+            *pixel = RGB::new(r, g, b);
+        }
+    }
+    Ok(image)
+}
+
+/// Decode a portable float map, in either byte order and with one or three channels.
+fn decode_pfm(bytes: &[u8]) -> std::io::Result<HdrImage> {
+    let mut r = Reader::new(bytes);
+    let channel_count = match r.line()?.trim() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("not a PFM file")),
+    };
+    let size = r.line()?;
+    let mut size = size.split_whitespace().map(str::parse::<usize>);
+    let (width, height) = match (size.next(), size.next()) {
+        (Some(Ok(width)), Some(Ok(height))) => (width, height),
+        _ => return Err(invalid_data("invalid image size")),
+    };
+    let scale = r
+        .line()?
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| invalid_data("invalid scale"))?;
+
+    let mut image = HdrImage::new(width, height);
+    for y in (0..height).rev() {
+        for pixel in &mut image.pixels[y * width..(y + 1) * width] {
+            let mut value = || {
+                let bytes = r.array()?;
+                Ok::<_, std::io::Error>(if scale < 0.0 {
+                    f32::from_le_bytes(bytes)
+                } else {
+                    f32::from_be_bytes(bytes)
+                })
+            };
+            *pixel = if channel_count == 1 {
+                let v = value()?;
+                RGB::new(v, v, v)
+            } else {
+                RGB::new(value()?, value()?, value()?)
+            };
+        }
+    }
+    Ok(image)
+}
+
+/// Decode a Radiance HDR file with flat or run length encoded scanlines, stored top to bottom.
+fn decode_radiance_hdr(bytes: &[u8]) -> std::io::Result<HdrImage> {
+    let mut r = Reader::new(bytes);
+    if !r.line()?.starts_with("#?") {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+    loop {
+        let line = r.line()?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data(format!("unsupported {}", line)));
+        }
+    }
+    let size = r.line()?.split_whitespace().collect::<Vec<_>>();
+    let (width, height) = match size[..] {
+        ["-Y", height, "+X", width] => match (width.parse(), height.parse()) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid_data("invalid image size")),
+        },
+        _ => return Err(invalid_data("only -Y +X image orientation is supported")),
+    };
+    let mut image = HdrImage::new(width, height);
+    let mut line = vec![[0u8; 4]; width];
+    for y in 0..height {
+        let start = r.array::<4>()?;
+        let encoded = start[0] == 2
+            && start[1] == 2
+            && (8..0x8000).contains(&width)
+            && u16::from_be_bytes([start[2], start[3]]) as usize == width;
+        if encoded {
+            // each component is stored separately, as runs of one value or literal values
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = r.byte()? as usize;
+                    let (count, run) = if count > 128 {
+                        (count - 128, true)
+                    } else {
+                        (count, false)
+                    };
+                    if count == 0 || x + count > width {
+                        return Err(invalid_data("invalid run length encoding"));
+                    }
+                    let run_value = if run { r.byte()? } else { 0 };
+                    for pixel in &mut line[x..x + count] {
+                        pixel[component] = if run { run_value } else { r.byte()? };
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            line[0] = start;
+            for pixel in &mut line[1..] {
+                *pixel = r.array()?;
+            }
+        }
+        for (pixel, &[rm, gm, bm, e]) in image.pixels[y * width..(y + 1) * width]
+            .iter_mut()
+            .zip(&line)
+        {
+            *pixel = if e == 0 {
+                RGB::new(0.0, 0.0, 0.0)
+            } else {
+                let scale = 2f32.powi(e as i32 - 136);
+                let decode = |m: u8| (m as f32 + 0.5) * scale;
+                RGB::new(decode(rm), decode(gm), decode(bm))
+            };
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+    use rgb::RGB;
+
+    fn test_image() -> HdrImage {
+        HdrImage {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                RGB::new(0.0, 0.5, 1.0),
+                RGB::new(2.0, 4.0, 8.0),
+                RGB::new(100.0, 0.25, 0.125),
+                RGB::new(1e-3, 1e3, 0.0),
+            ],
+            aovs: Vec::new(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-test-{}-{}", std::process::id(), name))
+    }
+
+    fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn format_comes_from_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("out/a.PNG")).unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.exr")).unwrap(),
+            ImageFormat::Exr
+        );
+        assert!(ImageFormat::from_path(Path::new("a.jpg")).is_err());
+        assert!(ImageFormat::from_path(Path::new("a")).is_err());
+    }
+
+    #[test]
+    fn pfm_stores_rows_bottom_up() {
+        let path = temp_path("image.pfm");
+        save_image(
+            &path,
+            &test_image(),
+            &Default::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 4 * 3 * 4);
+        // the first pixel in the file is the bottom left one
+        assert_eq!(read_f32(&bytes, header.len()), 100.0);
+        assert_eq!(read_f32(&bytes, header.len() + 4 * 3 * 3 + 4), 4.0);
+    }
+
+    #[test]
+    fn exr_scanlines_can_be_found_from_offset_table() {
+        for (precision, bytes_per_value) in [(ExrPrecision::Float, 4), (ExrPrecision::Half, 2)] {
+            let path = temp_path("image.exr");
+            save_image(&path, &test_image(), &Default::default(), precision).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(&bytes[..4], &20000630i32.to_le_bytes());
+            let block_size = 8 + 2 * 3 * bytes_per_value;
+            let offset_table = bytes.len() - 2 * block_size - 2 * 8;
+            let second_line = u64::from_le_bytes(
+                bytes[offset_table + 8..offset_table + 16]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            assert_eq!(second_line, bytes.len() - block_size);
+            assert_eq!(&bytes[second_line..second_line + 4], &1i32.to_le_bytes());
+
+            // channels are stored B, G, R, with each channel's values for the line together
+            let value = |channel: usize, x: usize| {
+                let offset = second_line + 8 + (channel * 2 + x) * bytes_per_value;
+                match precision {
+                    ExrPrecision::Float => read_f32(&bytes, offset),
+                    ExrPrecision::Half => {
+                        half::f16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+                            .to_f32()
+                    }
+                }
+            };
+            assert_eq!(value(2, 0), 100.0);
+            assert_eq!(value(1, 1), 1000.0);
+            assert_eq!(value(0, 0), 0.125);
+        }
+    }
+
+    #[test]
+    fn aov_layers_get_their_own_files_unless_saving_exr() {
+        let mut image = test_image();
+        image.aovs.push(AovLayer {
+            aov: Aov::ObjectId,
+            name: "object-id".to_owned(),
+            channels: &["id"],
+            ids: true,
+            values: vec![1.0, 2.0, 3.0, 16_000_001.0],
+        });
+
+        let path = temp_path("layered.png");
+        save_image(&path, &image, &Default::default(), Default::default()).unwrap();
+        let layer_path = aov_path(&path, "object-id");
+        let bytes = std::fs::read(&layer_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&layer_path).unwrap();
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(read_f32(&bytes, header.len()), 3.0);
+
+        // in half precision EXR files, IDs are still stored exactly as integers
+        let path = temp_path("layered.exr");
+        save_image(&path, &image, &Default::default(), ExrPrecision::Half).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let block_size = 8 + 2 * (3 * 2 + 4);
+        let last_id = &bytes[bytes.len() - block_size + 8 + 2 * 2 * 3 + 4..][..4];
+        assert_eq!(u32::from_le_bytes(last_id.try_into().unwrap()), 16_000_001);
+        assert!(!layer_path.exists());
+    }
+
+    #[test]
+    fn saved_images_load_back() {
+        let original = test_image();
+        for (extension, tolerance) in [("exr", 0.0), ("pfm", 0.0), ("hdr", 1.0 / 128.0)] {
+            let path = temp_path(&format!("round-trip.{}", extension));
+            save_image(&path, &original, &Default::default(), Default::default()).unwrap();
+            let loaded = load_image(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!((loaded.width, loaded.height), (2, 2));
+            for (a, b) in original.pixels.iter().zip(&loaded.pixels) {
+                let brightest = a.r.max(a.g).max(a.b);
+                for (a, b) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                    assert!(
+                        (a - b).abs() <= brightest * tolerance,
+                        "{}: {} came back as {}",
+                        extension,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn radiance_hdr_run_length_encoding_is_decoded() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // red: a run of 8 halves; green: literal values; blue: two runs; exponent: one run
+        bytes.extend_from_slice(&[128 + 8, 128]);
+        bytes.extend_from_slice(&[8, 0, 32, 64, 96, 128, 160, 192, 224]);
+        bytes.extend_from_slice(&[128 + 3, 0, 128 + 5, 255]);
+        bytes.extend_from_slice(&[128 + 8, 129]);
+
+        let image = decode_radiance_hdr(&bytes).unwrap();
+        assert_eq!(image.width, 8);
+        assert!((image.pixels[0].r - 1.0).abs() < 0.01);
+        assert!((image.pixels[5].g - 1.25).abs() < 0.01);
+        assert!(image.pixels[2].b < 0.01);
+        assert!((image.pixels[3].b - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn empty_images_are_refused() {
+        let files = [
+            ("empty.pfm", &b"PF\n0 0\n-1.0\n"[..]),
+            ("empty.hdr", &b"#?RADIANCE\n\n-Y 0 +X 4\n"[..]),
+        ];
+        for (name, bytes) in files {
+            let path = temp_path(name);
+            std::fs::write(&path, bytes).unwrap();
+            let result = load_image(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(
+                matches!(result, Err(ImageFileError::Read { ref error, .. }) if error.kind() == ErrorKind::InvalidData),
+                "{} loaded as {:?}",
+                name,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn compressed_exr_is_refused() {
+        let path = temp_path("compressed.exr");
+        save_image(
+            &path,
+            &test_image(),
+            &Default::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let attribute = b"compression\0compression\0\x01\0\0\0";
+        let start = bytes
+            .windows(attribute.len())
+            .position(|w| w == attribute)
+            .unwrap();
+        bytes[start + attribute.len()] = 3;
+
+        let error = decode_exr(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rgbe_keeps_brightest_channel_precise() {
+        for (r, g, b) in [(1.0, 0.5, 0.25), (1000.0, 3.0, 0.0), (0.01, 0.02, 0.005)] {
+            let [rm, gm, bm, e] = rgbe(r, g, b);
+            let scale = 2f32.powi(e as i32 - 128) / 256.0;
+            for (original, mantissa) in [(r, rm), (g, gm), (b, bm)] {
+                let decoded = (mantissa as f32 + 0.5) * scale;
+                assert!(
+                    (decoded - original).abs() <= r.max(g).max(b) / 128.0,
+                    "{} came back as {}",
+                    original,
+                    decoded
+                );
+            }
+        }
+        assert_eq!(rgbe(0.0, 0.0, 0.0), [0; 4]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Geometry instancing: a [`Prototype`] is built once, with its own bounding volume hierarchy, and
+//! then shared between any number of [`Instance`]s, each placed with its own transform and
+//! optionally drawn with its own material.
+//!
+//! Prototypes can contain instances of other prototypes, so memory use grows with the number of
+//! distinct pieces rather than the number of objects that end up in the render: a million
+//! spheres can be a hundred instances of a prototype that's a hundred instances of a prototype
+//! that's a hundred spheres.
+
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh_node::BvhNode,
+    hittable::{HitRecord, Hittable, Transform},
+    material::Material,
+    matrix::Quaternion,
+    ray::Ray,
+    vec3::{Point3, Vec3},
+};
+
+/// A set of objects that can be placed many times without being copied.
+#[derive(Debug, Clone)]
+pub struct Prototype {
+    objects: Arc<BvhNode>,
+}
+
+impl Prototype {
+    /// Panics if `objects` is empty or any of them don't have a bounding box.
+    pub fn new(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> Self {
+        Self {
+            objects: Arc::new(BvhNode::new(objects, time0, time1)),
+        }
+    }
+
+    /// A copy of the prototype, where it was built and with its own materials.
+    pub fn instance(&self) -> Instance {
+        Instance {
+            placement: Transform::new(self.objects.clone()),
+            material: None,
+        }
+    }
+}
+
+/// One placement of a [`Prototype`], which is moved around with the same steps as a
+/// [`Transform`].
+#[derive(Debug, Clone)]
+pub struct Instance {
+    placement: Transform<Arc<BvhNode>>,
+    /// Used for everything in the prototype instead of its own materials
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    /// Then move the instance by `offset`.
+    pub fn translate(self, offset: Vec3) -> Self {
+        Self {
+            placement: self.placement.translate(offset),
+            ..self
+        }
+    }
+
+    /// Then rotate the instance `angle` degrees around `axis` (through the origin).
+    pub fn rotate(self, axis: Vec3, angle: f64) -> Self {
+        Self {
+            placement: self.placement.rotate(axis, angle),
+            ..self
+        }
+    }
+
+    /// Then rotate the instance by `rotation` (around the origin).
+    pub fn rotate_quaternion(self, rotation: Quaternion) -> Self {
+        Self {
+            placement: self.placement.rotate_quaternion(rotation),
+            ..self
+        }
+    }
+
+    /// Then scale the instance by `factors` along each axis (from the origin).
+    pub fn scale(self, factors: Vec3) -> Self {
+        Self {
+            placement: self.placement.scale(factors),
+            ..self
+        }
+    }
+
+    /// Draw everything in the instance with `material`. The material can be shared with other
+    /// instances; instances nested inside this one are overridden too.
+    pub fn material(self, material: Arc<dyn Material>) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+}
+
+impl Instance {
+    fn with_material<'a>(&'a self, rec: HitRecord<'a>) -> HitRecord<'a> {
+        match &self.material {
+            Some(material) => HitRecord {
+                mat_ptr: material.as_ref(),
+                ..rec
+            },
+            None => rec,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let rec = self.placement.hit(r, t_min, t_max)?;
+        Some(self.with_material(rec))
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.placement.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        self.placement.pdf_value(r)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        self.placement.random_direction(origin, time)
+    }
+
+    fn hit_surface(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let rec = self.placement.hit_surface(r, t_min, t_max)?;
+        Some(self.with_material(rec))
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.placement.transmittance(r, t_min, t_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{DiffuseLambertian, Metal},
+        sphere::Sphere,
+        vec3::Color,
+    };
+
+    /// `count` copies of `prototype` in a row along x, `spacing` apart.
+    fn row(prototype: &Prototype, count: usize, spacing: f64) -> Prototype {
+        let objects = (0..count)
+            .map(|i| {
+                Box::new(
+                    prototype
+                        .instance()
+                        .translate(Vec3::new(i as f64 * spacing, 0.0, 0.0)),
+                ) as Box<dyn Hittable>
+            })
+            .collect();
+        Prototype::new(objects, 0.0, 0.0)
+    }
+
+    #[test]
+    fn nested_instances_make_a_million_spheres_from_one() {
+        let sphere = Prototype::new(
+            vec![Box::new(Sphere::stationary(
+                Point3::zero(),
+                0.4,
+                Box::new(DiffuseLambertian::new(Color::one())),
+            ))],
+            0.0,
+            0.0,
+        );
+        let line = row(&sphere, 1000, 1.0);
+        // turn rows of spheres along x into columns along z
+        let square = Prototype::new(
+            (0..1000)
+                .map(|i| {
+                    Box::new(
+                        line.instance()
+                            .rotate(Vec3::new(0.0, 1.0, 0.0), -90.0)
+                            .translate(Vec3::new(i as f64, 0.0, 0.0)),
+                    ) as Box<dyn Hittable>
+                })
+                .collect(),
+            0.0,
+            0.0,
+        );
+        let square = square.instance();
+        let bbox = square.bounding_box(0.0, 0.0).unwrap();
+        assert!((bbox.max() - Point3::new(999.4, 0.4, 999.4)).length() < 1e-9);
+
+        // the ray threads between the spheres until it reaches the one at (700, 0, 300)
+        let r = Ray::new(
+            Point3::new(700.0, 5.0, 300.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            None,
+        );
+        let rec = square.hit(r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(700.0, 0.4, 300.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        let r = Ray::new(
+            Point3::new(700.5, 5.0, 300.5),
+            Vec3::new(0.0, -1.0, 0.0),
+            None,
+        );
+        assert!(square.hit(r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn outermost_material_override_wins() {
+        let lambertian: Arc<dyn Material> = Arc::new(DiffuseLambertian::new(Color::one()));
+        let metal: Arc<dyn Material> = Arc::new(Metal::new(Color::one(), 0.0));
+        let sphere = Prototype::new(
+            vec![Box::new(Sphere::stationary(
+                Point3::zero(),
+                1.0,
+                Box::new(DiffuseLambertian::new(Color::zero())),
+            ))],
+            0.0,
+            0.0,
+        );
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), None);
+        let material_of = |instance: &Instance| {
+            let rec = instance.hit(r, 0.001, f64::INFINITY).unwrap();
+            rec.mat_ptr as *const dyn Material as *const ()
+        };
+
+        let plain = sphere.instance();
+        let inner = sphere.instance().material(lambertian.clone());
+        assert_ne!(material_of(&plain), Arc::as_ptr(&lambertian) as *const ());
+        assert_eq!(material_of(&inner), Arc::as_ptr(&lambertian) as *const ());
+
+        let nested = Prototype::new(vec![Box::new(inner)], 0.0, 0.0);
+        let outer = nested.instance().material(metal.clone());
+        assert_eq!(material_of(&outer), Arc::as_ptr(&metal) as *const ());
+    }
+}
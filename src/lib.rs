@@ -0,0 +1,57 @@
+#![deny(clippy::all)] // make all clippy warnings into errors
+#![allow(clippy::many_single_char_names)]
+
+//! The All Seeing Crab: a raytracer that follows Ray Tracing in One Weekend (and its sequels).
+//!
+//! The usual flow for embedding the tracer is:
+//!
+//! 1. build a [`World`] out of [`hittable::Hittable`] objects (or use one of the built in
+//!    [`scenes::RenderScene`]s),
+//! 2. configure a [`camera::Camera`] from [`camera::CameraSettings`],
+//! 3. call [`render()`] (or [`render_with_progress()`], or [`render_progressive()`] to refine the
+//!    whole image a pass at a time) with a [`RenderConfig`] to get an [`HdrImage`] back,
+//! 4. tone map it with [`HdrImage::tone_map()`] to get a displayable [`ImageBuffer`].
+
+pub mod aabb;
+pub mod aarect;
+pub mod animation;
+pub mod aov;
+pub mod box3d;
+pub mod bvh_node;
+pub mod camera;
+pub mod color;
+pub mod constant_medium;
+pub mod denoise;
+pub mod environment;
+pub mod heterogeneous_medium;
+pub mod hittable;
+pub mod image_file;
+pub mod instance;
+pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod microfacet;
+pub mod obj;
+pub mod onb;
+pub mod perlin;
+pub mod principled;
+pub mod ray;
+mod render;
+pub mod sampler;
+pub mod scene_file;
+pub mod scenes;
+pub mod sky;
+pub mod spectrum;
+pub mod sphere;
+pub mod texture;
+pub mod tone_map;
+mod util;
+pub mod vec3;
+mod world;
+
+pub use render::{
+    ray_color, render, render_line, render_pass, render_progressive, render_with_progress,
+    AccumulationBuffer, AdaptiveSampling, HdrImage, ImageBuffer, RayColorMode, RenderConfig,
+    RenderProgress,
+};
+pub use world::World;
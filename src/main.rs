@@ -1,96 +1,27 @@
 #![deny(clippy::all)] // make all clippy warnings into errors
-#![allow(clippy::many_single_char_names)]
 
-mod aabb;
-mod aarect;
-mod box3d;
-mod bvh_node;
-mod camera;
-mod color;
-mod constant_medium;
-mod hittable;
-mod material;
-mod perlin;
-mod ray;
-mod scenes;
-mod sphere;
-mod texture;
+mod cli;
 mod ui;
-mod util;
-mod vec3;
 
-use camera::CameraSettings;
-use rgb::RGB8;
-use scenes::RenderScene;
-use std::f64::INFINITY;
-
-use crate::{
-    bvh_node::BvhNode,
-    camera::Camera,
-    color::color_as_rgb8,
-    hittable::Hittable,
-    ray::Ray,
-    vec3::{lerp, Color},
+use raytracer::{
+    bvh_node::BvhStats,
+    camera::{Camera, CameraSettings},
+    denoise::{denoise, Denoising},
+    environment::Environment,
+    render_line, render_progressive,
+    sky::Sky,
+    HdrImage, RenderConfig,
 };
+use structopt::StructOpt;
 
-#[derive(Debug)]
-struct World {
-    background: Option<Color>,
-    node: BvhNode,
-}
-
-impl From<BvhNode> for World {
-    fn from(node: BvhNode) -> Self {
-        Self {
-            node,
-            background: Default::default(),
-        }
-    }
-}
-
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-#[serde(default)] // if we add new fields, give them default values when deserializing old state
-struct RenderConfig {
-    image_width: usize,
-    image_height: usize,
-    samples_per_pixel: u32,
-    render_mode: RayColorMode,
-    scene: RenderScene,
-    output_filename: String,
-    display_actual_size: bool,
-}
-
-impl RenderConfig {
-    pub(crate) fn image_pixel_count(&self) -> usize {
-        self.image_width * self.image_height
-    }
-
-    pub(crate) fn aspect_ratio(&self) -> f64 {
-        self.image_width as f64 / self.image_height as f64
-    }
-}
-
-impl Default for RenderConfig {
-    fn default() -> Self {
-        let aspect_ratio = 16.0 / 9.0;
-        let image_width = 400;
-        Self {
-            image_width,
-            image_height: (image_width as f64 / aspect_ratio) as usize,
-            samples_per_pixel: 100,
-            render_mode: { RayColorMode::Material { depth: 50 } },
-            scene: Default::default(),
-            output_filename: "target/output.png".to_owned(),
-            display_actual_size: true,
-        }
-    }
-}
-
+#[allow(clippy::large_enum_variant)] // commands are rare, so their size doesn't matter
 enum RenderCommand {
     Render {
         config: RenderConfig,
         cam_settings: CameraSettings,
     },
+    /// Stop the render in progress, if any
+    Stop,
 }
 
 enum RenderResult {
@@ -100,61 +31,57 @@ enum RenderResult {
     },
     ImageLine {
         line_num: usize,
-        line_pixels: Vec<RGB8>,
+        /// Linear radiance of each pixel in the line, as an image one row high
+        line: HdrImage,
     },
+    /// The whole image so far, when rendering progressively. `total_passes` is the most passes the
+    /// render could take, which drops to `passes_completed` once every pixel has enough samples.
+    Frame {
+        image: HdrImage,
+        /// `image` denoised with the settings the render started with, if denoising was on. This
+        /// is done on the render thread so that the UI doesn't stall on every pass.
+        denoised: Option<(Denoising, HdrImage)>,
+        passes_completed: u32,
+        total_passes: u32,
+    },
+    /// The scene has been built and is about to be rendered
+    Built { bvh_stats: BvhStats },
+    /// The render could not be started, e.g. because the scene file was invalid
+    Failed { message: String },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
-enum RayColorMode {
-    /// shade as single purely matte color
-    BlockColor { color: Color },
-    /// shade by assuming the normal is the color
-    ShadeNormal,
-    /// shade based on distance from camera
-    Depth { max_t: f64 },
-    /// use the assigned materials of each hittable object
-    Material { depth: i32 },
-}
-
-fn ray_color(r: Ray, background: Option<Color>, world: &dyn Hittable, mode: RayColorMode) -> Color {
-    if let RayColorMode::Material { depth } = mode {
-        if depth <= 0 {
-            return Color::zero();
-        }
+fn main() {
+    let options = cli::CliOptions::from_args();
+    if options.headless {
+        std::process::exit(cli::run_headless(options));
     }
 
-    if let Some(rec) = world.hit(r, 0.001, INFINITY) {
-        return match mode {
-            RayColorMode::BlockColor { color } => color,
-            RayColorMode::ShadeNormal => 0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0)),
-            RayColorMode::Depth { max_t } => Color::one() - rec.t / max_t * Color::one(),
-            RayColorMode::Material { depth } => {
-                let emitted = rec.mat_ptr.emitted(rec.u, rec.v, rec.p);
-
-                if let Some((attenuation, scattered)) = rec.mat_ptr.scatter(r, &rec) {
-                    let new_depth = RayColorMode::Material { depth: depth - 1 };
-                    return emitted
-                        + attenuation * ray_color(scattered, background, world, new_depth);
-                } else {
-                    return emitted;
-                }
+    // options given without --headless set up the GUI, rather than being ignored
+    let initial_settings = if std::env::args_os().len() > 1 {
+        match options.gui_settings() {
+            Ok(settings) => Some(settings),
+            Err(e) => {
+                eprintln!("Failed to load scene: {}", e);
+                std::process::exit(cli::EXIT_RENDER_FAILED);
             }
-        };
-    }
-
-    background.unwrap_or_else(|| {
-        let unit_direction = r.direction().to_unit();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        let ground: Color = Color::new(1.0, 1.0, 1.0);
-        let sky: Color = Color::new(0.5, 0.7, 1.0);
-        lerp(t, ground, sky)
-    })
-}
+        }
+    } else {
+        None
+    };
 
-fn main() {
     let (command_tx, command_rx) = flume::unbounded::<RenderCommand>();
     let (result_tx, result_rx) = flume::unbounded::<RenderResult>();
+    spawn_render_thread(command_rx, result_tx);
 
+    let app = ui::TemplateApp::new(command_tx, result_rx, initial_settings);
+    let native_options = eframe::NativeOptions::default();
+    eframe::run_native(Box::new(app), native_options);
+}
+
+fn spawn_render_thread(
+    render_command_rx: flume::Receiver<RenderCommand>,
+    render_result_tx: flume::Sender<RenderResult>,
+) {
     // start a background thread to handle rendering, but drop its handle so we don't wait for it
     // to finish
     drop(std::thread::spawn(move || {
@@ -163,13 +90,9 @@ fn main() {
             .build()
             .expect("should be able to build threadpool")
             .install(|| {
-                run_render_loop(command_rx, result_tx);
+                run_render_loop(render_command_rx, render_result_tx);
             });
     }));
-
-    let app = ui::TemplateApp::new(command_tx, result_rx);
-    let native_options = eframe::NativeOptions::default();
-    eframe::run_native(Box::new(app), native_options);
 }
 
 fn run_render_loop(
@@ -183,6 +106,12 @@ fn run_render_loop(
         match render_command_rx.recv() {
             Err(flume::RecvError::Disconnected) => break, // nothing to do, just quit quietly
 
+            Ok(RenderCommand::Stop) => {
+                if let Some(ref should_abort) = abort_switch {
+                    should_abort.store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+
             Ok(RenderCommand::Render {
                 config,
                 cam_settings,
@@ -205,7 +134,27 @@ fn run_render_loop(
                     )));
                 }
 
-                let world = config.scene.create_world();
+                let mut world = match config.scene.create_world(config.seed) {
+                    Ok(world) => world,
+                    Err(e) => {
+                        render_result_tx
+                            .send(RenderResult::Failed {
+                                message: format!("Failed to load scene: {}", e),
+                            })
+                            .ok()
+                            .expect("sending Failed should succeed");
+                        continue;
+                    }
+                };
+                if let Some(ref sky) = config.sky {
+                    world.environment = Environment::Sky(Sky::new(sky));
+                }
+                render_result_tx
+                    .send(RenderResult::Built {
+                        bvh_stats: world.node.stats(),
+                    })
+                    .ok()
+                    .expect("sending Built should succeed");
 
                 let cam = Camera::new(cam_settings, config.aspect_ratio());
 
@@ -214,6 +163,36 @@ fn run_render_loop(
                 // drop the thread's join handle so that it runs in the background until rendering is done
                 std::mem::drop(std::thread::spawn(move || {
                     use rayon::prelude::*;
+
+                    if config.progressive {
+                        render_progressive(&world, &cam, &config, |buffer| {
+                            if abort_checker.load(std::sync::atomic::Ordering::SeqCst) {
+                                // stop rendering passes that nobody wants to see
+                                return false;
+                            }
+                            let total_passes = if buffer.active_pixels() == 0 {
+                                buffer.passes()
+                            } else {
+                                config.max_samples_per_pixel()
+                            };
+                            let image = buffer.to_image(&config);
+                            let denoised = config
+                                .denoising
+                                .map(|settings| (settings, denoise(&image, &settings)));
+                            render_result_tx
+                                .send(RenderResult::Frame {
+                                    image,
+                                    denoised,
+                                    passes_completed: buffer.passes(),
+                                    total_passes,
+                                })
+                                .ok()
+                                .unwrap();
+                            true
+                        });
+                        return;
+                    }
+
                     (0..config.image_height)
                         .rev()
                         .collect::<Vec<_>>()
@@ -224,36 +203,14 @@ fn run_render_loop(
                                 return;
                             }
 
-                            let mut line_pixels = Vec::with_capacity(config.image_width as usize);
-                            for i in 0..config.image_width {
-                                let mut pixel_color: Color = Color::zero();
-                                for _ in 0..config.samples_per_pixel {
-                                    let u = (i as f64 + util::random_double_unit())
-                                        / (config.image_width as f64 - 1.0);
-                                    let v = (j as f64 + util::random_double_unit())
-                                        / (config.image_height as f64 - 1.0);
-                                    let r = cam.get_ray(u, v);
-                                    pixel_color += ray_color(
-                                        r,
-                                        world.background,
-                                        &world.node,
-                                        config.render_mode,
-                                    );
-                                }
-
-                                let rgb8 = color_as_rgb8(pixel_color, config.samples_per_pixel);
-                                line_pixels.push(rgb8);
-                            }
+                            let line = render_line(&world, &cam, &config, j);
 
                             if abort_checker.load(std::sync::atomic::Ordering::SeqCst) {
                                 // don't send calculated image data if we should have already aborted
                                 return;
                             }
                             render_result_tx
-                                .send(RenderResult::ImageLine {
-                                    line_num: j,
-                                    line_pixels,
-                                })
+                                .send(RenderResult::ImageLine { line_num: j, line })
                                 .ok()
                                 .unwrap();
                         });
@@ -1,21 +1,81 @@
-use std::ops::Deref;
+use std::{
+    cell::Cell,
+    ops::Deref,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use crate::{
+    color::luminance,
     hittable::HitRecord,
-    ray::Ray,
+    microfacet::{self, Ggx},
+    onb::Onb,
+    ray::{Medium, Ray},
+    spectrum::D_LINE,
     texture::Texture,
-    util::random_double,
+    util::{random_double, random_pair},
     vec3::{Color, Point3, Vec3},
 };
-use derive_more::Constructor;
 
-pub(crate) trait Material: std::fmt::Debug + Send + Sync {
+/// A scattered ray along with what's needed to weigh it against other ways of sampling directions.
+#[derive(Debug, Clone, Copy)]
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    /// How much light coming back along `scattered` contributes: the BSDF times the cosine of the
+    /// scattered direction, divided by `pdf`
+    pub attenuation: Color,
+    /// Probability density (per unit solid angle) of having picked the direction of `scattered`,
+    /// or `None` if the scattering is specular and so no other direction could have been picked
+    pub pdf: Option<f64>,
+}
+
+pub trait Material: std::fmt::Debug + Send + Sync {
     /// Returns the scattered ray
     fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
 
     fn emitted(&self, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::zero()
     }
+
+    /// Like [`Material::scatter`], but also reports the probability density of the scattered
+    /// direction. By default the scattering is treated as specular.
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.scatter(r_in, rec)
+            .map(|(attenuation, scattered)| ScatterRecord {
+                scattered,
+                attenuation,
+                pdf: None,
+            })
+    }
+
+    /// The BSDF times the cosine of `direction`, for light that scatters from `direction` back
+    /// along `r_in`. Always zero for specular materials.
+    fn scattering(&self, _r_in: Ray, _rec: &HitRecord, _direction: Vec3) -> Color {
+        Color::zero()
+    }
+
+    /// Probability density (per unit solid angle) of [`Material::scatter_with_pdf`] picking
+    /// `direction`.
+    fn scattering_pdf(&self, _r_in: Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// The fraction of light the surface reflects at `rec`, ignoring direction; this is only used
+    /// for the albedo AOV pass, so it doesn't need to be exact.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    /// Whether the direction light scatters in depends on its wavelength, which means a path can
+    /// only go on carrying one wavelength after scattering here.
+    fn disperses(&self) -> bool {
+        false
+    }
+
+    /// Which material this is, for the material ID pass; 0 means it hasn't been numbered. Copies
+    /// of a material share its ID (see [`numbered_materials`]).
+    fn material_id(&self) -> u32 {
+        0
+    }
 }
 
 impl<M: Material + ?Sized, T: Deref<Target = M> + Send + Sync + std::fmt::Debug> Material for T {
@@ -26,12 +86,92 @@ impl<M: Material + ?Sized, T: Deref<Target = M> + Send + Sync + std::fmt::Debug>
     fn emitted(&self, u: f64, v: f64, p: Point3) -> Color {
         self.deref().emitted(u, v, p)
     }
+
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.deref().scatter_with_pdf(r_in, rec)
+    }
+
+    fn scattering(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.deref().scattering(r_in, rec, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        self.deref().scattering_pdf(r_in, rec, direction)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.deref().albedo(rec)
+    }
+
+    fn disperses(&self) -> bool {
+        self.deref().disperses()
+    }
+
+    fn material_id(&self) -> u32 {
+        self.deref().material_id()
+    }
+}
+
+thread_local! {
+    /// The ID of the next material made on this thread.
+    static NEXT_MATERIAL_ID: Cell<u32> = const { Cell::new(1) };
+}
+
+/// Run `f` with the materials it makes numbered from 1 in the order they're made, then go back to
+/// the numbering that was in use before. Building the same scene like this always gives its
+/// materials the same IDs, so they can be matched up across renders and animation frames.
+pub fn numbered_materials<T>(f: impl FnOnce() -> T) -> T {
+    let previous = NEXT_MATERIAL_ID.with(|next| next.replace(1));
+    let result = f();
+    NEXT_MATERIAL_ID.with(|next| next.set(previous));
+    result
+}
+
+/// An ID for a material that's just been made.
+pub(crate) fn next_material_id() -> u32 {
+    NEXT_MATERIAL_ID.with(|next| {
+        let id = next.get();
+        next.set(id.wrapping_add(1).max(1));
+        id
+    })
+}
+
+/// Density of cosine weighted scattering around `normal`, which is what lambertian materials use.
+fn cosine_pdf(normal: Vec3, direction: Vec3) -> f64 {
+    f64::max(normal.dot(direction.to_unit()), 0.0) / std::f64::consts::PI
+}
+
+/// Scatter with a cosine weighted direction around the normal, for lambertian materials.
+fn scatter_lambertian(albedo: Color, r_in: Ray, rec: &HitRecord) -> ScatterRecord {
+    let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+
+    // avoid degenerate scatter direction (avoid infinities and NaNs)
+    if scatter_direction.near_zero() {
+        scatter_direction = rec.normal;
+    }
+
+    ScatterRecord {
+        scattered: r_in.bounce(rec.p, scatter_direction),
+        // the BSDF is albedo / pi, which cancels with the density apart from the albedo
+        attenuation: albedo,
+        pdf: Some(cosine_pdf(rec.normal, scatter_direction)),
+    }
 }
 
 /// Bias of having light bounce towards the normal
-#[derive(Debug, Clone, Copy, Constructor)]
-pub(crate) struct DiffuseHack {
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseHack {
     albedo: Color,
+    material_id: u32,
+}
+
+impl DiffuseHack {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+            material_id: next_material_id(),
+        }
+    }
 }
 
 impl Material for DiffuseHack {
@@ -43,17 +183,32 @@ impl Material for DiffuseHack {
             scatter_direction = rec.normal;
         }
 
-        Some((
-            self.albedo,
-            Ray::new(rec.p, scatter_direction, Some(r_in.time())),
-        ))
+        Some((self.albedo, r_in.bounce(rec.p, scatter_direction)))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
     }
 }
 
 /// True lambertian reflection
-#[derive(Debug, Clone, Copy, Constructor)]
-pub(crate) struct DiffuseLambertian {
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseLambertian {
     albedo: Color,
+    material_id: u32,
+}
+
+impl DiffuseLambertian {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+            material_id: next_material_id(),
+        }
+    }
 }
 
 impl Material for DiffuseLambertian {
@@ -65,20 +220,47 @@ impl Material for DiffuseLambertian {
             scatter_direction = rec.normal;
         }
 
-        Some((
-            self.albedo,
-            Ray::new(rec.p, scatter_direction, Some(r_in.time())),
-        ))
+        Some((self.albedo, r_in.bounce(rec.p, scatter_direction)))
+    }
+
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(scatter_lambertian(self.albedo, r_in, rec))
+    }
+
+    fn scattering(&self, _r_in: Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.albedo * cosine_pdf(rec.normal, direction)
+    }
+
+    fn scattering_pdf(&self, _r_in: Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        cosine_pdf(rec.normal, direction)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
     }
 }
 
 /// True lambertian reflection with arbitrary textures
-#[derive(Debug, Clone, Constructor)]
-pub(crate) struct DiffuseLambertianTexture<T: Texture + Clone> {
+#[derive(Debug, Clone)]
+pub struct DiffuseLambertianTexture<T: Texture + ?Sized> {
     albedo: Box<T>,
+    material_id: u32,
 }
 
-impl<T: Texture + Clone> Material for DiffuseLambertianTexture<T> {
+impl<T: Texture + ?Sized> DiffuseLambertianTexture<T> {
+    pub fn new(albedo: Box<T>) -> Self {
+        Self {
+            albedo,
+            material_id: next_material_id(),
+        }
+    }
+}
+
+impl<T: Texture + ?Sized> Material for DiffuseLambertianTexture<T> {
     fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
 
@@ -89,15 +271,46 @@ impl<T: Texture + Clone> Material for DiffuseLambertianTexture<T> {
 
         Some((
             self.albedo.value(rec.u, rec.v, rec.p),
-            Ray::new(rec.p, scatter_direction, Some(r_in.time())),
+            r_in.bounce(rec.p, scatter_direction),
         ))
     }
+
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let albedo = self.albedo.value(rec.u, rec.v, rec.p);
+        Some(scatter_lambertian(albedo, r_in, rec))
+    }
+
+    fn scattering(&self, _r_in: Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * cosine_pdf(rec.normal, direction)
+    }
+
+    fn scattering_pdf(&self, _r_in: Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        cosine_pdf(rec.normal, direction)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
 }
 
 /// Hemispherical scattering
-#[derive(Debug, Clone, Copy, Constructor)]
-pub(crate) struct DiffuseHemispherical {
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseHemispherical {
     albedo: Color,
+    material_id: u32,
+}
+
+impl DiffuseHemispherical {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+            material_id: next_material_id(),
+        }
+    }
 }
 
 impl Material for DiffuseHemispherical {
@@ -109,25 +322,32 @@ impl Material for DiffuseHemispherical {
             scatter_direction = rec.normal;
         }
 
-        Some((
-            self.albedo,
-            Ray::new(rec.p, scatter_direction, Some(r_in.time())),
-        ))
+        Some((self.albedo, r_in.bounce(rec.p, scatter_direction)))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
     }
 }
 
 /// Reflective metal
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct Metal {
+pub struct Metal {
     albedo: Color,
     fuzz: f64,
+    material_id: u32,
 }
 
 impl Metal {
-    pub(crate) fn new(albedo: Color, fuzz: f64) -> Self {
+    pub fn new(albedo: Color, fuzz: f64) -> Self {
         Self {
             albedo,
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
+            material_id: next_material_id(),
         }
     }
 }
@@ -135,29 +355,300 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let reflected = Vec3::reflect(r_in.direction().to_unit(), rec.normal);
-        let scattered = Ray::new(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-            Some(r_in.time()),
-        );
+        let scattered = r_in.bounce(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere());
         if scattered.direction().dot(rec.normal) > 0.0 {
             Some((self.albedo, scattered))
         } else {
             None
         }
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
+}
+
+/// The complex index of refraction of a metal, for red, green and blue light.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ConductorIor {
+    Gold,
+    Copper,
+    Silver,
+    Aluminium,
+    Custom {
+        /// The real part, which bends light like a dielectric's index of refraction does
+        eta: Color,
+        /// The imaginary part (the extinction coefficient), which absorbs light
+        k: Color,
+    },
+}
+
+impl ConductorIor {
+    /// The real and imaginary parts of the index of refraction, measured at roughly the
+    /// wavelengths of red, green and blue light for the preset metals.
+    pub fn eta_k(&self) -> (Color, Color) {
+        match *self {
+            ConductorIor::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            ConductorIor::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            ConductorIor::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+            ConductorIor::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            ConductorIor::Custom { eta, k } => (eta, k),
+        }
+    }
+}
+
+/// Fraction of light reflected by a conductor with index of refraction `eta + k i`, for light
+/// arriving at an angle whose cosine is `cos_theta` (from the exact Fresnel equations for
+/// unpolarized light).
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cos2.sqrt();
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
 }
 
+/// A physically based metal: a rough surface of tiny mirror-like facets (with the GGX
+/// distribution) that reflect light according to the metal's complex index of refraction.
+#[derive(Debug)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    /// From 0 (polished) to 1 (very rough); colored textures use their luminance
+    roughness: Box<dyn Texture>,
+    material_id: u32,
+}
+
+impl Conductor {
+    pub fn new(ior: ConductorIor, roughness: Box<dyn Texture>) -> Self {
+        let (eta, k) = ior.eta_k();
+        Self {
+            eta,
+            k,
+            roughness,
+            material_id: next_material_id(),
+        }
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z),
+        )
+    }
+
+    /// The distribution of facets at `rec`, along with the frame around its normal and the
+    /// direction back along `r_in` in that frame.
+    fn local_frame(&self, r_in: Ray, rec: &HitRecord) -> (Ggx, Onb, Vec3) {
+        let roughness = luminance(self.roughness.value(rec.u, rec.v, rec.p));
+        let onb = Onb::from_w(rec.normal);
+        let wo = onb.to_local(-r_in.direction().to_unit());
+        (Ggx::from_roughness(roughness), onb, wo)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        self.scatter_with_pdf(r_in, rec)
+            .map(|scatter| (scatter.attenuation, scatter.scattered))
+    }
+
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (ggx, onb, wo) = self.local_frame(r_in, rec);
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let h = ggx.sample_visible_normal(wo, random_pair());
+        let wi = microfacet::reflect(wo, h);
+        if wi.z <= 0.0 {
+            // reflected into another facet; we don't follow light bouncing between facets
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered: r_in.bounce(rec.p, onb.local(wi)),
+            // most of the BSDF cancels with the density of picking visible facets
+            attenuation: self.fresnel(wo.dot(h)) * ggx.g(wo, wi) / ggx.g1(wo),
+            pdf: Some(ggx.reflection_pdf(wo, wi)),
+        })
+    }
+
+    fn scattering(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let (ggx, onb, wo) = self.local_frame(r_in, rec);
+        let wi = onb.to_local(direction.to_unit());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zero();
+        }
+        let h = (wo + wi).to_unit();
+        // the cosine of `wi` cancels with the one in the denominator of the BSDF
+        self.fresnel(wo.dot(h)) * ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z)
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let (ggx, onb, wo) = self.local_frame(r_in, rec);
+        let wi = onb.to_local(direction.to_unit());
+        if wi.z <= 0.0 {
+            return 0.0;
+        }
+        ggx.reflection_pdf(wo, wi)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.fresnel(1.0)
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
+}
+
+/// Gives every dielectric its own [`Medium::id`], so rays can tell which ones they're inside.
+static NEXT_MEDIUM_ID: AtomicU32 = AtomicU32::new(0);
+
 /// Dielectric metals (glass, water, etc)
+///
+/// Dielectrics can be nested: rays keep track of which ones they're inside, so they bend by the
+/// right amount going from water into glass, say. Where two overlap (like the water in a glass,
+/// which should touch the glass without gaps), the one with the higher priority is what's there.
+/// Copies of a dielectric are the same medium.
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct Dielectric {
+pub struct Dielectric {
     /// Index of refraction
     ir: f64,
+    /// How the index of refraction varies with wavelength, when rendering spectrally
+    dispersion: Option<Dispersion>,
+    /// Fraction of each color absorbed per unit distance inside
+    absorption: Color,
+    priority: i32,
+    id: u32,
+    material_id: u32,
 }
 
 impl Dielectric {
-    pub(crate) fn new(ir: f64) -> Self {
-        Self { ir }
+    pub fn new(ir: f64) -> Self {
+        Self {
+            ir,
+            dispersion: None,
+            absorption: Color::zero(),
+            priority: 0,
+            id: NEXT_MEDIUM_ID.fetch_add(1, Ordering::Relaxed),
+            material_id: next_material_id(),
+        }
+    }
+
+    /// Bend each wavelength of light by a different amount, splitting white light into a rainbow
+    /// when rendering spectrally. This replaces the index of refraction with the one `dispersion`
+    /// gives at the yellow sodium line, which is used when rendering in RGB.
+    pub fn dispersion(self, dispersion: Dispersion) -> Self {
+        Self {
+            ir: dispersion.ior(D_LINE),
+            dispersion: Some(dispersion),
+            ..self
+        }
+    }
+
+    /// Absorb light as it travels through, so thicker parts look deeper in color. Light that
+    /// travels a distance `d` keeps `exp(-absorption * d)` of each color.
+    pub fn absorption(self, absorption: Color) -> Self {
+        Self { absorption, ..self }
+    }
+
+    /// Defaults to 0; use a higher priority for something inside another dielectric, like an air
+    /// bubble (with an index of refraction of 1) in glass.
+    pub fn priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Index of refraction for light of `wavelength`, or for all light if there's no wavelength.
+    fn ior(&self, wavelength: Option<f64>) -> f64 {
+        match (self.dispersion, wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ir,
+        }
+    }
+
+    fn medium(&self, wavelength: Option<f64>) -> Medium {
+        Medium::new(
+            self.id,
+            self.priority,
+            self.ior(wavelength),
+            self.absorption,
+        )
+    }
+}
+
+/// How a dielectric's index of refraction varies with the wavelength of light, which is given in
+/// nanometres. The formulas themselves take wavelengths in micrometres, as their coefficients are
+/// usually quoted.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Dispersion {
+    /// Borosilicate crown glass (BK7), as used in most lenses
+    CrownGlass,
+    /// Dense flint glass (SF11), which disperses light strongly
+    FlintGlass,
+    Diamond,
+    /// Cauchy's equation, `n = a + b / λ²`, which works well for most clear materials in the
+    /// visible spectrum
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// The Sellmeier equation, `n² = 1 + Σ b λ² / (λ² - c)`
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Dispersion {
+    /// Index of refraction for light of `wavelength` nanometres.
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let (b, c) = match *self {
+            Dispersion::CrownGlass => (
+                [1.03961212, 0.231792344, 1.01046945],
+                [0.00600069867, 0.0200179144, 103.560653],
+            ),
+            Dispersion::FlintGlass => (
+                [1.73759695, 0.313747346, 1.89878101],
+                [0.013188707, 0.0623068142, 155.23629],
+            ),
+            Dispersion::Diamond => ([4.3356, 0.3306, 0.0], [0.011236, 0.030625, 0.0]),
+            Dispersion::Cauchy { a, b } => {
+                let micrometres = wavelength / 1000.0;
+                return a + b / (micrometres * micrometres);
+            }
+            Dispersion::Sellmeier { b, c } => (b, c),
+        };
+        let l2 = (wavelength / 1000.0).powi(2);
+        let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+        n2.sqrt()
     }
 }
 
@@ -173,10 +664,25 @@ impl Dielectric {
 impl Material for Dielectric {
     fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
+        let media = r_in.media;
+        let current = media.current().map(|medium| medium.id());
+        // the media the ray would be in on the other side of the surface
+        let beyond = if rec.front_face {
+            media.with(self.medium(r_in.wavelength()))
+        } else {
+            media.without(self.id)
+        };
+        if beyond.current().map(|medium| medium.id()) == current && current.is_some() {
+            // a surface inside a medium with a higher priority isn't really there
+            let mut scattered = r_in.bounce(rec.p, r_in.direction());
+            scattered.media = beyond;
+            return Some((attenuation, scattered));
+        }
+        let ir = self.ior(r_in.wavelength());
         let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
+            media.ior() / ir
         } else {
-            self.ir
+            ir / beyond.ior()
         };
 
         let unit_direction = r_in.direction().to_unit();
@@ -185,24 +691,49 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-        let direction = if cannot_refract
+        let scattered = if cannot_refract
             || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double(0.0, 1.0)
         {
-            Vec3::reflect(unit_direction, rec.normal)
+            r_in.bounce(rec.p, Vec3::reflect(unit_direction, rec.normal))
         } else {
-            Vec3::refract(unit_direction, rec.normal, refraction_ratio)
+            let mut refracted = r_in.bounce(
+                rec.p,
+                Vec3::refract(unit_direction, rec.normal, refraction_ratio),
+            );
+            refracted.media = beyond;
+            refracted
         };
-
-        let scattered = Ray::new(rec.p, direction, Some(r_in.time()));
         Some((attenuation, scattered))
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::one()
+    }
+
+    fn disperses(&self) -> bool {
+        self.dispersion.is_some()
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
 }
 
 /// Dielectric metals (glass, water, etc)
-#[derive(Debug, Constructor)]
-pub(crate) struct DiffuseLight {
+#[derive(Debug)]
+pub struct DiffuseLight {
     /// Index of refraction
     emit: Box<dyn Texture>,
+    material_id: u32,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Box<dyn Texture>) -> Self {
+        Self {
+            emit,
+            material_id: next_material_id(),
+        }
+    }
 }
 
 impl Material for DiffuseLight {
@@ -213,4 +744,167 @@ impl Material for DiffuseLight {
     fn emitted(&self, u: f64, v: f64, p: Point3) -> Color {
         self.emit.value(u, v, p)
     }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ray::MediaStack, texture::ColorTexture, util};
+
+    /// Where a ray arriving at 45 degrees to a surface facing up (or down) goes next.
+    fn scatter_at_45_degrees(material: &Dielectric, r_in: Ray, from_above: bool) -> Ray {
+        let rec = HitRecord::new(
+            1.0,
+            (0.0, 0.0),
+            r_in,
+            Vec3::new(0.0, if from_above { 1.0 } else { -1.0 }, 0.0),
+            material,
+        );
+        // keep going until the ray isn't reflected
+        loop {
+            let (_, scattered) = material.scatter(r_in, &rec).unwrap();
+            if scattered.direction().y < 0.0 {
+                return scattered;
+            }
+        }
+    }
+
+    #[test]
+    fn nested_dielectrics_bend_light_by_the_ratio_of_their_indices() {
+        let water = Dielectric::new(1.33);
+        let glass = Dielectric::new(1.5);
+        let mut r_in = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), None);
+        r_in.media = r_in.media.with(water.medium(None));
+
+        util::with_seed(1, || {
+            // snell's law: sin 45 * 1.33 = sin theta * 1.5
+            let into_glass = scatter_at_45_degrees(&glass, r_in, true);
+            let sin_theta = into_glass.direction().to_unit().x;
+            assert!((sin_theta - 1.33 / 1.5 * 0.5f64.sqrt()).abs() < 1e-6);
+            assert_eq!(into_glass.media.ior(), 1.5);
+
+            // and leaving the water for the air outside it
+            let out_of_water = scatter_at_45_degrees(&water, r_in, false);
+            let sin_theta = out_of_water.direction().to_unit().x;
+            assert!((sin_theta - 1.33 * 0.5f64.sqrt()).abs() < 1e-6);
+            assert_eq!(out_of_water.media, MediaStack::default());
+        });
+    }
+
+    #[test]
+    fn surfaces_inside_higher_priority_media_are_passed_through() {
+        let glass = Dielectric::new(1.5).priority(1);
+        let water = Dielectric::new(1.33);
+        let mut r_in = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), None);
+        r_in.media = r_in.media.with(glass.medium(None));
+
+        // the water starts inside the glass's wall, so its surface there isn't real
+        let rec = HitRecord::new(1.0, (0.0, 0.0), r_in, Vec3::new(0.0, 1.0, 0.0), &water);
+        let (attenuation, scattered) = water.scatter(r_in, &rec).unwrap();
+        assert_eq!(attenuation, Color::one());
+        assert_eq!(scattered.direction(), r_in.direction());
+        assert!(scattered.media.contains(water.id));
+        assert_eq!(scattered.media.ior(), 1.5);
+
+        // but the inside of the glass wall is, and it leads into the water
+        util::with_seed(2, || {
+            let into_water = scatter_at_45_degrees(&glass, scattered, false);
+            let sin_theta = into_water.direction().to_unit().x;
+            assert!((sin_theta - 1.5 / 1.33 * 0.5f64.sqrt()).abs() < 1e-6);
+            assert!((into_water.media.ior() - 1.33).abs() < 1e-6);
+        });
+    }
+
+    #[test]
+    fn dispersive_dielectrics_bend_blue_light_more_than_red() {
+        // the textbook indices at the d line
+        assert!((Dispersion::CrownGlass.ior(D_LINE) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::Diamond.ior(D_LINE) - 2.417).abs() < 1e-3);
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(500.0) - 1.516).abs() < 1e-9);
+
+        let glass = Dielectric::new(1.0).dispersion(Dispersion::FlintGlass);
+        assert_eq!(glass.ir, Dispersion::FlintGlass.ior(D_LINE));
+        let r_in = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), None);
+        util::with_seed(3, || {
+            let bend = |r_in: Ray| {
+                scatter_at_45_degrees(&glass, r_in, true)
+                    .direction()
+                    .to_unit()
+                    .x
+            };
+            let (blue, red) = (
+                bend(r_in.with_wavelength(450.0)),
+                bend(r_in.with_wavelength(650.0)),
+            );
+            assert!(
+                blue < red - 0.005,
+                "sin θ is {} for blue, {} for red",
+                blue,
+                red
+            );
+            // without a wavelength it's bent like yellow light
+            let white = bend(r_in);
+            assert!((white - 0.5f64.sqrt() / glass.ir).abs() < 1e-6);
+            assert!(blue < white && white < red);
+        });
+    }
+
+    #[test]
+    fn conductor_fresnel_matches_normal_and_grazing_incidence() {
+        for (eta, k) in [(0.2, 3.9), (1.657, 9.224), (1.5, 0.0)] {
+            let normal = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+            assert!((fresnel_conductor(1.0, eta, k) - normal).abs() < 1e-9);
+            assert!(fresnel_conductor(1e-4, eta, k) > 0.99);
+        }
+    }
+
+    #[test]
+    fn rough_conductor_samples_match_its_bsdf() {
+        let gold = Conductor::new(
+            ConductorIor::Gold,
+            Box::new(ColorTexture::from_rgb(0.5, 0.5, 0.5)),
+        );
+        let r_in = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), None);
+        let rec = HitRecord::new(1.0, (0.0, 0.0), r_in, Vec3::new(0.0, 1.0, 0.0), &gold);
+        let samples = 200_000;
+
+        // the average weight of the samples is the light the surface reflects overall...
+        let sampled = util::with_seed(3, || {
+            (0..samples)
+                .filter_map(|_| {
+                    let scatter = gold.scatter_with_pdf(r_in, &rec)?;
+                    let direction = scatter.scattered.direction();
+                    // ...and each sample's weight and density agree with evaluating it directly
+                    let pdf = gold.scattering_pdf(r_in, &rec, direction);
+                    assert!((scatter.pdf.unwrap() - pdf).abs() < 1e-6 * pdf.max(1.0));
+                    let expected = gold.scattering(r_in, &rec, direction) / pdf;
+                    assert!((scatter.attenuation - expected).length() < 1e-6);
+                    Some(scatter.attenuation)
+                })
+                .fold(Color::zero(), |a, b| a + b)
+        }) / samples as f64;
+        let integrated = util::with_seed(4, || {
+            (0..samples)
+                .map(|_| {
+                    let direction = Vec3::random_in_hemisphere(rec.normal);
+                    gold.scattering(r_in, &rec, direction)
+                })
+                .fold(Color::zero(), |a, b| a + b)
+        }) * 2.0
+            * std::f64::consts::PI
+            / samples as f64;
+        assert!(
+            (sampled - integrated).length() < 0.02,
+            "{} != {}",
+            sampled,
+            integrated
+        );
+        // some light is lost to facets shadowing each other, but not much
+        assert!(sampled.x < gold.albedo(&rec).x && sampled.x > 0.7);
+    }
 }
@@ -0,0 +1,301 @@
+//! 4x4 matrices and quaternions for placing objects with arbitrary affine transforms (see
+//! [`crate::hittable::Transform`]).
+
+use std::ops::Mul;
+
+use crate::{
+    util::degrees_to_radians,
+    vec3::{Point3, Vec3},
+};
+
+/// A 4x4 matrix acting on column vectors, so `a * b` applies `b` first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    /// The rows of the matrix
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    /// A matrix that moves points by `offset`.
+    pub fn translation(offset: Vec3) -> Self {
+        let mut matrix = Self::IDENTITY;
+        for i in 0..3 {
+            matrix.m[i][3] = offset[i];
+        }
+        matrix
+    }
+
+    /// A matrix that scales by `factors` along each axis.
+    pub fn scaling(factors: Vec3) -> Self {
+        let mut matrix = Self::IDENTITY;
+        for i in 0..3 {
+            matrix.m[i][i] = factors[i];
+        }
+        matrix
+    }
+
+    /// A matrix that rotates by `angle` degrees around `axis`, anticlockwise when looking back
+    /// along the axis.
+    pub fn rotation(axis: Vec3, angle: f64) -> Self {
+        Quaternion::from_axis_angle(axis, angle).into()
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut matrix = Self::IDENTITY;
+        for i in 0..4 {
+            for j in 0..4 {
+                matrix.m[i][j] = self.m[j][i];
+            }
+        }
+        matrix
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let row = |i: usize| m[i][0] * p.x + m[i][1] * p.y + m[i][2] * p.z + m[i][3];
+        let w = row(3);
+        let p = Point3::new(row(0), row(1), row(2));
+        if w == 1.0 {
+            p
+        } else {
+            p / w
+        }
+    }
+
+    /// Transform a direction, which unlike a point isn't moved by translations.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        let row = |i: usize| m[i][0] * v.x + m[i][1] * v.y + m[i][2] * v.z;
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    /// Determinant of the top left 3x3 part: how much the matrix scales volumes by (negative if it
+    /// mirrors them).
+    pub fn linear_determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut matrix = Matrix4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                matrix.m[i][j] = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        matrix
+    }
+}
+
+/// A rotation, stored as a unit quaternion.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quaternion {
+    /// No rotation at all.
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// A rotation by `angle` degrees around `axis`, anticlockwise when looking back along the
+    /// axis.
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Self {
+        let axis = axis.to_unit();
+        let half = degrees_to_radians(angle) / 2.0;
+        let s = half.sin();
+        Self {
+            w: half.cos(),
+            x: axis.x * s,
+            y: axis.y * s,
+            z: axis.z * s,
+        }
+    }
+
+    /// The same rotation scaled to unit length, which is what a rotation matrix needs.
+    pub fn normalized(self) -> Self {
+        let length = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Self {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    /// The opposite rotation.
+    pub fn conjugate(self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn dot(self, other: Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Spherical linear interpolation: the rotation a fraction `t` of the way from `self` to
+    /// `other`, turning at a constant speed the short way round.
+    pub fn slerp(self, other: Quaternion, t: f64) -> Self {
+        let (a, mut b) = (self.normalized(), other.normalized());
+        // q and -q are the same rotation, but only one of them is the short way round
+        let mut cos_theta = a.dot(b);
+        if cos_theta < 0.0 {
+            b = Quaternion {
+                w: -b.w,
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+            };
+            cos_theta = -cos_theta;
+        }
+        let (wa, wb) = if cos_theta > 0.9995 {
+            // nearly the same rotation, where the sines below lose precision
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            (
+                ((1.0 - t) * theta).sin() / theta.sin(),
+                (t * theta).sin() / theta.sin(),
+            )
+        };
+        Quaternion {
+            w: wa * a.w + wb * b.w,
+            x: wa * a.x + wb * b.x,
+            y: wa * a.y + wb * b.y,
+            z: wa * a.z + wb * b.z,
+        }
+        .normalized()
+    }
+}
+
+impl From<Quaternion> for Matrix4 {
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q.normalized();
+        Matrix4 {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn rotations_turn_anticlockwise_around_their_axis() {
+        let quarter_turn = Matrix4::rotation(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        assert_close(
+            quarter_turn.transform_vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let around_x = Matrix4::rotation(Vec3::new(2.0, 0.0, 0.0), 90.0);
+        assert_close(
+            around_x.transform_point(Point3::new(0.0, 1.0, 0.0)),
+            Point3::new(0.0, 0.0, 1.0),
+        );
+        // rotating back undoes it, and rotations keep volumes the same
+        let back =
+            Matrix4::from(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0).conjugate());
+        assert_close(
+            (back * quarter_turn).transform_point(Point3::new(1.0, 2.0, 3.0)),
+            Point3::new(1.0, 2.0, 3.0),
+        );
+        assert!((quarter_turn.linear_determinant() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn products_apply_the_right_hand_side_first() {
+        let scale_then_move = Matrix4::translation(Vec3::new(1.0, 0.0, 0.0))
+            * Matrix4::scaling(Vec3::new(2.0, 3.0, 4.0));
+        assert_close(
+            scale_then_move.transform_point(Point3::new(1.0, 1.0, 1.0)),
+            Point3::new(3.0, 3.0, 4.0),
+        );
+        // directions aren't moved
+        assert_close(
+            scale_then_move.transform_vector(Vec3::new(1.0, 1.0, 1.0)),
+            Vec3::new(2.0, 3.0, 4.0),
+        );
+        assert_eq!(scale_then_move.linear_determinant(), 24.0);
+    }
+
+    #[test]
+    fn slerp_turns_at_a_constant_speed_the_short_way_round() {
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let start = Quaternion::from_axis_angle(y, 10.0);
+        // 350 degrees is the same as -10, so halfway is no rotation at all
+        let end = Quaternion::from_axis_angle(y, 350.0);
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        assert_close(Matrix4::from(start.slerp(end, 0.5)).transform_vector(x), x);
+
+        let end = Quaternion::from_axis_angle(y, 130.0);
+        for t in [0.0, 0.25, 0.5, 1.0] {
+            assert_close(
+                Matrix4::from(start.slerp(end, t)).transform_vector(x),
+                Matrix4::rotation(y, 10.0 + 120.0 * t).transform_vector(x),
+            );
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh_node::BvhNode,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    vec3::{Point3, Vec3},
+};
+
+/// Vertex data shared by all the triangles of a mesh.
+#[derive(Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    /// Texture coordinates, as (u, v)
+    pub uvs: Vec<(f64, f64)>,
+    pub materials: Vec<Box<dyn Material>>,
+}
+
+/// Indexes into the buffers of a [`MeshData`] to describe one corner of a triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshFace {
+    pub vertices: [MeshVertex; 3],
+    /// Index into the materials of the mesh
+    pub material: usize,
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    mesh: Arc<MeshData>,
+    face: MeshFace,
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<MeshData>, face: MeshFace) -> Self {
+        Self { mesh, face }
+    }
+
+    fn position(&self, corner: usize) -> Point3 {
+        self.mesh.positions[self.face.vertices[corner].position]
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Möller–Trumbore intersection
+        let p0 = self.position(0);
+        let edge1 = self.position(1) - p0;
+        let edge2 = self.position(2) - p0;
+
+        let pvec = r.direction().cross(edge2);
+        let det = edge1.dot(pvec);
+        if det.abs() < 1e-12 {
+            return None; // ray is parallel to the triangle
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(edge1);
+        let b2 = r.direction().dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+        let [v0, v1, v2] = self.face.vertices;
+
+        // which side was hit is decided by the flat triangle, since the interpolated normal can
+        // face away from rays that hit the front near silhouettes
+        let geometric = edge1.cross(edge2).to_unit();
+        let (outward_normal, shading_normal) = match (v0.normal, v1.normal, v2.normal) {
+            (Some(n0), Some(n1), Some(n2)) => {
+                let [n0, n1, n2] = [n0, n1, n2].map(|n| self.mesh.normals[n]);
+                // the vertex normals say which side is outside, even if the winding disagrees
+                let outward = if (n0 + n1 + n2).dot(geometric) < 0.0 {
+                    -geometric
+                } else {
+                    geometric
+                };
+                (outward, Some(b0 * n0 + b1 * n1 + b2 * n2))
+            }
+            _ => (geometric, None),
+        };
+        let uv = match (v0.uv, v1.uv, v2.uv) {
+            (Some(t0), Some(t1), Some(t2)) => {
+                let uvs = &self.mesh.uvs;
+                (
+                    b0 * uvs[t0].0 + b1 * uvs[t1].0 + b2 * uvs[t2].0,
+                    b0 * uvs[t0].1 + b1 * uvs[t1].1 + b2 * uvs[t2].1,
+                )
+            }
+            _ => (b1, b2),
+        };
+
+        let mut rec = HitRecord::new(
+            t,
+            uv,
+            r,
+            outward_normal,
+            &*self.mesh.materials[self.face.material],
+        );
+        // opposing vertex normals can cancel out, leaving just the geometric normal
+        if let Some(shading) = shading_normal.filter(|n| !n.near_zero()) {
+            let shading = shading.to_unit();
+            rec.normal = if shading.dot(rec.normal) < 0.0 {
+                -shading
+            } else {
+                shading
+            };
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        let mut min = self.position(0);
+        let mut max = min;
+        for corner in 1..3 {
+            let p = self.position(corner);
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        // Axis aligned triangles would have a zero width bounding box, so pad every dimension a
+        // small amount.
+        let padding = Vec3::new(0.0001, 0.0001, 0.0001);
+        Some(Aabb::new(min - padding, max + padding))
+    }
+}
+
+/// A triangle mesh with its own bounding volume hierarchy.
+#[derive(Debug)]
+pub struct Mesh {
+    bvh: BvhNode,
+    triangle_count: usize,
+}
+
+impl Mesh {
+    /// Panics if `faces` is empty.
+    pub fn new(data: MeshData, faces: Vec<MeshFace>, time0: f64, time1: f64) -> Self {
+        let data = Arc::new(data);
+        let triangle_count = faces.len();
+        let triangles = faces
+            .into_iter()
+            .map(|face| Box::new(Triangle::new(data.clone(), face)) as Box<dyn Hittable>)
+            .collect();
+        Self {
+            bvh: BvhNode::new(triangles, time0, time1),
+            triangle_count,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_count
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.bvh.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::DiffuseLambertian, vec3::Color};
+
+    fn vertex(position: usize) -> MeshVertex {
+        MeshVertex {
+            position,
+            uv: Some(position),
+            normal: Some(position),
+        }
+    }
+
+    fn unit_triangle() -> Triangle {
+        let data = MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, 1.0).to_unit(),
+                Vec3::new(0.0, 1.0, 1.0).to_unit(),
+            ],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            materials: vec![Box::new(DiffuseLambertian::new(Color::one()))],
+        };
+        Triangle::new(
+            Arc::new(data),
+            MeshFace {
+                vertices: [vertex(0), vertex(1), vertex(2)],
+                material: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn hit_interpolates_uvs_and_normals() {
+        let tri = unit_triangle();
+        let r = Ray::new(
+            Point3::new(0.25, 0.25, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            None,
+        );
+
+        let rec = tri.hit(r, 0.001, f64::INFINITY).expect("ray should hit");
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!((rec.u - 0.25).abs() < 1e-9);
+        assert!((rec.v - 0.25).abs() < 1e-9);
+        assert!(rec.front_face);
+        // the interpolated normal leans towards the corners with tilted normals
+        assert!(rec.normal.x > 0.0 && rec.normal.y > 0.0);
+    }
+
+    #[test]
+    fn side_comes_from_the_triangle_not_the_vertex_normals() {
+        let triangle = |positions: [Point3; 3], normals: [Vec3; 3]| {
+            let data = MeshData {
+                positions: positions.to_vec(),
+                normals: normals.to_vec(),
+                uvs: vec![],
+                materials: vec![Box::new(DiffuseLambertian::new(Color::one()))],
+            };
+            let vertices = [0, 1, 2].map(|i| MeshVertex {
+                uv: None,
+                ..vertex(i)
+            });
+            Triangle::new(
+                Arc::new(data),
+                MeshFace {
+                    vertices,
+                    material: 0,
+                },
+            )
+        };
+        let anticlockwise = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        let clockwise = [anticlockwise[0], anticlockwise[2], anticlockwise[1]];
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let hit = |tri: &Triangle, direction: Vec3| {
+            let origin = Point3::new(0.25, 0.25, 0.0) - direction;
+            let rec = tri.hit(Ray::new(origin, direction, None), 0.001, f64::INFINITY);
+            let rec = rec.expect("ray should hit");
+            (rec.front_face, rec.normal)
+        };
+
+        // the vertex normals point up even though the winding says down
+        let tri = triangle(clockwise, [up; 3]);
+        assert_eq!(hit(&tri, -up), (true, up));
+        assert_eq!(hit(&tri, up), (false, -up));
+
+        // a grazing ray that hits the front, but travels along the tilted vertex normals
+        let tilted = Vec3::new(1.0, 0.0, 0.2).to_unit();
+        let tri = triangle(anticlockwise, [tilted; 3]);
+        let (front_face, normal) = hit(&tri, Vec3::new(1.0, 0.0, -0.1));
+        assert!(front_face);
+        assert!((normal - tilted).length() < 1e-9);
+
+        // vertex normals that cancel out where the ray hits
+        let tri = triangle(anticlockwise, [up, -up, -up]);
+        let (_, normal) = hit(&tri, -up);
+        assert_eq!(normal, up);
+    }
+
+    #[test]
+    fn miss_outside_edges() {
+        let tri = unit_triangle();
+        let r = Ray::new(
+            Point3::new(0.75, 0.75, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            None,
+        );
+        assert!(tri.hit(r, 0.001, f64::INFINITY).is_none());
+    }
+}
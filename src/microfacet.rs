@@ -0,0 +1,187 @@
+//! The GGX (Trowbridge-Reitz) microfacet distribution, for rough surfaces that are made of many
+//! tiny mirror-like facets. Directions are in a local frame around the surface normal, which is
+//! the z axis (see [`crate::onb::Onb`]).
+
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+/// The smoothest a surface can get; any smoother and the density of facet normals gets so peaked
+/// that it can't be sampled or evaluated reliably.
+const MIN_ALPHA: f64 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ggx {
+    /// Width of the distribution of facet normals
+    alpha: f64,
+}
+
+impl Ggx {
+    /// A distribution for a surface with `roughness` from 0 (polished) to 1 (very rough). The
+    /// roughness is squared, which makes it look perceptually linear.
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    /// Density of facets facing along `h` (per unit solid angle, projected onto the surface).
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * denominator * denominator)
+    }
+
+    /// Smith's auxiliary function, which measures how much of the surface facets hide when seen
+    /// from `w`.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of the facets facing `h`-wards that can be seen from `w`.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of facets that can be seen from both `wo` and `wi` (the height correlated form,
+    /// which accounts for facets that are high up being visible from both).
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Pick a facet normal in proportion to how much of it can be seen from `wo`, using two
+    /// random numbers from 0 to 1 (from "Sampling the GGX Distribution of Visible Normals",
+    /// Heitz 2018).
+    pub fn sample_visible_normal(&self, wo: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        // stretch the view so the facets form a hemisphere, and sample the part of it that's seen
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).to_unit();
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / length2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        // and squash it back
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).to_unit()
+    }
+
+    /// Probability density of [`Ggx::sample_visible_normal`] picking `h`.
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+
+    /// Probability density of picking `wi` by reflecting `wo` off a facet normal picked with
+    /// [`Ggx::sample_visible_normal`].
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let h = (wo + wi).to_unit();
+        let cos = wo.dot(h);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        self.visible_normal_pdf(wo, h) / (4.0 * cos)
+    }
+}
+
+/// Mirror `wo` around the facet normal `h`.
+pub fn reflect(wo: Vec3, h: Vec3) -> Vec3 {
+    2.0 * wo.dot(h) * h - wo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn projected_facet_areas_add_up_to_the_surface() {
+        // ∫ D(h) cos(h) dh = 1 over the hemisphere, for any roughness
+        for roughness in [0.2, 0.5, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            let samples = 400_000;
+            let integral = util::with_seed(1, || {
+                (0..samples)
+                    .map(|_| {
+                        let h = Vec3::random_in_hemisphere(Vec3::new(0.0, 0.0, 1.0)).to_unit();
+                        ggx.d(h) * h.z
+                    })
+                    .sum::<f64>()
+            }) * 2.0
+                * PI
+                / samples as f64;
+            assert!(
+                (integral - 1.0).abs() < 0.03,
+                "integral is {} for roughness {}",
+                integral,
+                roughness
+            );
+        }
+    }
+
+    #[test]
+    fn visible_normals_are_sampled_with_their_density() {
+        let ggx = Ggx::from_roughness(0.6);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        // the density integrates to one over the hemisphere...
+        let samples = 400_000;
+        let integral = util::with_seed(2, || {
+            (0..samples)
+                .map(|_| {
+                    let h = Vec3::random_in_hemisphere(Vec3::new(0.0, 0.0, 1.0)).to_unit();
+                    ggx.visible_normal_pdf(wo, h)
+                })
+                .sum::<f64>()
+        }) * 2.0
+            * PI
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.03, "integral is {}", integral);
+
+        // ...and matches where the samples land: check the fraction within 30° of the normal
+        let sampled = util::with_seed(4, || {
+            (0..samples)
+                .filter(|_| ggx.sample_visible_normal(wo, util::random_pair()).z > (PI / 6.0).cos())
+                .count()
+        }) as f64
+            / samples as f64;
+        let expected = util::with_seed(5, || {
+            (0..samples)
+                .map(|_| {
+                    let h = Vec3::random_in_hemisphere(Vec3::new(0.0, 0.0, 1.0)).to_unit();
+                    if h.z > (PI / 6.0).cos() {
+                        ggx.visible_normal_pdf(wo, h)
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f64>()
+        }) * 2.0
+            * PI
+            / samples as f64;
+        assert!(
+            (sampled - expected).abs() < 0.02,
+            "{} of samples near the normal, expected {}",
+            sampled,
+            expected
+        );
+    }
+}
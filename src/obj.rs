@@ -0,0 +1,552 @@
+//! Loading of Wavefront OBJ meshes and their MTL material libraries.
+//!
+//! Only the parts of the formats that map onto our primitives and materials are supported:
+//! vertex positions, texture coordinates, normals, polygonal faces (which get fan triangulated)
+//! and `usemtl`/`mtllib`. Everything else (groups, smoothing groups, lines, etc) is ignored.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    material::{Dielectric, DiffuseLambertianTexture, DiffuseLight, Material, Metal},
+    mesh::{Mesh, MeshData, MeshFace, MeshVertex},
+    principled::Principled,
+    texture::{ColorTexture, ImageTexture, Texture},
+    vec3::{Color, Point3, Vec3},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Texture {
+        path: PathBuf,
+        error: lodepng::Error,
+    },
+    /// The file did not contain any faces
+    Empty { path: PathBuf },
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, error } => {
+                write!(f, "could not load texture {}: {}", path.display(), error)
+            }
+            ObjError::Empty { path } => write!(f, "{} does not contain any faces", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Load an OBJ file as a mesh, along with any material libraries it references.
+///
+/// Faces that use a material which isn't defined anywhere get a plain grey diffuse material.
+pub fn load_obj<P: AsRef<Path>>(path: P, time0: f64, time1: f64) -> Result<Mesh, ObjError> {
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let contents = parse_obj(&read_file(path)?, path)?;
+    if contents.faces.is_empty() {
+        return Err(ObjError::Empty {
+            path: path.to_owned(),
+        });
+    }
+
+    let mut library = HashMap::new();
+    for mtl_path in &contents.material_libraries {
+        let mtl_path = base_dir.join(mtl_path);
+        library.extend(parse_mtl(&read_file(&mtl_path)?, &mtl_path)?);
+    }
+
+    let materials = contents
+        .material_names
+        .iter()
+        .map(|name| match library.get(name) {
+            Some(mtl) => mtl.build(base_dir),
+            None => MtlMaterial::default().build(base_dir),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let data = MeshData {
+        positions: contents.positions,
+        normals: contents.normals,
+        uvs: contents.uvs,
+        materials,
+    };
+    Ok(Mesh::new(data, contents.faces, time0, time1))
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// The raw contents of an OBJ file, before materials have been resolved.
+#[derive(Debug, Default)]
+struct ObjContents {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    /// Faces whose material is an index into `material_names`
+    faces: Vec<MeshFace>,
+    material_names: Vec<String>,
+    material_libraries: Vec<PathBuf>,
+}
+
+/// Name of the material used by faces that come before any `usemtl` statement.
+const DEFAULT_MATERIAL: &str = "default";
+
+fn parse_obj(source: &str, path: &Path) -> Result<ObjContents, ObjError> {
+    let mut contents = ObjContents::default();
+    let mut current_material = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: line_index + 1,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats::<3>(&args).map_err(error)?;
+                contents.positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                // a zero length normal is kept as zero, and faces fall back to the geometric
+                // normal wherever they use it
+                let [x, y, z] = parse_floats::<3>(&args).map_err(error)?;
+                let normal = Vec3::new(x, y, z);
+                contents.normals.push(if normal.near_zero() {
+                    Vec3::zero()
+                } else {
+                    normal.to_unit()
+                });
+            }
+            "vt" => contents.uvs.push(parse_uv(&args).map_err(error)?),
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(format!(
+                        "faces need at least 3 vertices, found {}",
+                        args.len()
+                    )));
+                }
+                let mut vertices = args
+                    .iter()
+                    .map(|arg| parse_vertex(arg, &contents))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                if vertices
+                    .iter()
+                    .filter_map(|v| v.normal)
+                    .any(|n| contents.normals[n] == Vec3::zero())
+                {
+                    // the mesh only interpolates normals when all 3 corners have one
+                    for vertex in &mut vertices {
+                        vertex.normal = None;
+                    }
+                }
+                let material = *current_material.get_or_insert_with(|| {
+                    material_index(&mut contents.material_names, DEFAULT_MATERIAL)
+                });
+                // fan triangulation, which is fine for the convex polygons that exporters produce
+                for i in 1..vertices.len() - 1 {
+                    contents.faces.push(MeshFace {
+                        vertices: [vertices[0], vertices[i], vertices[i + 1]],
+                        material,
+                    });
+                }
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = Some(material_index(&mut contents.material_names, &name));
+            }
+            "mtllib" => contents
+                .material_libraries
+                .extend(args.iter().map(PathBuf::from)),
+            _ => (), // groups, smoothing groups, lines, etc
+        }
+    }
+
+    Ok(contents)
+}
+
+fn material_index(names: &mut Vec<String>, name: &str) -> usize {
+    names.iter().position(|n| n == name).unwrap_or_else(|| {
+        names.push(name.to_owned());
+        names.len() - 1
+    })
+}
+
+fn parse_floats<const N: usize>(args: &[&str]) -> Result<[f64; N], String> {
+    if args.len() < N {
+        return Err(format!("expected {} numbers, found {}", N, args.len()));
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| format!("invalid number '{}'", arg))?;
+    }
+    Ok(values)
+}
+
+/// Parse `u v`, or just `u` with `v` defaulting to 0. The optional third (w) coordinate is ignored.
+fn parse_uv(args: &[&str]) -> Result<(f64, f64), String> {
+    if args.len() >= 2 {
+        let [u, v] = parse_floats::<2>(args)?;
+        Ok((u, v))
+    } else {
+        let [u] = parse_floats::<1>(args)?;
+        Ok((u, 0.0))
+    }
+}
+
+/// Parse a face vertex such as `1`, `1/2`, `1//3` or `1/2/3`.
+fn parse_vertex(arg: &str, contents: &ObjContents) -> Result<MeshVertex, String> {
+    let mut parts = arg.split('/');
+    let position = parse_index(parts.next(), contents.positions.len())?
+        .ok_or_else(|| format!("face vertex '{}' has no position", arg))?;
+    let uv = parse_index(parts.next(), contents.uvs.len())?;
+    let normal = parse_index(parts.next(), contents.normals.len())?;
+    Ok(MeshVertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+/// Convert a 1-based (or negative, meaning relative to the end) OBJ index to a 0-based one.
+fn parse_index(part: Option<&str>, len: usize) -> Result<Option<usize>, String> {
+    let part = match part {
+        Some(p) if !p.is_empty() => p,
+        _ => return Ok(None),
+    };
+    let index: isize = part
+        .parse()
+        .map_err(|_| format!("invalid index '{}'", part))?;
+    let resolved = if index < 0 {
+        len as isize + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= len as isize {
+        return Err(format!("index {} is out of range", index));
+    }
+    Ok(Some(resolved as usize))
+}
+
+/// The subset of an MTL material that we know how to render.
+#[derive(Debug, Clone, PartialEq)]
+struct MtlMaterial {
+    /// `Kd`
+    diffuse: Color,
+    /// `Ks`
+    specular: Color,
+    /// `Ke`
+    emission: Color,
+    /// `Ns`, from 0 to 1000
+    shininess: f64,
+    /// `Ni`
+    ior: f64,
+    /// `d`, or 1 - `Tr`
+    dissolve: f64,
+    illum: u32,
+    /// `map_Kd`
+    diffuse_map: Option<PathBuf>,
+    /// `Pr`, `Pm`, `Ps` and `Pc` from the PBR extension; materials that set any of them are
+    /// rendered with a [`Principled`] material
+    roughness: Option<f64>,
+    metallic: Option<f64>,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.73, 0.73, 0.73),
+            specular: Color::zero(),
+            emission: Color::zero(),
+            shininess: 0.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Pick whichever of our materials is the closest match.
+    fn build(&self, base_dir: &Path) -> Result<Box<dyn Material>, ObjError> {
+        let is_nonzero = |c: Color| c.x > 0.0 || c.y > 0.0 || c.z > 0.0;
+
+        Ok(if is_nonzero(self.emission) {
+            Box::new(DiffuseLight::new(Box::new(ColorTexture::new(
+                self.emission,
+            ))))
+        } else if self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.sheen.is_some()
+            || self.clearcoat.is_some()
+        {
+            Box::new(
+                Principled::new(self.diffuse_texture(base_dir)?)
+                    .roughness(self.roughness.unwrap_or(0.5))
+                    .metallic(self.metallic.unwrap_or(0.0))
+                    .sheen(self.sheen.unwrap_or(0.0))
+                    .clearcoat(self.clearcoat.unwrap_or(0.0))
+                    .transmission(1.0 - self.dissolve)
+                    .ior(self.glass_ior()),
+            )
+        } else if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Box::new(Dielectric::new(self.glass_ior()))
+        } else if matches!(self.illum, 3 | 5 | 8) {
+            let albedo = if is_nonzero(self.specular) {
+                self.specular
+            } else {
+                self.diffuse
+            };
+            let fuzz = 1.0 - (self.shininess / 1000.0).clamp(0.0, 1.0);
+            Box::new(Metal::new(albedo, fuzz))
+        } else {
+            Box::new(DiffuseLambertianTexture::new(
+                self.diffuse_texture(base_dir)?,
+            ))
+        })
+    }
+
+    /// `map_Kd` if there is one, otherwise `Kd`.
+    fn diffuse_texture(&self, base_dir: &Path) -> Result<Box<dyn Texture>, ObjError> {
+        Ok(match self.diffuse_map {
+            Some(ref map) => {
+                let path = base_dir.join(map);
+                let texture = ImageTexture::load_from_png(path.to_string_lossy())
+                    .map_err(|error| ObjError::Texture { path, error })?;
+                Box::new(texture)
+            }
+            None => Box::new(ColorTexture::new(self.diffuse)),
+        })
+    }
+
+    fn glass_ior(&self) -> f64 {
+        // most exporters leave Ni at 1 for glass, which would make it invisible
+        if self.ior > 1.0 {
+            self.ior
+        } else {
+            1.5
+        }
+    }
+}
+
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: line_index + 1,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, mtl)) = current.take() {
+                materials.insert(name, mtl);
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let mtl = match current {
+            Some((_, ref mut mtl)) => mtl,
+            None => continue, // nothing to apply statements to yet
+        };
+
+        match keyword {
+            "Kd" => mtl.diffuse = parse_color(&args).map_err(error)?,
+            "Ks" => mtl.specular = parse_color(&args).map_err(error)?,
+            "Ke" => mtl.emission = parse_color(&args).map_err(error)?,
+            "Ns" => mtl.shininess = parse_floats::<1>(&args).map_err(error)?[0],
+            "Ni" => mtl.ior = parse_floats::<1>(&args).map_err(error)?[0],
+            "d" => mtl.dissolve = parse_floats::<1>(&args).map_err(error)?[0],
+            "Tr" => mtl.dissolve = 1.0 - parse_floats::<1>(&args).map_err(error)?[0],
+            "Pr" => mtl.roughness = Some(parse_floats::<1>(&args).map_err(error)?[0]),
+            "Pm" => mtl.metallic = Some(parse_floats::<1>(&args).map_err(error)?[0]),
+            "Ps" => mtl.sheen = Some(parse_floats::<1>(&args).map_err(error)?[0]),
+            "Pc" => mtl.clearcoat = Some(parse_floats::<1>(&args).map_err(error)?[0]),
+            "illum" => {
+                mtl.illum = args
+                    .first()
+                    .and_then(|a| a.parse().ok())
+                    .ok_or_else(|| error("invalid illumination model".to_owned()))?
+            }
+            // texture options come before the file name, so just take the last argument
+            "map_Kd" => mtl.diffuse_map = args.last().map(PathBuf::from),
+            _ => (),
+        }
+    }
+    if let Some((name, mtl)) = current {
+        materials.insert(name, mtl);
+    }
+
+    Ok(materials)
+}
+
+/// Parse `r g b`, or a single value to use for all 3 channels.
+fn parse_color(args: &[&str]) -> Result<Color, String> {
+    if args.len() >= 3 {
+        let [r, g, b] = parse_floats::<3>(args)?;
+        Ok(Color::new(r, g, b))
+    } else {
+        let [v] = parse_floats::<1>(args)?;
+        Ok(Color::new(v, v, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_faces_and_triangulates_polygons() {
+        let source = "
+            # a unit square, as a quad
+            mtllib square.mtl
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vn 0 0 1
+            f 1/1/1 2/1/1 3/1/1 4/1/1
+            usemtl red
+            f -4//-1 -2//-1 -1//-1
+        ";
+        let contents = parse_obj(source, Path::new("square.obj")).unwrap();
+
+        assert_eq!(contents.positions.len(), 4);
+        assert_eq!(contents.faces.len(), 3);
+        assert_eq!(contents.material_names, vec!["default", "red"]);
+        assert_eq!(
+            contents.material_libraries,
+            vec![PathBuf::from("square.mtl")]
+        );
+
+        let positions = |f: &MeshFace| f.vertices.map(|v| v.position);
+        assert_eq!(positions(&contents.faces[0]), [0, 1, 2]);
+        assert_eq!(positions(&contents.faces[1]), [0, 2, 3]);
+        assert_eq!(positions(&contents.faces[2]), [0, 2, 3]);
+        assert_eq!(contents.faces[2].material, 1);
+        assert_eq!(contents.faces[2].vertices[0].uv, None);
+        assert_eq!(contents.faces[2].vertices[0].normal, Some(0));
+    }
+
+    #[test]
+    fn single_texture_coordinate_defaults_v_to_zero() {
+        let contents = parse_obj("vt 0.25\nvt 0.5 0.75 0\n", Path::new("uv.obj")).unwrap();
+        assert_eq!(contents.uvs, vec![(0.25, 0.0), (0.5, 0.75)]);
+    }
+
+    #[test]
+    fn zero_length_normal_falls_back_to_geometric_normal() {
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vn 0 0 1
+            vn 0 0 0
+            f 1//1 2//1 3//2
+            f 1//1 2//1 3//1
+        ";
+        let contents = parse_obj(source, Path::new("flat.obj")).unwrap();
+
+        assert!(contents.normals.iter().all(|n| n.x.is_finite()));
+        assert!(contents.faces[0]
+            .vertices
+            .iter()
+            .all(|v| v.normal.is_none()));
+        assert!(contents.faces[1]
+            .vertices
+            .iter()
+            .all(|v| v.normal == Some(0)));
+    }
+
+    #[test]
+    fn out_of_range_index_is_an_error() {
+        let err = parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n", Path::new("bad.obj")).unwrap_err();
+        assert!(matches!(err, ObjError::Parse { line: 3, .. }));
+    }
+
+    #[test]
+    fn parses_materials() {
+        let source = "
+            newmtl glass
+            Ni 1.45
+            d 0.1
+            newmtl gold
+            Kd 0.8 0.6 0.2
+            Ks 1.0 0.8 0.4
+            Ns 900
+            illum 3
+            newmtl textured
+            map_Kd -bm 1.0 textures/wood.png
+            newmtl car_paint
+            Kd 0.6 0.05 0.05
+            Pr 0.3
+            Pc 1
+        ";
+        let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+
+        assert_eq!(materials.len(), 4);
+        assert_eq!(materials["glass"].ior, 1.45);
+        assert_eq!(materials["glass"].dissolve, 0.1);
+        assert_eq!(materials["gold"].specular, Color::new(1.0, 0.8, 0.4));
+        assert_eq!(materials["gold"].illum, 3);
+        assert_eq!(
+            materials["textured"].diffuse_map,
+            Some(PathBuf::from("textures/wood.png"))
+        );
+        assert_eq!(materials["car_paint"].roughness, Some(0.3));
+        assert_eq!(materials["car_paint"].clearcoat, Some(1.0));
+        assert_eq!(materials["car_paint"].metallic, None);
+        let built = materials["car_paint"].build(Path::new(".")).unwrap();
+        assert!(format!("{:?}", built).starts_with("Principled"));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis, used to turn directions sampled around the z axis into directions around
+/// an arbitrary axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    /// Build a basis whose `w` axis points along `n`.
+    pub fn from_w(n: Vec3) -> Self {
+        let w = n.to_unit();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).to_unit();
+        let u = w.cross(v);
+        Self { u, v, w }
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    /// Convert a vector in this basis' coordinates to world coordinates.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Convert a vector in world coordinates to this basis' coordinates; the opposite of
+    /// [`Onb::local`].
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
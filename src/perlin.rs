@@ -4,7 +4,7 @@ use crate::{
 };
 
 #[derive(Clone, Debug)]
-pub(crate) struct Perlin {
+pub struct Perlin {
     ran_float: Vec<Vec3>,
     perm_x: Vec<i32>,
     perm_y: Vec<i32>,
@@ -13,8 +13,14 @@ pub(crate) struct Perlin {
 
 const POINT_COUNT: usize = 256;
 
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Perlin {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         let mut ran_float = Vec::with_capacity(POINT_COUNT);
         for _ in 0..POINT_COUNT {
             ran_float.push(Vec3::random(-1.0, 1.0));
@@ -37,14 +43,14 @@ impl Perlin {
         p
     }
 
-    fn permute(p: &mut Vec<i32>, point_count: usize) {
+    fn permute(p: &mut [i32], point_count: usize) {
         for i in (0..point_count).rev() {
             let target = util::random_int(0, i as i32) as usize;
             p.swap(i, target);
         }
     }
 
-    pub(crate) fn sample_turbulence(&self, p: Point3, depth: i32) -> f64 {
+    pub fn sample_turbulence(&self, p: Point3, depth: i32) -> f64 {
         let mut accum = 0.0;
         let mut temp_p = p;
         let mut weight = 1.0;
@@ -56,7 +62,7 @@ impl Perlin {
         accum.abs()
     }
 
-    pub(crate) fn sample_noise(&self, p: Point3) -> f64 {
+    pub fn sample_noise(&self, p: Point3) -> f64 {
         let mut u = p.x - p.x.floor();
         let mut v = p.y - p.y.floor();
         let mut w = p.z - p.z.floor();
@@ -0,0 +1,557 @@
+//! A principled "uber" material in the style of Disney's BRDF: one set of artist friendly
+//! parameters that blends between diffuse surfaces, plastics, metals, cloth-like sheen, lacquered
+//! clearcoats and glass.
+
+use std::f64::consts::PI;
+
+use crate::{
+    color::luminance,
+    hittable::HitRecord,
+    material::{next_material_id, Material, ScatterRecord},
+    microfacet::{self, Ggx},
+    onb::Onb,
+    ray::Ray,
+    texture::Texture,
+    util::{random_double_unit, random_pair},
+    vec3::{lerp, Color, Vec3},
+};
+
+/// Reflectance at normal incidence of a dielectric with a `specular` of 1; the default of 0.5
+/// gives 4%, which is about right for most non-metals.
+const MAX_DIELECTRIC_REFLECTANCE: f64 = 0.08;
+/// How much of the base color tints the sheen.
+const SHEEN_TINT: f64 = 0.5;
+/// The clearcoat is a thin glossy layer that's always about this rough.
+const CLEARCOAT_ROUGHNESS: f64 = 0.25;
+/// Reflectance at normal incidence of the clearcoat (like a polyurethane lacquer).
+const CLEARCOAT_REFLECTANCE: f64 = 0.04;
+
+/// A scalar parameter of a [`Principled`] material.
+#[derive(Debug)]
+pub enum Parameter {
+    Value(f64),
+    /// Read from a texture; colored textures use their luminance
+    Texture(Box<dyn Texture>),
+}
+
+impl Parameter {
+    fn value(&self, rec: &HitRecord) -> f64 {
+        match self {
+            Parameter::Value(value) => *value,
+            Parameter::Texture(texture) => luminance(texture.value(rec.u, rec.v, rec.p)),
+        }
+    }
+}
+
+impl From<f64> for Parameter {
+    fn from(value: f64) -> Self {
+        Parameter::Value(value)
+    }
+}
+
+impl From<Box<dyn Texture>> for Parameter {
+    fn from(texture: Box<dyn Texture>) -> Self {
+        Parameter::Texture(texture)
+    }
+}
+
+/// One material for most surfaces, with parameters that (apart from the index of refraction) go
+/// from 0 to 1 and can be blended freely.
+#[derive(Debug)]
+pub struct Principled {
+    base_color: Box<dyn Texture>,
+    /// From dielectric to metal, which reflects the base color
+    metallic: Parameter,
+    /// From polished to very rough
+    roughness: Parameter,
+    /// Strength of a dielectric's reflections
+    specular: Parameter,
+    /// Tints a dielectric's reflections towards the base color
+    specular_tint: Parameter,
+    /// A soft glow at grazing angles, like cloth
+    sheen: Parameter,
+    /// A second, glossy coat on top
+    clearcoat: Parameter,
+    /// From opaque to glass, tinted by the base color
+    transmission: Parameter,
+    /// Index of refraction, which only matters for transmission
+    ior: Parameter,
+    material_id: u32,
+}
+
+impl Principled {
+    /// A smooth-ish plastic with `base_color`; the other parameters default to 0, apart from
+    /// roughness and specular (0.5) and the index of refraction (1.5).
+    pub fn new(base_color: Box<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: 0.0.into(),
+            roughness: 0.5.into(),
+            specular: 0.5.into(),
+            specular_tint: 0.0.into(),
+            sheen: 0.0.into(),
+            clearcoat: 0.0.into(),
+            transmission: 0.0.into(),
+            ior: 1.5.into(),
+            material_id: next_material_id(),
+        }
+    }
+
+    pub fn metallic(self, metallic: impl Into<Parameter>) -> Self {
+        Self {
+            metallic: metallic.into(),
+            ..self
+        }
+    }
+
+    pub fn roughness(self, roughness: impl Into<Parameter>) -> Self {
+        Self {
+            roughness: roughness.into(),
+            ..self
+        }
+    }
+
+    pub fn specular(self, specular: impl Into<Parameter>) -> Self {
+        Self {
+            specular: specular.into(),
+            ..self
+        }
+    }
+
+    pub fn specular_tint(self, specular_tint: impl Into<Parameter>) -> Self {
+        Self {
+            specular_tint: specular_tint.into(),
+            ..self
+        }
+    }
+
+    pub fn sheen(self, sheen: impl Into<Parameter>) -> Self {
+        Self {
+            sheen: sheen.into(),
+            ..self
+        }
+    }
+
+    pub fn clearcoat(self, clearcoat: impl Into<Parameter>) -> Self {
+        Self {
+            clearcoat: clearcoat.into(),
+            ..self
+        }
+    }
+
+    pub fn transmission(self, transmission: impl Into<Parameter>) -> Self {
+        Self {
+            transmission: transmission.into(),
+            ..self
+        }
+    }
+
+    pub fn ior(self, ior: impl Into<Parameter>) -> Self {
+        Self {
+            ior: ior.into(),
+            ..self
+        }
+    }
+
+    /// The parameters at `rec`, seen from along `r_in`.
+    fn at(&self, r_in: Ray, rec: &HitRecord) -> Surface {
+        let unit = |parameter: &Parameter| parameter.value(rec).clamp(0.0, 1.0);
+        let onb = Onb::from_w(rec.normal);
+        let transmission = unit(&self.transmission);
+        Surface {
+            base_color: self.base_color.value(rec.u, rec.v, rec.p),
+            metallic: unit(&self.metallic),
+            roughness: unit(&self.roughness),
+            specular: unit(&self.specular),
+            specular_tint: unit(&self.specular_tint),
+            sheen: unit(&self.sheen),
+            clearcoat: unit(&self.clearcoat),
+            transmission,
+            ior: self.ior.value(rec).max(1.0),
+            // normals face the ray, so the back of a surface is the inside of a transmissive object
+            inside: !rec.front_face && transmission > 0.0,
+            wo: onb.to_local(-r_in.direction().to_unit()),
+            onb,
+        }
+    }
+}
+
+/// Which part of the material a scattered ray was picked with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+const LOBES: [Lobe; 4] = [
+    Lobe::Diffuse,
+    Lobe::Specular,
+    Lobe::Clearcoat,
+    Lobe::Transmission,
+];
+
+/// A [`Principled`] material's parameters at one point, with directions in the frame around the
+/// normal.
+#[derive(Debug)]
+struct Surface {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    ior: f64,
+    inside: bool,
+    onb: Onb,
+    /// Back along the incoming ray
+    wo: Vec3,
+}
+
+impl Surface {
+    /// The base color with its brightness taken out.
+    fn tint(&self) -> Color {
+        let brightness = luminance(self.base_color);
+        if brightness > 0.0 {
+            self.base_color / brightness
+        } else {
+            Color::one()
+        }
+    }
+
+    /// Reflectance of the specular lobe at normal incidence.
+    fn specular_color(&self) -> Color {
+        let dielectric = MAX_DIELECTRIC_REFLECTANCE
+            * self.specular
+            * lerp(self.specular_tint, Color::one(), self.tint());
+        lerp(self.metallic, dielectric, self.base_color)
+    }
+
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    /// How likely each of [`LOBES`] is to be sampled: roughly how much light they reflect.
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let weights = if self.inside {
+            [0.0, 0.0, 0.0, 1.0]
+        } else {
+            [
+                self.diffuse_weight(),
+                0.25 + 0.75 * self.metallic,
+                0.25 * self.clearcoat,
+                (1.0 - self.metallic) * self.transmission,
+            ]
+        };
+        let total: f64 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    fn specular_distribution(&self) -> Ggx {
+        Ggx::from_roughness(self.roughness)
+    }
+
+    fn clearcoat_distribution(&self) -> Ggx {
+        Ggx::from_roughness(CLEARCOAT_ROUGHNESS)
+    }
+
+    /// The BSDF times the cosine of `wi`, for the lobes that reflect light diffusely or glossily
+    /// (the transmission lobe is treated as specular, so it's left out).
+    fn reflection(&self, wi: Vec3) -> Color {
+        let wo = self.wo;
+        if self.inside || wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zero();
+        }
+        let h = (wo + wi).to_unit();
+        let cos_d = wi.dot(h);
+
+        // Burley's diffuse, which gets darker at grazing angles on smooth surfaces and brighter
+        // on rough ones
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = |cos: f64| 1.0 + (fd90 - 1.0) * schlick_weight(cos);
+        let diffuse = self.base_color * retro(wi.z) * retro(wo.z) / PI;
+        let sheen =
+            self.sheen * lerp(SHEEN_TINT, Color::one(), self.tint()) * schlick_weight(cos_d);
+        let diffuse = (diffuse + sheen) * self.diffuse_weight() * wi.z;
+
+        // the cosine of `wi` cancels with the one in the denominator of the microfacet BSDFs
+        let ggx = self.specular_distribution();
+        let f0 = self.specular_color();
+        let fresnel = f0 + (Color::one() - f0) * schlick_weight(cos_d);
+        let specular = fresnel * ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z);
+
+        let clearcoat = if self.clearcoat > 0.0 {
+            let ggx = self.clearcoat_distribution();
+            let fresnel =
+                CLEARCOAT_REFLECTANCE + (1.0 - CLEARCOAT_REFLECTANCE) * schlick_weight(cos_d);
+            0.25 * self.clearcoat * fresnel * ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z)
+        } else {
+            0.0
+        };
+
+        diffuse + specular + Color::one() * clearcoat
+    }
+
+    /// Probability density of scattering towards `wi` with one of the reflecting lobes.
+    fn reflection_pdf(&self, wi: Vec3) -> f64 {
+        if self.inside || self.wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, clearcoat, _] = self.lobe_probabilities();
+        diffuse * wi.z / PI
+            + specular * self.specular_distribution().reflection_pdf(self.wo, wi)
+            + clearcoat * self.clearcoat_distribution().reflection_pdf(self.wo, wi)
+    }
+
+    /// Refract through a facet picked from the visible normals, returning the new direction and
+    /// the weight of the sample. Going in, reflections are left to the specular lobe; coming out,
+    /// the light is reflected back in as often as the Fresnel equations say.
+    fn sample_transmission(&self) -> Option<(Vec3, Color)> {
+        let ggx = self.specular_distribution();
+        let h = ggx.sample_visible_normal(self.wo, random_pair());
+        let cos_o = self.wo.dot(h);
+        // the relative index of refraction, from the side the light is going to
+        let eta = if self.inside {
+            1.0 / self.ior
+        } else {
+            self.ior
+        };
+        let fresnel = fresnel_dielectric(cos_o, eta);
+
+        let reflected = self.inside && random_double_unit() < fresnel;
+        let wi = if reflected {
+            microfacet::reflect(self.wo, h)
+        } else {
+            Vec3::refract(-self.wo, h, 1.0 / eta)
+        };
+        // the facet can send the light to the wrong side of the surface, where it's lost
+        if cos_o <= 0.0 || (wi.z > 0.0) != reflected {
+            return None;
+        }
+        let weight = if self.inside {
+            Color::one()
+        } else {
+            (1.0 - fresnel) * self.base_color
+        };
+        Some((wi, weight * ggx.g(self.wo, wi) / ggx.g1(self.wo)))
+    }
+}
+
+/// How much Schlick's approximation to the Fresnel equations moves towards full reflection at
+/// an angle whose cosine is `cos_theta`.
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Fraction of unpolarized light reflected by a dielectric when arriving at an angle whose cosine
+/// is `cos_theta`, where `eta` is the ratio of the indices of refraction on the far and near side.
+fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        self.scatter_with_pdf(r_in, rec)
+            .map(|scatter| (scatter.attenuation, scatter.scattered))
+    }
+
+    fn scatter_with_pdf(&self, r_in: Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let surface = self.at(r_in, rec);
+        if surface.wo.z <= 0.0 {
+            return None;
+        }
+        let probabilities = surface.lobe_probabilities();
+        let mut pick = random_double_unit();
+        let lobe = LOBES
+            .into_iter()
+            .zip(probabilities)
+            .find(|&(_, probability)| {
+                pick -= probability;
+                pick < 0.0
+            })
+            .map_or(Lobe::Specular, |(lobe, _)| lobe);
+
+        let scattered = |wi: Vec3| r_in.bounce(rec.p, surface.onb.local(wi));
+        let wi = match lobe {
+            Lobe::Transmission => {
+                // transmission is specular enough that it isn't combined with light sampling
+                let (wi, weight) = surface.sample_transmission()?;
+                return Some(ScatterRecord {
+                    scattered: scattered(wi),
+                    attenuation: weight * (1.0 - surface.metallic) * surface.transmission
+                        / probabilities[3],
+                    pdf: None,
+                });
+            }
+            Lobe::Diffuse => {
+                let direction = rec.normal + Vec3::random_unit_vector();
+                if direction.near_zero() {
+                    return None;
+                }
+                surface.onb.to_local(direction.to_unit())
+            }
+            Lobe::Specular => {
+                let ggx = surface.specular_distribution();
+                microfacet::reflect(
+                    surface.wo,
+                    ggx.sample_visible_normal(surface.wo, random_pair()),
+                )
+            }
+            Lobe::Clearcoat => {
+                let ggx = surface.clearcoat_distribution();
+                microfacet::reflect(
+                    surface.wo,
+                    ggx.sample_visible_normal(surface.wo, random_pair()),
+                )
+            }
+        };
+
+        let pdf = surface.reflection_pdf(wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered: scattered(wi),
+            attenuation: surface.reflection(wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn scattering(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let surface = self.at(r_in, rec);
+        surface.reflection(surface.onb.to_local(direction.to_unit()))
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let surface = self.at(r_in, rec);
+        surface.reflection_pdf(surface.onb.to_local(direction.to_unit()))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.value(rec.u, rec.v, rec.p)
+    }
+
+    fn material_id(&self) -> u32 {
+        self.material_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{texture::ColorTexture, util, vec3::Point3};
+
+    fn white() -> Box<dyn Texture> {
+        Box::new(ColorTexture::from_rgb(1.0, 1.0, 1.0))
+    }
+
+    /// A ray arriving at 45 degrees at the origin, with the surface facing up (or down).
+    fn hit(material: &Principled, from_above: bool) -> (Ray, HitRecord<'_>) {
+        let y = if from_above { 1.0 } else { -1.0 };
+        let r_in = Ray::new(Point3::new(-1.0, y, 0.0), Vec3::new(1.0, -y, 0.0), None);
+        let rec = HitRecord::new(1.0, (0.0, 0.0), r_in, Vec3::new(0.0, 1.0, 0.0), material);
+        (r_in, rec)
+    }
+
+    #[test]
+    fn samples_match_the_bsdf() {
+        let material = Principled::new(Box::new(ColorTexture::from_rgb(0.8, 0.3, 0.1)))
+            .metallic(0.3)
+            .roughness(0.4)
+            .specular_tint(0.5)
+            .sheen(0.5)
+            .clearcoat(1.0);
+        let (r_in, rec) = hit(&material, true);
+        let samples = 200_000;
+
+        let sampled = util::with_seed(3, || {
+            (0..samples)
+                .filter_map(|_| {
+                    let scatter = material.scatter_with_pdf(r_in, &rec)?;
+                    let direction = scatter.scattered.direction();
+                    let pdf = material.scattering_pdf(r_in, &rec, direction);
+                    assert!((scatter.pdf.unwrap() - pdf).abs() < 1e-6 * pdf.max(1.0));
+                    let expected = material.scattering(r_in, &rec, direction) / pdf;
+                    assert!((scatter.attenuation - expected).length() < 1e-6);
+                    Some(scatter.attenuation)
+                })
+                .fold(Color::zero(), |a, b| a + b)
+        }) / samples as f64;
+        let integrated = util::with_seed(4, || {
+            (0..samples)
+                .map(|_| {
+                    let direction = Vec3::random_in_hemisphere(rec.normal);
+                    material.scattering(r_in, &rec, direction)
+                })
+                .fold(Color::zero(), |a, b| a + b)
+        }) * 2.0
+            * PI
+            / samples as f64;
+        assert!(
+            (sampled - integrated).length() < 0.02,
+            "{} != {}",
+            sampled,
+            integrated
+        );
+    }
+
+    #[test]
+    fn smooth_metal_is_a_mirror() {
+        let material = Principled::new(white()).metallic(1.0).roughness(0.0);
+        let (r_in, rec) = hit(&material, true);
+        let average = util::with_seed(5, || {
+            (0..1000)
+                .map(|_| {
+                    let scatter = material.scatter_with_pdf(r_in, &rec).unwrap();
+                    assert!((scatter.attenuation - Color::one()).length() < 0.01);
+                    scatter.scattered.direction().to_unit()
+                })
+                .fold(Vec3::zero(), |a, b| a + b)
+        }) / 1000.0;
+        // even the smoothest facets spread the reflections a little
+        assert!((average - Vec3::new(1.0, 1.0, 0.0).to_unit()).length() < 0.01);
+    }
+
+    #[test]
+    fn clear_glass_keeps_the_light_it_refracts_or_reflects() {
+        let material = Principled::new(white()).transmission(1.0).roughness(0.05);
+        let samples = 100_000;
+        for from_above in [true, false] {
+            let (r_in, rec) = hit(&material, from_above);
+            let (total, through) = util::with_seed(6, || {
+                (0..samples)
+                    .filter_map(|_| material.scatter_with_pdf(r_in, &rec))
+                    .fold((Color::zero(), 0), |(total, through), scatter| {
+                        let refracted = scatter.scattered.direction().dot(rec.normal) < 0.0;
+                        (total + scatter.attenuation, through + refracted as usize)
+                    })
+            });
+            let total = total / samples as f64;
+            assert!(
+                (total - Color::one()).length() < 0.05,
+                "{} of the light is kept going {}",
+                total,
+                if from_above { "in" } else { "out" }
+            );
+            // at 45 degrees going out of glass, all the light is reflected back in
+            let through = through as f64 / samples as f64;
+            if from_above {
+                assert!(through > 0.7, "{} went through", through);
+            } else {
+                assert!(through < 0.01, "{} went through", through);
+            }
+        }
+    }
+}
@@ -1,34 +1,227 @@
-use crate::vec3::{Point3, Vec3};
+use crate::vec3::{Color, Point3, Vec3};
+
+/// How many nested media a ray can be inside at once; entering any more is ignored.
+pub const MAX_NESTED_MEDIA: usize = 4;
 
 #[derive(Debug, Copy, Clone, Default)]
-pub(crate) struct Ray {
+pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
     pub tm: f64,
+    /// What the ray is travelling through
+    pub media: MediaStack,
+    /// In nanometres, when rendering spectrally; single precision keeps rays small
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
-    pub(crate) fn new(orig: Point3, dir: Vec3, time: Option<f64>) -> Self {
+    pub fn new(orig: Point3, dir: Vec3, time: Option<f64>) -> Self {
         Self {
             orig,
             dir,
             tm: time.unwrap_or(0.0),
+            media: MediaStack::default(),
+            wavelength: None,
+        }
+    }
+
+    /// The same ray, bending like light of `wavelength` nanometres.
+    pub fn with_wavelength(self, wavelength: f64) -> Self {
+        Self {
+            wavelength: Some(wavelength as f32),
+            ..self
         }
     }
 
-    pub(crate) fn origin(&self) -> Point3 {
+    /// A ray leaving `orig` towards `dir` at the same time as this one, through the same media and
+    /// with the same wavelength.
+    pub fn bounce(&self, orig: Point3, dir: Vec3) -> Self {
+        Self { orig, dir, ..*self }
+    }
+
+    pub fn origin(&self) -> Point3 {
         self.orig
     }
 
-    pub(crate) fn direction(&self) -> Vec3 {
+    pub fn direction(&self) -> Vec3 {
         self.dir
     }
 
-    pub(crate) fn time(&self) -> f64 {
+    pub fn time(&self) -> f64 {
         self.tm
     }
 
-    pub(crate) fn at(&self, t: f64) -> Point3 {
+    /// The wavelength of light that decides which way the ray bends, when rendering spectrally.
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength.map(f64::from)
+    }
+
+    pub fn at(&self, t: f64) -> Point3 {
         self.orig + self.dir * t
     }
 }
+
+/// The inside of a [`crate::material::Dielectric`], which a ray has entered. Every ray carries
+/// a few of these, so they're stored in single precision to keep rays small.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Medium {
+    id: u32,
+    priority: i32,
+    ior: f32,
+    absorption: [f32; 3],
+}
+
+impl Medium {
+    pub fn new(id: u32, priority: i32, ior: f64, absorption: Color) -> Self {
+        Self {
+            id,
+            priority,
+            ior: ior as f32,
+            absorption: [
+                absorption.x as f32,
+                absorption.y as f32,
+                absorption.z as f32,
+            ],
+        }
+    }
+
+    /// Which dielectric this is the inside of
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Where media overlap, the one with the highest priority is what's really there
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Index of refraction
+    pub fn ior(&self) -> f64 {
+        self.ior as f64
+    }
+
+    /// Fraction of each color absorbed per unit distance travelled
+    pub fn absorption(&self) -> Color {
+        let [r, g, b] = self.absorption;
+        Color::new(r as f64, g as f64, b as f64)
+    }
+}
+
+/// The media a ray is inside, in the order it entered them. Outside all of them is air.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MediaStack {
+    /// Only the first `len` are in use
+    media: [Medium; MAX_NESTED_MEDIA],
+    len: u8,
+}
+
+impl PartialEq for MediaStack {
+    fn eq(&self, other: &Self) -> bool {
+        self.entered() == other.entered()
+    }
+}
+
+impl MediaStack {
+    fn entered(&self) -> &[Medium] {
+        &self.media[..self.len as usize]
+    }
+
+    /// The medium the ray is really in: the one with the highest priority, or the most recently
+    /// entered of those that share it.
+    pub fn current(&self) -> Option<&Medium> {
+        // max_by_key picks the last of equal maximums
+        self.entered().iter().max_by_key(|medium| medium.priority)
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.entered().iter().any(|medium| medium.id == id)
+    }
+
+    /// Index of refraction of the current medium.
+    pub fn ior(&self) -> f64 {
+        self.current().map_or(1.0, |medium| medium.ior())
+    }
+
+    /// The stack after entering `medium`.
+    pub fn with(mut self, medium: Medium) -> Self {
+        if (self.len as usize) < MAX_NESTED_MEDIA {
+            self.media[self.len as usize] = medium;
+            self.len += 1;
+        }
+        self
+    }
+
+    /// The stack after leaving the medium `id` (the most recently entered, if there's more than
+    /// one); unchanged if the ray isn't in it.
+    pub fn without(mut self, id: u32) -> Self {
+        if let Some(i) = self.entered().iter().rposition(|medium| medium.id == id) {
+            self.media.copy_within(i + 1..self.len as usize, i);
+            self.len -= 1;
+        }
+        self
+    }
+
+    /// Fraction of each color that makes it `distance` through the current medium (by the
+    /// Beer-Lambert law).
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let absorption = match self.current() {
+            Some(medium) => medium.absorption(),
+            None => return Color::one(),
+        };
+        let transmit = |absorption: f64| {
+            if absorption > 0.0 {
+                (-absorption * distance).exp()
+            } else {
+                1.0
+            }
+        };
+        Color::new(
+            transmit(absorption.x),
+            transmit(absorption.y),
+            transmit(absorption.z),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medium(id: u32, priority: i32) -> Medium {
+        Medium::new(id, priority, 1.0 + id as f64 / 4.0, Color::zero())
+    }
+
+    #[test]
+    fn highest_priority_medium_is_current() {
+        let media = MediaStack::default();
+        assert_eq!(media.ior(), 1.0);
+
+        let media = media.with(medium(1, 1)).with(medium(2, 0));
+        assert_eq!(media.current().unwrap().id(), 1);
+        // ties go to the one entered last
+        let media = media.with(medium(3, 1));
+        assert_eq!(media.current().unwrap().id(), 3);
+        assert_eq!(media.without(3).current().unwrap().id(), 1);
+        assert_eq!(media.without(1).without(3).ior(), 1.5);
+        // leaving something the ray isn't in changes nothing
+        assert_eq!(media.without(7), media);
+
+        let full = media.with(medium(4, 0)).with(medium(5, 9));
+        assert!(full.contains(4) && !full.contains(5));
+    }
+
+    #[test]
+    fn absorption_follows_the_beer_lambert_law() {
+        let absorbing = Medium::new(1, 0, 1.5, Color::new(0.0, 1.0, 2.0));
+        let media = MediaStack::default().with(absorbing);
+        assert_eq!(
+            media.transmittance(0.5),
+            Color::new(1.0, (-0.5f64).exp(), (-1.0f64).exp())
+        );
+        assert_eq!(
+            media.transmittance(f64::INFINITY),
+            Color::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(MediaStack::default().transmittance(1.0), Color::one());
+    }
+}
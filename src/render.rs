@@ -0,0 +1,1234 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rgb::{RGB, RGB8};
+
+use crate::{
+    aov::{self, Aov, AovLayer, AovSample, AovSums, EMISSION_BOUNCES},
+    camera::Camera,
+    color::{color_to_rgb, luminance, rgb_to_color},
+    denoise::{Denoising, GUIDE_AOVS},
+    hittable::{HitRecord, Hittable},
+    image_file::ExrPrecision,
+    ray::Ray,
+    sampler::{self, SamplerKind},
+    scenes::RenderScene,
+    sky::SkySettings,
+    spectrum::{Spectrum, Wavelengths},
+    tone_map::ToneMapping,
+    util,
+    vec3::Color,
+    World,
+};
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)] // if we add new fields, give them default values when deserializing old state
+pub struct RenderConfig {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: u32,
+    pub render_mode: RayColorMode,
+    pub scene: RenderScene,
+    pub output_filename: String,
+    pub display_actual_size: bool,
+    /// Render the whole image one sample per pixel at a time, instead of line by line
+    pub progressive: bool,
+    /// Take more samples in noisy pixels than in smooth ones, instead of taking
+    /// `samples_per_pixel` samples everywhere
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// Where the random numbers for building the scene and sampling pixels come from; rendering
+    /// the same config with the same seed gives the same image
+    pub seed: u64,
+    /// How the random numbers for each pixel's samples are spread out
+    pub sampler: SamplerKind,
+    /// How the rendered radiance is turned into displayable colors; changing this doesn't need a
+    /// re-render
+    pub tone_mapping: ToneMapping,
+    /// Precision of the channels when `output_filename` is an OpenEXR file
+    pub exr_precision: ExrPrecision,
+    /// Auxiliary images to render alongside the beauty image
+    pub aovs: Vec<Aov>,
+    /// Filter the noise out of the finished image, guided by the albedo, normal and depth AOVs
+    /// (which are rendered for the filter even if `aovs` doesn't ask for them)
+    pub denoising: Option<Denoising>,
+    /// Light the scene with a procedural sun and sky instead of its own background
+    pub sky: Option<SkySettings>,
+}
+
+impl RenderConfig {
+    pub fn image_pixel_count(&self) -> usize {
+        self.image_width * self.image_height
+    }
+
+    /// The most samples that any pixel will get.
+    pub fn max_samples_per_pixel(&self) -> u32 {
+        match self.adaptive_sampling {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples_per_pixel,
+        }
+    }
+
+    /// The AOVs that need rendering: the ones asked for, plus any that the denoiser needs.
+    pub fn rendered_aovs(&self) -> Vec<Aov> {
+        let mut aovs = self.aovs.clone();
+        if self.denoising.is_some() {
+            for aov in GUIDE_AOVS {
+                if !aovs.contains(&aov) {
+                    aovs.push(aov);
+                }
+            }
+        }
+        aovs
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width = 400;
+        Self {
+            image_width,
+            image_height: (image_width as f64 / aspect_ratio) as usize,
+            samples_per_pixel: 100,
+            render_mode: { RayColorMode::Material { depth: 50 } },
+            scene: Default::default(),
+            output_filename: "target/output.png".to_owned(),
+            display_actual_size: true,
+            progressive: false,
+            adaptive_sampling: None,
+            seed: 0,
+            sampler: SamplerKind::default(),
+            tone_mapping: ToneMapping::default(),
+            exr_precision: ExrPrecision::default(),
+            aovs: Vec::new(),
+            denoising: None,
+            sky: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSampling {
+    /// A pixel is done once the standard error of its mean brightness is less than this fraction
+    /// of the brightness
+    pub noise_threshold: f64,
+    /// Samples every pixel gets before its noise is measured
+    pub min_samples: u32,
+    /// Samples a pixel gets even if it's still noisy
+    pub max_samples: u32,
+    /// Show how many samples each pixel took (black for none, white for `max_samples`) instead of
+    /// the rendered image
+    pub show_sample_counts: bool,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            noise_threshold: 0.05,
+            min_samples: 16,
+            max_samples: 400,
+            show_sample_counts: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum RayColorMode {
+    /// shade as single purely matte color
+    BlockColor { color: Color },
+    /// shade by assuming the normal is the color
+    ShadeNormal,
+    /// shade based on distance from camera
+    Depth { max_t: f64 },
+    /// use the assigned materials of each hittable object
+    Material { depth: i32 },
+    /// use the assigned materials, but also sample the lights (and any environment map) directly
+    /// and combine that with the materials' scattering using multiple importance sampling
+    ImportanceSampled { depth: i32 },
+    /// like `Material`, but trace light a few wavelengths at a time rather than as red, green and
+    /// blue, so that dispersive dielectrics split light into its colors
+    Spectral { depth: i32 },
+}
+
+/// A high dynamic range image of the linear radiance reaching each pixel, stored row by row
+/// starting from the top left corner.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<RGB<f32>>,
+    /// Layers of the auxiliary images that were rendered along with it, if any
+    pub aovs: Vec<AovLayer>,
+}
+
+impl HdrImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![RGB::new(0.0, 0.0, 0.0); width * height],
+            aovs: Vec::new(),
+        }
+    }
+
+    /// Copy the one row image `line` (like [`render_line`] returns) into row `y`, where row 0 is
+    /// the top of the image, adding any AOV layers that `line` has and this image doesn't yet.
+    pub fn set_row(&mut self, y: usize, line: &HdrImage) {
+        assert_eq!(line.width, self.width);
+        assert_eq!(line.height, 1);
+        self.pixels[y * self.width..(y + 1) * self.width].copy_from_slice(&line.pixels);
+
+        for line_layer in &line.aovs {
+            let index = match self.aovs.iter().position(|l| l.name == line_layer.name) {
+                Some(index) => index,
+                None => {
+                    let pixel_count = self.width * self.height;
+                    self.aovs
+                        .push(AovLayer::empty_like(line_layer, pixel_count));
+                    self.aovs.len() - 1
+                }
+            };
+            let layer = &mut self.aovs[index];
+            let row_len = self.width * layer.channels.len();
+            layer.values[y * row_len..(y + 1) * row_len].copy_from_slice(&line_layer.values);
+        }
+    }
+
+    /// Get the pixel at column `x` and row `y`, where row 0 is the top of the image.
+    pub fn pixel(&self, x: usize, y: usize) -> RGB<f32> {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Make a displayable 8 bit image from this one.
+    pub fn tone_map(&self, tone_mapping: &ToneMapping) -> ImageBuffer {
+        ImageBuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|&p| tone_mapping.to_rgb8(rgb_to_color(p)))
+                .collect(),
+        }
+    }
+}
+
+/// An 8 bit sRGB image, stored row by row starting from the top left corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<RGB8>,
+}
+
+impl ImageBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![RGB8 { r: 0, g: 0, b: 0 }; width * height],
+        }
+    }
+
+    /// Get the pixel at column `x` and row `y`, where row 0 is the top of the image.
+    pub fn pixel(&self, x: usize, y: usize) -> RGB8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, path: P) -> lodepng::Result<()> {
+        lodepng::encode_file(
+            path,
+            &self.pixels,
+            self.width,
+            self.height,
+            lodepng::ColorType::RGB,
+            8,
+        )
+    }
+}
+
+/// The samples taken so far for one pixel.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PixelSamples {
+    sum: Color,
+    /// Sum of the squared luminance of each sample, for estimating how noisy the pixel is
+    sum_luminance_squared: f64,
+    count: u32,
+    /// Only allocated when AOVs are being rendered, since they take a lot more space
+    aovs: Option<Box<AovSums>>,
+}
+
+impl PixelSamples {
+    fn add(&mut self, sample: Color) {
+        self.sum += sample;
+        self.sum_luminance_squared += luminance(sample).powi(2);
+        self.count += 1;
+    }
+
+    /// Add the AOVs of the sample that was just added with [`PixelSamples::add`].
+    fn add_aovs(&mut self, sample: &AovSample) {
+        self.aovs.get_or_insert_with(Default::default).add(sample);
+    }
+
+    fn average_aovs(&self) -> AovSample {
+        let average = match &self.aovs {
+            Some(aovs) => aovs.average(self.count),
+            None => AovSample::default(),
+        };
+        AovSample {
+            variance: self.mean_variance(),
+            ..average
+        }
+    }
+
+    /// Whether `config` calls for this pixel to be sampled again.
+    fn wants_more(&self, config: &RenderConfig) -> bool {
+        let adaptive = match config.adaptive_sampling {
+            Some(adaptive) => adaptive,
+            None => return self.count < config.samples_per_pixel,
+        };
+        if self.count >= adaptive.max_samples {
+            false
+        } else if self.count < adaptive.min_samples.max(2) {
+            true
+        } else {
+            self.relative_error() > adaptive.noise_threshold
+        }
+    }
+
+    /// Variance of the mean luminance (the square of its standard error), which is infinite until
+    /// there are enough samples to estimate it.
+    fn mean_variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = luminance(self.sum) / n;
+        let variance = ((self.sum_luminance_squared - n * mean * mean) / (n - 1.0)).max(0.0);
+        variance / n
+    }
+
+    /// Standard error of the mean luminance, relative to the mean luminance.
+    fn relative_error(&self) -> f64 {
+        let mean = luminance(self.sum) / self.count as f64;
+        self.mean_variance().sqrt() / mean.max(1e-3)
+    }
+
+    /// The pixel's average radiance (or its sample count, if `config` asks for that).
+    fn to_rgb(&self, config: &RenderConfig) -> RGB<f32> {
+        match config.adaptive_sampling {
+            Some(adaptive) if adaptive.show_sample_counts => {
+                let fraction = self.count as f32 / adaptive.max_samples.max(1) as f32;
+                let level = fraction.clamp(0.0, 1.0);
+                RGB::new(level, level, level)
+            }
+            _ => color_to_rgb(self.sum / self.count.max(1) as f64),
+        }
+    }
+}
+
+/// A running total of the samples taken for every pixel of an image, so that an image can be made
+/// from however many passes have been rendered so far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccumulationBuffer {
+    pub width: usize,
+    pub height: usize,
+    /// Stored row by row starting from the top left corner
+    pixels: Vec<PixelSamples>,
+    passes: u32,
+    active_pixels: usize,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![PixelSamples::default(); width * height],
+            passes: 0,
+            active_pixels: width * height,
+        }
+    }
+
+    /// Number of passes rendered so far; with adaptive sampling, some pixels will have been
+    /// skipped by later passes.
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    /// Number of pixels that still wanted more samples after the last pass.
+    pub fn active_pixels(&self) -> usize {
+        self.active_pixels
+    }
+
+    /// Average the samples taken so far into an image (or show how many samples were taken, if
+    /// `config` asks for that).
+    pub fn to_image(&self, config: &RenderConfig) -> HdrImage {
+        HdrImage {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|p| p.to_rgb(config)).collect(),
+            aovs: aov_layers(config, &self.pixels),
+        }
+    }
+}
+
+/// The AOV layers that `config` asks for, averaged from `pixels`.
+fn aov_layers(config: &RenderConfig, pixels: &[PixelSamples]) -> Vec<AovLayer> {
+    let aovs = config.rendered_aovs();
+    if aovs.is_empty() {
+        return Vec::new();
+    }
+    let samples = pixels
+        .iter()
+        .map(PixelSamples::average_aovs)
+        .collect::<Vec<_>>();
+    aov::layers(&aovs, &samples)
+}
+
+/// How far along a render is; passed to the callback of [`render_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderProgress {
+    pub lines_completed: usize,
+    pub total_lines: usize,
+}
+
+impl RenderProgress {
+    pub fn fraction(&self) -> f32 {
+        self.lines_completed as f32 / self.total_lines as f32
+    }
+}
+
+pub fn ray_color(r: Ray, world: &World, mode: RayColorMode) -> Color {
+    trace(r, world, mode, PathState::camera(None))
+}
+
+/// How far a path has got from the camera, for filling in the AOVs from what the path finds: the
+/// surface AOVs from its first hit, and the emission AOV's bounces from the light it carries back.
+struct PathState<'a> {
+    bounce: usize,
+    /// How much of the light arriving along the path at this bounce makes it back to the camera
+    throughput: Color,
+    aovs: Option<&'a mut AovSample>,
+}
+
+impl<'a> PathState<'a> {
+    fn camera(aovs: Option<&'a mut AovSample>) -> Self {
+        Self {
+            bounce: 0,
+            throughput: Color::one(),
+            aovs,
+        }
+    }
+
+    /// Note that the path went along `r` to `hit` (or nothing) at this bounce.
+    fn hit(&mut self, r: Ray, hit: Option<&HitRecord>) {
+        if let (0, Some(aovs)) = (self.bounce, self.aovs.as_deref_mut()) {
+            aovs.record_first_hit(r, hit);
+        }
+    }
+
+    /// Note that `light` arrives at this bounce after being emitted `bounces_later`.
+    fn record(&mut self, bounces_later: usize, light: Color) {
+        if let Some(aovs) = self.aovs.as_deref_mut() {
+            let bounce = (self.bounce + bounces_later).min(EMISSION_BOUNCES - 1);
+            aovs.emission[bounce] += self.throughput * light;
+        }
+    }
+
+    /// The state of the path after scattering with `attenuation` at this bounce.
+    fn next(&mut self, attenuation: Color) -> PathState<'_> {
+        PathState {
+            bounce: self.bounce + 1,
+            throughput: self.throughput * attenuation,
+            aovs: self.aovs.as_deref_mut(),
+        }
+    }
+}
+
+/// Color `r` the way `mode` asks for, adding the light that reaches the camera to `path`.
+fn trace(r: Ray, world: &World, mode: RayColorMode, mut path: PathState) -> Color {
+    match mode {
+        RayColorMode::Material { depth } => return material_color(r, world, depth, path),
+        RayColorMode::ImportanceSampled { depth } => {
+            return importance_sampled_color(r, world, depth, None, path)
+        }
+        RayColorMode::Spectral { depth } => return spectral_color(r, world, depth, path),
+        _ => (),
+    }
+
+    sampler::start_bounce();
+    let hit = world.node.hit(r, 0.001, f64::INFINITY);
+    path.hit(r, hit.as_ref());
+    let color = match hit {
+        Some(rec) => match mode {
+            RayColorMode::BlockColor { color } => color,
+            RayColorMode::ShadeNormal => 0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0)),
+            RayColorMode::Depth { max_t } => Color::one() - rec.t / max_t * Color::one(),
+            _ => unreachable!("handled above"),
+        },
+        None => world.environment.color(r.direction()),
+    };
+    path.record(0, color);
+    color
+}
+
+/// Path trace `r`, using only the materials' own scattering.
+fn material_color(r: Ray, world: &World, depth: i32, mut path: PathState) -> Color {
+    if depth <= 0 {
+        return Color::zero();
+    }
+
+    sampler::start_bounce();
+    let hit = world.node.hit(r, 0.001, f64::INFINITY);
+    path.hit(r, hit.as_ref());
+    let rec = match hit {
+        Some(rec) => rec,
+        None => {
+            let background =
+                world.environment.color(r.direction()) * r.media.transmittance(f64::INFINITY);
+            path.record(0, background);
+            return background;
+        }
+    };
+
+    // the medium that the ray went through absorbs some of the light coming back along it
+    let transmittance = r.media.transmittance(rec.t * r.direction().length());
+    let emitted = rec.mat_ptr.emitted(rec.u, rec.v, rec.p) * transmittance;
+    path.record(0, emitted);
+    match rec.mat_ptr.scatter(r, &rec) {
+        Some((attenuation, scattered)) => {
+            let attenuation = attenuation * transmittance;
+            emitted
+                + attenuation * material_color(scattered, world, depth - 1, path.next(attenuation))
+        }
+        None => emitted,
+    }
+}
+
+/// Path trace `r` carrying a few wavelengths of light picked at random, using only the materials'
+/// own scattering, and turn the light found into a color.
+fn spectral_color(r: Ray, world: &World, depth: i32, path: PathState) -> Color {
+    let wavelengths = Wavelengths::sample(util::random_double_unit());
+    let radiance = spectral_radiance(
+        r.with_wavelength(wavelengths.hero()),
+        world,
+        wavelengths,
+        depth,
+        Spectrum::one(),
+        path,
+    );
+    wavelengths.to_rgb(radiance)
+}
+
+/// The light arriving back along `r` at each of `wavelengths`, with the colors of lights,
+/// materials and media turned into spectra.
+///
+/// `throughput` is how much of the light makes it back to the camera, which is needed to turn the
+/// light recorded in `path` into colors.
+fn spectral_radiance(
+    r: Ray,
+    world: &World,
+    wavelengths: Wavelengths,
+    depth: i32,
+    throughput: Spectrum,
+    mut path: PathState,
+) -> Spectrum {
+    if depth <= 0 {
+        return Spectrum::zero();
+    }
+
+    sampler::start_bounce();
+    let hit = world.node.hit(r, 0.001, f64::INFINITY);
+    path.hit(r, hit.as_ref());
+    let rec = match hit {
+        Some(rec) => rec,
+        None => {
+            let background = wavelengths.from_rgb(
+                world.environment.color(r.direction()) * r.media.transmittance(f64::INFINITY),
+            );
+            path.record(0, wavelengths.to_rgb(throughput * background));
+            return background;
+        }
+    };
+
+    let transmittance = wavelengths.from_rgb(r.media.transmittance(rec.t * r.direction().length()));
+    let emitted = wavelengths.from_rgb(rec.mat_ptr.emitted(rec.u, rec.v, rec.p)) * transmittance;
+    path.record(0, wavelengths.to_rgb(throughput * emitted));
+    match rec.mat_ptr.scatter(r, &rec) {
+        Some((attenuation, scattered)) => {
+            let attenuation = wavelengths.from_rgb(attenuation) * transmittance;
+            // after being split up by wavelength, the path can only go on carrying the hero
+            let (wavelengths, attenuation) = if rec.mat_ptr.disperses() {
+                wavelengths.disperse(attenuation)
+            } else {
+                (wavelengths, attenuation)
+            };
+            emitted
+                + attenuation
+                    * spectral_radiance(
+                        scattered,
+                        world,
+                        wavelengths,
+                        depth - 1,
+                        throughput * attenuation,
+                        path.next(Color::one()),
+                    )
+        }
+        None => emitted,
+    }
+}
+
+/// Path trace `r`, sampling the world's lights (and its environment, if it's a map) directly at
+/// every diffuse bounce.
+///
+/// `scatter_pdf` is the density with which the material that scattered `r` picked its direction,
+/// if it wasn't specular; light that `r` hits directly then only gets part of the credit, since
+/// the same light could have been found by sampling the lights.
+fn importance_sampled_color(
+    r: Ray,
+    world: &World,
+    depth: i32,
+    scatter_pdf: Option<f64>,
+    mut path: PathState,
+) -> Color {
+    if depth <= 0 {
+        return Color::zero();
+    }
+
+    sampler::start_bounce();
+    let hit = world.node.hit(r, 0.001, f64::INFINITY);
+    path.hit(r, hit.as_ref());
+    let rec = match hit {
+        Some(rec) => rec,
+        None => {
+            let mut background =
+                world.environment.color(r.direction()) * r.media.transmittance(f64::INFINITY);
+            if let Some(scatter_pdf) = scatter_pdf {
+                background *= power_heuristic(scatter_pdf, world.light_pdf_value(r));
+            }
+            path.record(0, background);
+            return background;
+        }
+    };
+
+    // the medium that the ray went through absorbs some of the light coming back along it
+    let transmittance = r.media.transmittance(rec.t * r.direction().length());
+    let mut emitted = rec.mat_ptr.emitted(rec.u, rec.v, rec.p) * transmittance;
+    if let Some(scatter_pdf) = scatter_pdf {
+        emitted *= power_heuristic(scatter_pdf, world.light_pdf_value(r));
+    }
+    path.record(0, emitted);
+
+    let scatter = match rec.mat_ptr.scatter_with_pdf(r, &rec) {
+        Some(scatter) => scatter,
+        None => return emitted,
+    };
+    // the lights can't be seen by the next bounce if there isn't one. This doesn't depend on
+    // whether this bounce was specular: materials that mix specular and non-specular lobes need
+    // the lights sampled for the others whichever was picked (purely specular ones see nothing)
+    let direct = if depth > 1 {
+        sample_lights(r, &rec, world) * transmittance
+    } else {
+        Color::zero()
+    };
+    path.record(1, direct);
+
+    let attenuation = scatter.attenuation * transmittance;
+    emitted
+        + direct
+        + attenuation
+            * importance_sampled_color(
+                scatter.scattered,
+                world,
+                depth - 1,
+                scatter.pdf,
+                path.next(attenuation),
+            )
+}
+
+/// Light arriving at `rec` from a direction picked by sampling the world's lights, weighted for
+/// combining with the material's own sampling.
+fn sample_lights(r_in: Ray, rec: &HitRecord, world: &World) -> Color {
+    if !world.has_sampled_lights() {
+        return Color::zero();
+    }
+
+    let to_light = r_in.bounce(rec.p, world.random_light_direction(rec.p, r_in.time()));
+    let light_pdf = world.light_pdf_value(to_light);
+    let scattering = rec.mat_ptr.scattering(r_in, rec, to_light.direction());
+    if light_pdf <= 0.0 || scattering == Color::zero() {
+        return Color::zero();
+    }
+
+    // whatever surface is hit first is what's seen, whether or not it's the light that was
+    // sampled, dimmed by any fog or smoke on the way
+    let (emitted, t_light) = match world.node.hit_surface(to_light, 0.001, f64::INFINITY) {
+        Some(light_rec) => (
+            light_rec
+                .mat_ptr
+                .emitted(light_rec.u, light_rec.v, light_rec.p)
+                * to_light
+                    .media
+                    .transmittance(light_rec.t * to_light.direction().length()),
+            light_rec.t,
+        ),
+        None => (
+            world.environment.color(to_light.direction())
+                * to_light.media.transmittance(f64::INFINITY),
+            f64::INFINITY,
+        ),
+    };
+    let emitted = emitted * world.node.transmittance(to_light, 0.001, t_light);
+    let scatter_pdf = rec.mat_ptr.scattering_pdf(r_in, rec, to_light.direction());
+    scattering * emitted * power_heuristic(light_pdf, scatter_pdf) / light_pdf
+}
+
+/// Weight for a sample picked with density `pdf` when another strategy could have picked it with
+/// density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Take the next sample of the pixel in column `i` of line `j`, where line 0 is the bottom of the
+/// image, and add it to `samples`.
+///
+/// Each sample of each pixel gets its own stream of random numbers (spread out by the configured
+/// sampler), so the result doesn't depend on which thread takes the sample or what it did before.
+fn sample_pixel(
+    world: &World,
+    cam: &Camera,
+    config: &RenderConfig,
+    i: usize,
+    j: usize,
+    samples: &mut PixelSamples,
+) {
+    let sample = samples.count;
+    let pixel_seed = util::mix_seed(config.seed, &[i as u64, j as u64]);
+    let samples_per_pixel = config.max_samples_per_pixel();
+    let wants_aovs = !config.aovs.is_empty() || config.denoising.is_some();
+    let (color, aovs) = util::with_seed(util::mix_seed(pixel_seed, &[sample as u64]), || {
+        sampler::with_pixel_sample(
+            config.sampler,
+            samples_per_pixel,
+            pixel_seed,
+            sample,
+            || {
+                let (du, dv) = util::random_pair();
+                let u = (i as f64 + du) / (config.image_width as f64 - 1.0);
+                let v = (j as f64 + dv) / (config.image_height as f64 - 1.0);
+                let r = cam.get_ray(u, v);
+                if !wants_aovs {
+                    return (ray_color(r, world, config.render_mode), None);
+                }
+
+                // the path fills these in as it goes, so they match what it actually hit
+                let mut aovs = AovSample::default();
+                aovs.record_first_hit(r, None);
+                let color = trace(
+                    r,
+                    world,
+                    config.render_mode,
+                    PathState::camera(Some(&mut aovs)),
+                );
+                (color, Some(aovs))
+            },
+        )
+    });
+
+    samples.add(color);
+    if let Some(aovs) = aovs {
+        samples.add_aovs(&aovs);
+    }
+}
+
+/// Render a single line of the image, where line 0 is the bottom of the image, as an image one
+/// row high (with any AOVs that `config` asks for).
+pub fn render_line(world: &World, cam: &Camera, config: &RenderConfig, j: usize) -> HdrImage {
+    let mut line_samples = Vec::with_capacity(config.image_width);
+    for i in 0..config.image_width {
+        let mut samples = PixelSamples::default();
+        while samples.wants_more(config) {
+            sample_pixel(world, cam, config, i, j, &mut samples);
+        }
+        line_samples.push(samples);
+    }
+
+    HdrImage {
+        width: config.image_width,
+        height: 1,
+        pixels: line_samples.iter().map(|p| p.to_rgb(config)).collect(),
+        aovs: aov_layers(config, &line_samples),
+    }
+}
+
+/// Add one sample to every pixel of `buffer` that needs one, using the current rayon thread pool.
+pub fn render_pass(
+    world: &World,
+    cam: &Camera,
+    config: &RenderConfig,
+    buffer: &mut AccumulationBuffer,
+) {
+    use rayon::prelude::*;
+
+    assert_eq!(buffer.width, config.image_width);
+    assert_eq!(buffer.height, config.image_height);
+    if buffer.width == 0 {
+        return;
+    }
+
+    buffer.active_pixels = buffer
+        .pixels
+        .par_chunks_mut(config.image_width)
+        .enumerate()
+        .map(|(row, row_pixels)| {
+            // rows are stored top first, but lines are numbered from the bottom
+            let j = config.image_height - row - 1;
+            let mut active = 0;
+            for (i, samples) in row_pixels.iter_mut().enumerate() {
+                if samples.wants_more(config) {
+                    sample_pixel(world, cam, config, i, j, samples);
+                    active += samples.wants_more(config) as usize;
+                }
+            }
+            active
+        })
+        .sum();
+    buffer.passes += 1;
+}
+
+/// Render the whole image one pass at a time until every pixel has enough samples, calling
+/// `on_pass` after each pass.
+///
+/// If `on_pass` returns false then rendering stops early, and the image is made from the passes
+/// rendered so far.
+pub fn render_progressive<F>(
+    world: &World,
+    cam: &Camera,
+    config: &RenderConfig,
+    mut on_pass: F,
+) -> HdrImage
+where
+    F: FnMut(&AccumulationBuffer) -> bool,
+{
+    let mut buffer = AccumulationBuffer::new(config.image_width, config.image_height);
+    while buffer.active_pixels() > 0 {
+        render_pass(world, cam, config, &mut buffer);
+        if !on_pass(&buffer) {
+            break;
+        }
+    }
+    buffer.to_image(config)
+}
+
+/// Render the whole image in memory, using the current rayon thread pool.
+pub fn render(world: &World, cam: &Camera, config: &RenderConfig) -> HdrImage {
+    render_with_progress(world, cam, config, |_| ())
+}
+
+/// Render the whole image in memory, calling `on_progress` each time a line of the image is done.
+///
+/// Lines are rendered in parallel, so `on_progress` may be called from several threads at once.
+pub fn render_with_progress<F>(
+    world: &World,
+    cam: &Camera,
+    config: &RenderConfig,
+    on_progress: F,
+) -> HdrImage
+where
+    F: Fn(RenderProgress) + Sync,
+{
+    use rayon::prelude::*;
+
+    let lines_completed = AtomicUsize::new(0);
+    let mut image = HdrImage::new(config.image_width, config.image_height);
+    if config.image_width == 0 {
+        return image;
+    }
+
+    let lines = (0..config.image_height)
+        .into_par_iter()
+        .map(|row| {
+            // rows are stored top first, but lines are numbered from the bottom
+            let j = config.image_height - row - 1;
+            let line = render_line(world, cam, config, j);
+
+            on_progress(RenderProgress {
+                lines_completed: lines_completed.fetch_add(1, Ordering::SeqCst) + 1,
+                total_lines: config.image_height,
+            });
+            line
+        })
+        .collect::<Vec<_>>();
+    for (row, line) in lines.iter().enumerate() {
+        image.set_row(row, line);
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::{
+        aarect::XzRect,
+        bvh_node::BvhNode,
+        camera::CameraSettings,
+        environment::{Environment, EnvironmentMap},
+        hittable::HittableList,
+        material::{Dielectric, DiffuseLambertian, DiffuseLight},
+        sphere::Sphere,
+        texture::ColorTexture,
+        vec3::{Point3, Vec3},
+    };
+
+    fn single_sphere_world() -> World {
+        World::new(
+            BvhNode::new(
+                vec![Box::new(Sphere::stationary(
+                    Point3::new(0.0, 0.0, 0.0),
+                    1.0,
+                    Box::new(DiffuseLambertian::new(Color::new(0.5, 0.5, 0.5))),
+                ))],
+                0.0,
+                0.0,
+            ),
+            Environment::Color(Color::zero()),
+        )
+    }
+
+    #[test]
+    fn render_fills_whole_image() {
+        let config = RenderConfig {
+            image_width: 8,
+            image_height: 6,
+            samples_per_pixel: 1,
+            render_mode: RayColorMode::BlockColor {
+                color: Color::one(),
+            },
+            ..Default::default()
+        };
+        let cam = Camera::new(
+            CameraSettings::default()
+                .look_from(Point3::new(0.0, 0.0, 10.0))
+                .focusing_on(10.0, 0.0),
+            config.aspect_ratio(),
+        );
+        let progress_calls = AtomicUsize::new(0);
+
+        let image = render_with_progress(&single_sphere_world(), &cam, &config, |progress| {
+            assert_eq!(progress.total_lines, 6);
+            progress_calls.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(image.pixels.len(), 8 * 6);
+        assert_eq!(progress_calls.load(Ordering::SeqCst), 6);
+        // the sphere is in the middle of the frame and the background is black
+        assert_eq!(image.pixel(4, 3), RGB::new(1.0, 1.0, 1.0));
+        assert_eq!(image.pixel(0, 0), RGB::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn aovs_describe_first_surface_and_emission_adds_up_to_image() {
+        let world = World::new(
+            BvhNode::new(
+                vec![Box::new(Sphere::stationary(
+                    Point3::new(0.0, 0.0, 0.0),
+                    1.0,
+                    Box::new(DiffuseLambertian::new(Color::new(0.5, 0.5, 0.5))),
+                ))],
+                0.0,
+                0.0,
+            ),
+            Environment::Color(Color::new(0.5, 0.5, 0.5)),
+        );
+        let config = RenderConfig {
+            image_width: 8,
+            image_height: 6,
+            samples_per_pixel: 4,
+            render_mode: RayColorMode::Material { depth: 10 },
+            aovs: Aov::ALL.to_vec(),
+            ..Default::default()
+        };
+        let cam = Camera::new(
+            CameraSettings::default()
+                .look_from(Point3::new(0.0, 0.0, 10.0))
+                .focusing_on(10.0, 0.0),
+            config.aspect_ratio(),
+        );
+
+        let image = render(&world, &cam, &config);
+        let layer = |name: &str| image.aovs.iter().find(|l| l.name == name).unwrap();
+        // the sphere only covers a few pixels in the middle
+        let (center, corner) = (3 * 8 + 3, 0);
+        assert_eq!(layer("object-id").value(center, 0), 1.0);
+        assert_eq!(layer("object-id").value(corner, 0), 0.0);
+        assert_ne!(layer("material-id").value(center, 0), 0.0);
+        assert!((layer("depth").value(center, 0) - 9.0).abs() < 0.1);
+        assert_eq!(layer("depth").value(corner, 0), f32::INFINITY);
+        assert_eq!(layer("albedo").value(center, 1), 0.5);
+        assert!(layer("normal").value(center, 2) > 0.8);
+        assert!(layer("position").value(center, 2) > 0.8);
+
+        for (index, pixel) in image.pixels.iter().enumerate() {
+            let emission = (0..EMISSION_BOUNCES)
+                .map(|bounce| layer(&format!("emission{}", bounce)).value(index, 0))
+                .sum::<f32>();
+            assert!(
+                (emission - pixel.r).abs() < 1e-5,
+                "emission adds up to {} but the pixel is {}",
+                emission,
+                pixel.r
+            );
+        }
+        assert_eq!(layer("emission0").value(corner, 0), 0.5);
+        assert!(layer("emission1").value(center, 0) > 0.0);
+    }
+
+    #[test]
+    fn same_seed_gives_same_image_on_any_number_of_threads() {
+        let scene = RenderScene::ManyBalls;
+        let config = RenderConfig {
+            image_width: 16,
+            image_height: 9,
+            samples_per_pixel: 4,
+            render_mode: RayColorMode::Material { depth: 10 },
+            scene: scene.clone(),
+            seed: 42,
+            ..Default::default()
+        };
+        let cam = Camera::new(
+            scene.default_camera_settings().unwrap(),
+            config.aspect_ratio(),
+        );
+        let render_on_threads = |threads, config: &RenderConfig| {
+            let world = config.scene.create_world(config.seed).unwrap();
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| render(&world, &cam, config))
+        };
+
+        let single_threaded = render_on_threads(1, &config);
+        assert_eq!(single_threaded, render_on_threads(4, &config));
+        assert_ne!(
+            single_threaded,
+            render_on_threads(
+                4,
+                &RenderConfig {
+                    seed: 43,
+                    ..config.clone()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn importance_sampling_matches_material_mode() {
+        // a floor lit by a small light above it, which material mode rarely finds by chance
+        let light = Arc::new(XzRect::new(
+            -0.25,
+            0.25,
+            -0.25,
+            0.25,
+            1.0,
+            Box::new(DiffuseLight::new(Box::new(ColorTexture::from_rgb(
+                10.0, 10.0, 10.0,
+            )))),
+        ));
+        let floor = XzRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            Box::new(DiffuseLambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let world = World::new(
+            BvhNode::new(vec![Box::new(floor), Box::new(light.clone())], 0.0, 0.0),
+            Environment::Color(Color::zero()),
+        )
+        .with_lights(HittableList {
+            objects: vec![Box::new(light)],
+        });
+
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+        let samples = 50_000;
+        let average = |mode| {
+            (0..samples)
+                .map(|_| ray_color(r, &world, mode))
+                .sum::<Color>()
+                / samples as f64
+        };
+        let material = average(RayColorMode::Material { depth: 5 });
+        let importance = average(RayColorMode::ImportanceSampled { depth: 5 });
+
+        assert!(
+            (material.x - importance.x).abs() < 0.1 * material.x,
+            "material mode gave {}, importance sampling gave {}",
+            material,
+            importance
+        );
+    }
+
+    #[test]
+    fn absorbing_dielectrics_tint_light_by_the_distance_it_travels_inside() {
+        // with the same index of refraction as air the ray goes straight through the middle
+        let glass = Sphere::stationary(
+            Point3::zero(),
+            1.0,
+            Box::new(Dielectric::new(1.0).absorption(Color::new(1.0, 0.5, 0.0))),
+        );
+        let world = World::new(
+            BvhNode::new(vec![Box::new(glass)], 0.0, 0.0),
+            Environment::Color(Color::one()),
+        );
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), None);
+        let expected = Color::new((-2.0f64).exp(), (-1.0f64).exp(), 1.0);
+        for mode in [
+            RayColorMode::Material { depth: 5 },
+            RayColorMode::ImportanceSampled { depth: 5 },
+        ] {
+            let color = ray_color(r, &world, mode);
+            assert!((color - expected).length() < 1e-9, "{}", color);
+        }
+    }
+
+    #[test]
+    fn spectral_mode_keeps_colors_without_dispersion() {
+        let floor = XzRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            Box::new(DiffuseLambertian::new(Color::new(0.7, 0.4, 0.2))),
+        );
+        let world = World::new(
+            BvhNode::new(vec![Box::new(floor)], 0.0, 0.0),
+            Environment::Color(Color::new(0.5, 0.8, 1.0)),
+        );
+
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+        let samples = 50_000;
+        let spectral = util::with_seed(3, || {
+            (0..samples)
+                .map(|_| ray_color(r, &world, RayColorMode::Spectral { depth: 5 }))
+                .sum::<Color>()
+                / samples as f64
+        });
+        // the light bounces off the floor once, so is the product of the two colors
+        let expected = Color::new(0.35, 0.32, 0.2);
+        assert!(
+            (spectral - expected).length() < 0.05,
+            "spectral mode gave {}",
+            spectral
+        );
+    }
+
+    #[test]
+    fn importance_sampled_environment_map_matches_material_mode() {
+        // a dim sky with a small, bright sun above the floor
+        let mut sky = HdrImage::new(16, 8);
+        for p in &mut sky.pixels {
+            *p = RGB::new(0.25, 0.25, 0.25);
+        }
+        sky.pixels[16 + 3] = RGB::new(200.0, 200.0, 200.0);
+        let floor = XzRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            Box::new(DiffuseLambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let world = World::new(
+            BvhNode::new(vec![Box::new(floor)], 0.0, 0.0),
+            Environment::Map(EnvironmentMap::new(sky, 0.0, 1.0)),
+        );
+
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+        let samples = 50_000;
+        let average = |mode| {
+            (0..samples)
+                .map(|_| ray_color(r, &world, mode))
+                .sum::<Color>()
+                / samples as f64
+        };
+        let material = average(RayColorMode::Material { depth: 5 });
+        let importance = average(RayColorMode::ImportanceSampled { depth: 5 });
+
+        assert!(
+            (material.x - importance.x).abs() < 0.1 * material.x,
+            "material mode gave {}, importance sampling gave {}",
+            material,
+            importance
+        );
+    }
+
+    #[test]
+    fn progressive_render_can_stop_after_any_pass() {
+        let config = RenderConfig {
+            image_width: 8,
+            image_height: 6,
+            samples_per_pixel: 10,
+            render_mode: RayColorMode::BlockColor {
+                color: Color::one(),
+            },
+            ..Default::default()
+        };
+        let cam = Camera::new(
+            CameraSettings::default()
+                .look_from(Point3::new(0.0, 0.0, 10.0))
+                .focusing_on(10.0, 0.0),
+            config.aspect_ratio(),
+        );
+
+        let mut passes = Vec::new();
+        let image = render_progressive(&single_sphere_world(), &cam, &config, |buffer| {
+            passes.push(buffer.passes());
+            buffer.passes() < 3
+        });
+
+        assert_eq!(passes, vec![1, 2, 3]);
+        assert_eq!(image.pixels.len(), 8 * 6);
+        assert_eq!(image.pixel(0, 0), RGB::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_samples_on_noisy_pixels() {
+        let config = RenderConfig {
+            adaptive_sampling: Some(AdaptiveSampling {
+                noise_threshold: 0.01,
+                min_samples: 4,
+                max_samples: 64,
+                show_sample_counts: false,
+            }),
+            ..Default::default()
+        };
+
+        let mut smooth = PixelSamples::default();
+        while smooth.wants_more(&config) {
+            smooth.add(Color::one());
+        }
+        let mut noisy = PixelSamples::default();
+        while noisy.wants_more(&config) {
+            noisy.add(Color::one() * (noisy.count % 2) as f64);
+        }
+
+        assert_eq!(smooth.count, 4);
+        assert_eq!(noisy.count, 64);
+    }
+}
@@ -0,0 +1,494 @@
+//! Low discrepancy samplers, which spread each pixel's samples out more evenly than independent
+//! random numbers do, so images converge faster at the same sample count.
+//!
+//! Every random decision made while tracing a sample gets its own *dimension*: the first few are
+//! the position within the pixel, the point on the lens, the shutter time and the wavelength, and
+//! after that each bounce gets a block of [`DIMENSIONS_PER_BOUNCE`] dimensions for picking lights
+//! and scattering. Random numbers needed past the end of a block (or after
+//! [`LOW_DISCREPANCY_BOUNCES`] bounces) come from the ordinary seeded random number generator
+//! instead.
+
+use std::cell::Cell;
+
+use crate::util;
+
+/// Dimensions used by the camera: 2 for the position within the pixel, 2 for the lens, 1 for the
+/// time and 1 for the wavelength (in spectral mode).
+pub const CAMERA_DIMENSIONS: u32 = 6;
+/// Dimensions given to each bounce, which is enough for picking a light, a point on it, and a
+/// scattered direction (even one needing 3 numbers) after passing through a medium.
+pub const DIMENSIONS_PER_BOUNCE: u32 = 8;
+/// Bounces after this many use ordinary random numbers, since they contribute little to the image.
+pub const LOW_DISCREPANCY_BOUNCES: u32 = 8;
+/// Every dimension a sampler is asked for is less than this.
+pub const MAX_DIMENSIONS: u32 = CAMERA_DIMENSIONS + DIMENSIONS_PER_BOUNCE * LOW_DISCREPANCY_BOUNCES;
+
+/// Generates the sample points for a pixel.
+///
+/// `pixel_seed` identifies the pixel (and the render's seed), `index` is which of the pixel's
+/// samples is being taken and `dimension` is which random decision within that sample is being
+/// made. The same arguments always give the same values.
+pub trait Sampler {
+    /// A value in [0, 1).
+    fn sample_1d(&self, pixel_seed: u64, index: u32, dimension: u32) -> f64;
+
+    /// A point in [0, 1)², using dimensions `dimension` and `dimension + 1`.
+    fn sample_2d(&self, pixel_seed: u64, index: u32, dimension: u32) -> (f64, f64) {
+        (
+            self.sample_1d(pixel_seed, index, dimension),
+            self.sample_1d(pixel_seed, index, dimension + 1),
+        )
+    }
+}
+
+/// Which sampler to use for rendering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    #[default]
+    Independent,
+    /// Jittered samples, one in each of a grid of strata (see [`StratifiedSampler`])
+    Stratified,
+    /// The Halton sequence (see [`HaltonSampler`])
+    Halton,
+    /// The Sobol sequence (see [`SobolSampler`])
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Call `f` with this kind of sampler, set up for `samples_per_pixel` samples, or return `None`
+    /// for independent random numbers.
+    fn with_sampler<T>(
+        self,
+        samples_per_pixel: u32,
+        f: impl FnOnce(&dyn Sampler) -> T,
+    ) -> Option<T> {
+        match self {
+            SamplerKind::Independent => None,
+            SamplerKind::Stratified => Some(f(&StratifiedSampler::new(samples_per_pixel))),
+            SamplerKind::Halton => Some(f(&HaltonSampler)),
+            SamplerKind::Sobol => Some(f(&SobolSampler)),
+        }
+    }
+}
+
+impl std::str::FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "unknown sampler '{}', expected one of: independent, stratified, halton, sobol",
+                s
+            )),
+        }
+    }
+}
+
+/// Splits each dimension (or pair of dimensions) into as many strata as there are samples, and
+/// puts each sample at a random point in a different stratum. Which sample goes in which stratum is
+/// shuffled differently for each pixel and dimension, so the dimensions aren't correlated.
+#[derive(Debug, Clone, Copy)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample_1d(&self, pixel_seed: u64, index: u32, dimension: u32) -> f64 {
+        let strata = self.samples_per_pixel;
+        let stratum = permutation_element(
+            index % strata,
+            strata,
+            hash32(pixel_seed, &[dimension as u64]),
+        );
+        (stratum as f64 + jitter(pixel_seed, index, dimension)) / strata as f64
+    }
+
+    fn sample_2d(&self, pixel_seed: u64, index: u32, dimension: u32) -> (f64, f64) {
+        // as square a grid as will fit all the samples
+        let columns = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let strata = columns * rows;
+        let stratum = permutation_element(
+            index % strata,
+            strata,
+            hash32(pixel_seed, &[dimension as u64]),
+        );
+        (
+            ((stratum % columns) as f64 + jitter(pixel_seed, index, dimension)) / columns as f64,
+            ((stratum / columns) as f64 + jitter(pixel_seed, index, dimension + 1)) / rows as f64,
+        )
+    }
+}
+
+/// The Halton sequence, which uses the radical inverse in a different prime base for each
+/// dimension. Each pixel's sequence is shifted by a random offset in each dimension (a
+/// Cranley-Patterson rotation) so that neighbouring pixels don't share sample points.
+#[derive(Debug, Clone, Copy)]
+pub struct HaltonSampler;
+
+/// One prime base per dimension.
+const PRIMES: [u32; MAX_DIMENSIONS as usize] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311, 313, 317, 331, 337, 347, 349,
+];
+
+impl Sampler for HaltonSampler {
+    fn sample_1d(&self, pixel_seed: u64, index: u32, dimension: u32) -> f64 {
+        let base = PRIMES[dimension as usize % PRIMES.len()];
+        let offset = unit_f64(util::mix_seed(pixel_seed, &[dimension as u64]));
+        (radical_inverse(base, index) + offset).fract()
+    }
+}
+
+/// Mirror the digits of `index` in `base` around the decimal point.
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inv_base;
+    }
+    result
+}
+
+/// The Sobol sequence with Owen scrambling, using Burley's "Practical Hash-based Owen Scrambling":
+/// every dimension pair is the first two Sobol dimensions, but with the sample order shuffled
+/// differently per pair and each pair's values scrambled differently, which keeps the good 2D
+/// distribution without needing a table of direction numbers for every dimension.
+#[derive(Debug, Clone, Copy)]
+pub struct SobolSampler;
+
+impl SobolSampler {
+    fn sample(pixel_seed: u64, index: u32, dimension: u32, sobol_dimensions: u32) -> [f64; 2] {
+        let seed = hash32(pixel_seed, &[dimension as u64]);
+        let shuffled = nested_uniform_scramble(index, seed);
+        let mut result = [0.0; 2];
+        for (d, value) in result
+            .iter_mut()
+            .enumerate()
+            .take(sobol_dimensions as usize)
+        {
+            let scrambled = nested_uniform_scramble(
+                sobol(shuffled, d as u32),
+                hash32(seed as u64, &[d as u64]),
+            );
+            *value = scrambled as f64 / (1u64 << 32) as f64;
+        }
+        result
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn sample_1d(&self, pixel_seed: u64, index: u32, dimension: u32) -> f64 {
+        Self::sample(pixel_seed, index, dimension, 1)[0]
+    }
+
+    fn sample_2d(&self, pixel_seed: u64, index: u32, dimension: u32) -> (f64, f64) {
+        let [x, y] = Self::sample(pixel_seed, index, dimension, 2);
+        (x, y)
+    }
+}
+
+/// Sobol dimension 0 (the van der Corput sequence) or 1, as a 32 bit fraction.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut index = index;
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scramble the bits of `x` (as a fraction), using Laine and Karras' hash.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..len` picked by `seed` (Kensler's "Correlated
+/// Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+fn hash32(seed: u64, values: &[u64]) -> u32 {
+    (util::mix_seed(seed, values) >> 32) as u32
+}
+
+fn unit_f64(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn jitter(pixel_seed: u64, index: u32, dimension: u32) -> f64 {
+    unit_f64(util::mix_seed(
+        pixel_seed,
+        &[index as u64, dimension as u64],
+    ))
+}
+
+/// What the sample being traced on this thread is, and which dimension it's up to.
+#[derive(Debug, Clone, Copy)]
+struct SampleState {
+    kind: SamplerKind,
+    samples_per_pixel: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+    /// Dimensions from here on belong to the next bounce
+    block_end: u32,
+    bounces: u32,
+}
+
+thread_local! {
+    static CURRENT_SAMPLE: Cell<Option<SampleState>> = const { Cell::new(None) };
+}
+
+/// Run `f` (which traces sample `index` of the pixel picked by `pixel_seed`) with random numbers
+/// coming from `kind` of sampler.
+pub(crate) fn with_pixel_sample<T>(
+    kind: SamplerKind,
+    samples_per_pixel: u32,
+    pixel_seed: u64,
+    index: u32,
+    f: impl FnOnce() -> T,
+) -> T {
+    let state = SampleState {
+        kind,
+        samples_per_pixel,
+        pixel_seed,
+        index,
+        dimension: 0,
+        block_end: CAMERA_DIMENSIONS,
+        bounces: 0,
+    };
+    let previous = CURRENT_SAMPLE.with(|current| current.replace(Some(state)));
+    let result = f();
+    CURRENT_SAMPLE.with(|current| current.set(previous));
+    result
+}
+
+/// Move on to the dimensions for the next bounce of the current sample's path.
+pub(crate) fn start_bounce() {
+    CURRENT_SAMPLE.with(|current| {
+        if let Some(mut state) = current.get() {
+            if state.bounces < LOW_DISCREPANCY_BOUNCES {
+                state.dimension = CAMERA_DIMENSIONS + state.bounces * DIMENSIONS_PER_BOUNCE;
+                state.block_end = state.dimension + DIMENSIONS_PER_BOUNCE;
+            } else {
+                state.block_end = state.dimension;
+            }
+            state.bounces += 1;
+            current.set(Some(state));
+        }
+    })
+}
+
+/// The next `count` dimensions of the current sample, if there is one and it has that many left
+/// before the next bounce.
+fn next_dimensions<T>(count: u32, f: impl FnOnce(&dyn Sampler, u64, u32, u32) -> T) -> Option<T> {
+    CURRENT_SAMPLE.with(|current| {
+        let mut state = current.get()?;
+        if state.dimension + count > state.block_end {
+            return None;
+        }
+        let dimension = state.dimension;
+        state.dimension += count;
+        current.set(Some(state));
+        state.kind.with_sampler(state.samples_per_pixel, |sampler| {
+            f(sampler, state.pixel_seed, state.index, dimension)
+        })
+    })
+}
+
+/// The next 1D sample value for the sample being traced on this thread, if it's using a low
+/// discrepancy sampler.
+pub(crate) fn next_1d() -> Option<f64> {
+    next_dimensions(1, |sampler, pixel_seed, index, dimension| {
+        sampler.sample_1d(pixel_seed, index, dimension)
+    })
+}
+
+/// The next 2D sample point for the sample being traced on this thread, if it's using a low
+/// discrepancy sampler.
+pub(crate) fn next_2d() -> Option<(f64, f64)> {
+    next_dimensions(2, |sampler, pixel_seed, index, dimension| {
+        sampler.sample_2d(pixel_seed, index, dimension)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers(samples: u32) -> Vec<(&'static str, Box<dyn Sampler>)> {
+        vec![
+            ("stratified", Box::new(StratifiedSampler::new(samples))),
+            ("halton", Box::new(HaltonSampler)),
+            ("sobol", Box::new(SobolSampler)),
+        ]
+    }
+
+    #[test]
+    fn samples_are_in_unit_interval() {
+        for (name, sampler) in samplers(16) {
+            for dimension in 0..MAX_DIMENSIONS {
+                for index in 0..32 {
+                    let x = sampler.sample_1d(7, index, dimension);
+                    let (y, z) = sampler.sample_2d(7, index, dimension);
+                    for v in [x, y, z] {
+                        assert!((0.0..1.0).contains(&v), "{} gave {}", name, v);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_well_distributed_in_2d() {
+        // 16 samples in a 4x4 grid of cells should land one per cell for a stratified or (0,2)
+        // sequence; independent random points leave some cells empty nearly every time
+        let samples = 16;
+        for (name, sampler) in samplers(samples) {
+            if name == "halton" {
+                // base 2 and 3 don't line up with a 4x4 grid
+                continue;
+            }
+            for dimension in [0, CAMERA_DIMENSIONS, CAMERA_DIMENSIONS + 2] {
+                let mut cells = [0; 16];
+                for index in 0..samples {
+                    let (x, y) = sampler.sample_2d(1234, index, dimension);
+                    cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                }
+                assert_eq!(cells, [1; 16], "{} dimension {}", name, dimension);
+            }
+        }
+    }
+
+    #[test]
+    fn low_discrepancy_integrates_better_than_random() {
+        // estimate the area of a quarter disk with 64 samples, for a few hundred pixels
+        let samples = 64;
+        let pixels = 200;
+        let exact = std::f64::consts::FRAC_PI_4;
+        let error = |estimate: &dyn Fn(u64) -> f64| {
+            (0..pixels)
+                .map(|pixel| (estimate(pixel) - exact).powi(2))
+                .sum::<f64>()
+                / pixels as f64
+        };
+
+        let random_error = error(&|pixel| {
+            util::with_seed(pixel, || {
+                (0..samples)
+                    .filter(|_| {
+                        let (x, y) = (util::random_double_unit(), util::random_double_unit());
+                        x * x + y * y < 1.0
+                    })
+                    .count() as f64
+                    / samples as f64
+            })
+        });
+        for (name, sampler) in samplers(samples) {
+            let sampler_error = error(&|pixel| {
+                (0..samples)
+                    .filter(|&index| {
+                        let (x, y) = sampler.sample_2d(pixel, index, CAMERA_DIMENSIONS);
+                        x * x + y * y < 1.0
+                    })
+                    .count() as f64
+                    / samples as f64
+            });
+            assert!(
+                sampler_error < random_error / 2.0,
+                "{} had mean squared error {} vs {} for random samples",
+                name,
+                sampler_error,
+                random_error
+            );
+        }
+    }
+
+    #[test]
+    fn dimensions_restart_at_each_bounce() {
+        with_pixel_sample(SamplerKind::Sobol, 16, 99, 3, || {
+            let pixel = next_2d().unwrap();
+            let _lens = next_2d().unwrap();
+            let _time = next_1d().unwrap();
+            let _wavelength = next_1d().unwrap();
+            assert_eq!(next_1d(), None, "camera dimensions are used up");
+
+            start_bounce();
+            let first_bounce = next_2d().unwrap();
+            for _ in 0..DIMENSIONS_PER_BOUNCE - 2 {
+                next_1d().unwrap();
+            }
+            assert_eq!(next_1d(), None, "bounce dimensions are used up");
+
+            assert_eq!(pixel, SobolSampler.sample_2d(99, 3, 0));
+            assert_eq!(
+                first_bounce,
+                SobolSampler.sample_2d(99, 3, CAMERA_DIMENSIONS)
+            );
+        });
+        assert_eq!(next_1d(), None, "no sample is being traced");
+    }
+}
@@ -0,0 +1,1095 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    aarect::{XyRect, XzRect, YzRect},
+    animation::{AnimatedTransform, Keyframe},
+    box3d::Box3D,
+    bvh_node::BvhNode,
+    camera::CameraSettings,
+    constant_medium::ConstantMedium,
+    environment::{Environment, EnvironmentMap},
+    heterogeneous_medium::{Density, DensityGrid, HeightFog, HeterogeneousMedium, Turbulence},
+    hittable::{Hittable, HittableList, RotateY, Transform, Translate},
+    image_file::{load_image, ImageFileError},
+    instance::Prototype,
+    material::{
+        numbered_materials, Conductor, ConductorIor, Dielectric, DiffuseLambertianTexture,
+        DiffuseLight, Dispersion, Material, Metal,
+    },
+    matrix::Quaternion,
+    obj::{load_obj, ObjError},
+    perlin::Perlin,
+    principled::{Parameter, Principled},
+    sky::{Sky, SkySettings},
+    sphere::Sphere,
+    texture::{
+        CheckerTexture, ColorTexture, ImageTexture, MarbleTexture, NoiseTexture, PositionTexture,
+        Texture, TurbulenceTexture,
+    },
+    vec3::{Color, Point3, Vec3},
+    World,
+};
+
+/// A scene loaded from (or saved to) a RON scene description file.
+///
+/// See the `scenes/` directory for examples of what these look like.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SceneDescription {
+    /// What rays that hit nothing see; leave out to use the default white-to-blue sky.
+    #[serde(default)]
+    pub environment: EnvironmentDescription,
+    /// Camera to use unless the user has customized it.
+    #[serde(default)]
+    pub camera: CameraSettings,
+    /// Named sets of objects that can be placed many times with [`ObjectDescription::Instance`];
+    /// each can use the prototypes listed before it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prototypes: Vec<PrototypeDescription>,
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PrototypeDescription {
+    pub name: String,
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ObjectDescription {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: MaterialDescription,
+    },
+    MovingSphere {
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: MaterialDescription,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialDescription,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialDescription,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialDescription,
+    },
+    Box3D {
+        min: Point3,
+        max: Point3,
+        material: MaterialDescription,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDescription>,
+        texture: TextureDescription,
+        density: f64,
+    },
+    /// Fog, smoke or cloud filling `boundary` (which can be any closed shape) as thickly as
+    /// `density` says at each point.
+    HeterogeneousMedium {
+        boundary: Box<ObjectDescription>,
+        texture: TextureDescription,
+        density: DensityDescription,
+    },
+    Translate {
+        offset: Vec3,
+        object: Box<ObjectDescription>,
+    },
+    RotateY {
+        /// Rotation in degrees
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    /// `object` moved by each of `steps` in turn.
+    Transform {
+        steps: Vec<TransformStep>,
+        object: Box<ObjectDescription>,
+    },
+    /// `object` moving between `keyframes` while the shutter is open, which blurs it.
+    Animated {
+        keyframes: Vec<KeyframeDescription>,
+        object: Box<ObjectDescription>,
+    },
+    /// A set of objects that gets its own bounding volume hierarchy, which is useful for
+    /// transforming many objects at once.
+    Group { objects: Vec<ObjectDescription> },
+    /// A Wavefront OBJ mesh, using the materials from its MTL files; relative paths are relative to
+    /// the scene file.
+    Mesh { path: PathBuf },
+    /// A copy of one of the scene's prototypes, moved by each of `steps` in turn and drawn with
+    /// `material` instead of the prototype's own materials if it's given.
+    Instance {
+        prototype: String,
+        #[serde(default)]
+        steps: Vec<TransformStep>,
+        #[serde(default)]
+        material: Option<MaterialDescription>,
+    },
+}
+
+impl ObjectDescription {
+    /// Whether this is a light that can be sampled directly; groups never count, since the lights
+    /// inside them can be sampled individually.
+    fn emits_light(&self) -> bool {
+        match self {
+            ObjectDescription::Sphere { material, .. }
+            | ObjectDescription::MovingSphere { material, .. }
+            | ObjectDescription::XyRect { material, .. }
+            | ObjectDescription::XzRect { material, .. }
+            | ObjectDescription::YzRect { material, .. }
+            | ObjectDescription::Box3D { material, .. } => {
+                matches!(material, MaterialDescription::DiffuseLight { .. })
+            }
+            ObjectDescription::Translate { object, .. }
+            | ObjectDescription::RotateY { object, .. }
+            | ObjectDescription::Transform { object, .. }
+            | ObjectDescription::Animated { object, .. } => object.emits_light(),
+            ObjectDescription::ConstantMedium { .. }
+            | ObjectDescription::HeterogeneousMedium { .. }
+            | ObjectDescription::Group { .. }
+            | ObjectDescription::Mesh { .. }
+            | ObjectDescription::Instance { .. } => false,
+        }
+    }
+}
+
+/// One step of an [`ObjectDescription::Transform`] or [`ObjectDescription::Instance`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum TransformStep {
+    Translate(Vec3),
+    /// Rotation by `angle` degrees around `axis`, anticlockwise when looking back along it
+    Rotate {
+        axis: Vec3,
+        angle: f64,
+    },
+    /// Rotation by a quaternion, which doesn't need to be normalized
+    Quaternion(Quaternion),
+    /// Scale along each axis; negative factors mirror the object, and none can be zero
+    Scale(Vec3),
+}
+
+/// Check that none of `steps` scales by zero, which would squash the object flat.
+fn check_scales(steps: &[TransformStep]) -> Result<(), SceneFileError> {
+    let zero = steps.iter().any(|step| match step {
+        TransformStep::Scale(factors) => (0..3).any(|axis| factors[axis] == 0.0),
+        _ => false,
+    });
+    if zero {
+        Err(SceneFileError::ZeroScale)
+    } else {
+        Ok(())
+    }
+}
+
+/// Where an [`ObjectDescription::Animated`] object is at `time`: scaled by `scale`, then rotated
+/// `angle` degrees around `axis`, then moved by `translation`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct KeyframeDescription {
+    pub time: f64,
+    #[serde(default)]
+    pub translation: Vec3,
+    #[serde(default = "default_axis")]
+    pub axis: Vec3,
+    #[serde(default)]
+    pub angle: f64,
+    #[serde(default = "Vec3::one")]
+    pub scale: Vec3,
+}
+
+fn default_axis() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: TextureDescription,
+    },
+    Metal {
+        albedo: Color,
+        fuzz: f64,
+    },
+    /// A physically based metal; `roughness` goes from 0 (polished) to 1.
+    Conductor {
+        ior: ConductorIor,
+        roughness: TextureDescription,
+    },
+    /// Glass, water, etc; see [`Dielectric`] for how `priority` works.
+    Dielectric {
+        ir: f64,
+        /// How the index of refraction varies with wavelength in spectral mode, e.g.
+        /// `Some(FlintGlass)`; this replaces `ir`
+        #[serde(default)]
+        dispersion: Option<Dispersion>,
+        /// Fraction of each color absorbed per unit distance inside
+        #[serde(default)]
+        absorption: Color,
+        #[serde(default)]
+        priority: i32,
+    },
+    DiffuseLight {
+        emit: TextureDescription,
+    },
+    /// One material for most surfaces, e.g. `Principled((base_color: ..., metallic: Value(1.0)))`
+    Principled(PrincipledDescription),
+}
+
+/// The parameters of a [`Principled`] material; any that are left out get their defaults.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PrincipledDescription {
+    pub base_color: TextureDescription,
+    pub metallic: ParameterDescription,
+    pub roughness: ParameterDescription,
+    pub specular: ParameterDescription,
+    pub specular_tint: ParameterDescription,
+    pub sheen: ParameterDescription,
+    pub clearcoat: ParameterDescription,
+    pub transmission: ParameterDescription,
+    pub ior: ParameterDescription,
+}
+
+impl Default for PrincipledDescription {
+    fn default() -> Self {
+        Self {
+            base_color: TextureDescription::Color(Color::new(0.8, 0.8, 0.8)),
+            metallic: ParameterDescription::Value(0.0),
+            roughness: ParameterDescription::Value(0.5),
+            specular: ParameterDescription::Value(0.5),
+            specular_tint: ParameterDescription::Value(0.0),
+            sheen: ParameterDescription::Value(0.0),
+            clearcoat: ParameterDescription::Value(0.0),
+            transmission: ParameterDescription::Value(0.0),
+            ior: ParameterDescription::Value(1.5),
+        }
+    }
+}
+
+/// A number that's either the same everywhere or read from a texture (using its luminance).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ParameterDescription {
+    Value(f64),
+    Texture(TextureDescription),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum TextureDescription {
+    Color(Color),
+    Checker {
+        scale: f64,
+        odd: Box<TextureDescription>,
+        even: Box<TextureDescription>,
+    },
+    Position,
+    Noise {
+        scale: f64,
+    },
+    Turbulence {
+        scale: f64,
+        depth: i32,
+    },
+    Marble {
+        scale: f64,
+        depth: i32,
+    },
+    /// A PNG image; relative paths are relative to the scene file.
+    Image {
+        path: PathBuf,
+    },
+}
+
+/// How dense an [`ObjectDescription::HeterogeneousMedium`] is at each point.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum DensityDescription {
+    /// Densities at the centers of a grid of `resolution` voxels filling the box from `min` to
+    /// `max`, ordered by x, then y, then z
+    Grid {
+        min: Point3,
+        max: Point3,
+        resolution: (usize, usize, usize),
+        values: Vec<f64>,
+    },
+    /// Perlin turbulence reaching up to `density`, for clouds and smoke
+    Turbulence {
+        density: f64,
+        scale: f64,
+        depth: i32,
+    },
+    /// Fog that's `density` thick up to `height`, and gets thinner by a factor of e every
+    /// `falloff` above it
+    HeightFog {
+        density: f64,
+        height: f64,
+        falloff: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum EnvironmentDescription {
+    Color(Color),
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    /// An equirectangular HDR image (.hdr, .exr or .pfm) that lights the scene; relative paths are
+    /// relative to the scene file.
+    Image {
+        path: PathBuf,
+        /// Degrees to turn the image around the y axis
+        #[serde(default)]
+        rotation: f64,
+        /// Brightness multiplier
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    /// A physically based daylight sky with a sun, which lights the scene
+    Sky(SkySettings),
+}
+
+impl Default for EnvironmentDescription {
+    fn default() -> Self {
+        match Environment::default() {
+            Environment::Gradient { bottom, top } => {
+                EnvironmentDescription::Gradient { bottom, top }
+            }
+            _ => unreachable!("the default environment is a gradient"),
+        }
+    }
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse(ron::Error),
+    Texture {
+        path: PathBuf,
+        error: lodepng::Error,
+    },
+    Mesh(ObjError),
+    Environment(ImageFileError),
+    /// Scenes (and groups) must contain at least one object
+    Empty,
+    /// An instance of a prototype that isn't listed before it
+    UnknownPrototype(String),
+    /// Animations need at least one keyframe, no two at the same time, and a scale that never
+    /// reaches zero
+    Keyframes,
+    /// A transform step that scales by zero along some axis
+    ZeroScale,
+    /// A density grid with a different number of values than its resolution calls for
+    DensityGrid {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl std::fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneFileError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            SceneFileError::Parse(error) => write!(f, "invalid scene description: {}", error),
+            SceneFileError::Texture { path, error } => {
+                write!(f, "could not load texture {}: {}", path.display(), error)
+            }
+            SceneFileError::Mesh(error) => write!(f, "could not load mesh: {}", error),
+            SceneFileError::Environment(error) => {
+                write!(f, "could not load environment map: {}", error)
+            }
+            SceneFileError::Empty => {
+                write!(f, "scenes and groups must contain at least one object")
+            }
+            SceneFileError::UnknownPrototype(name) => {
+                write!(
+                    f,
+                    "no prototype named '{}' is listed before it's used",
+                    name
+                )
+            }
+            SceneFileError::Keyframes => write!(
+                f,
+                "animations need at least one keyframe, all at finite times and no two at the same time, and \
+                 can't scale by zero or change the sign of a scale factor between keyframes"
+            ),
+            SceneFileError::ZeroScale => write!(f, "transforms can't scale by zero"),
+            SceneFileError::DensityGrid { expected, found } => write!(
+                f,
+                "density grid needs {} values for its resolution, but has {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl SceneDescription {
+    /// Read a scene description from a RON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneFileError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|error| SceneFileError::Io {
+            path: path.to_owned(),
+            error,
+        })?;
+        Self::from_ron_str(&contents)
+    }
+
+    pub fn from_ron_str(s: &str) -> Result<Self, SceneFileError> {
+        ron::from_str(s).map_err(SceneFileError::Parse)
+    }
+
+    pub fn to_ron_string(&self) -> String {
+        // keep each object on a single line so that big scenes stay readable
+        let config = ron::ser::PrettyConfig::new()
+            .with_depth_limit(2)
+            .with_decimal_floats(true);
+        ron::ser::to_string_pretty(self, config).expect("scene descriptions should serialize")
+    }
+
+    /// Construct the objects of the scene without putting them in a BVH, resolving relative paths
+    /// against `base_dir`.
+    pub fn build_objects(&self, base_dir: &Path) -> Result<Vec<Box<dyn Hittable>>, SceneFileError> {
+        self.builder(base_dir)?.build_objects(&self.objects)
+    }
+
+    /// Construct the objects of the scene, resolving relative texture paths against `base_dir`.
+    pub fn build_world(&self, base_dir: &Path) -> Result<World, SceneFileError> {
+        numbered_materials(|| {
+            let builder = self.builder(base_dir)?;
+            let node = builder.build_group(&self.objects)?;
+            let environment = builder.build_environment(&self.environment)?;
+            // the lights are copies of objects already in the world, so they're numbered last to
+            // keep them from shifting the IDs of everything else
+            let mut lights = HittableList::new();
+            builder.build_lights(&self.objects, &mut lights)?;
+            Ok(World::new(node, environment).with_lights(lights))
+        })
+    }
+
+    /// A builder for the objects of the scene, with its prototypes already built.
+    fn builder<'a>(&self, base_dir: &'a Path) -> Result<Builder<'a>, SceneFileError> {
+        let mut builder = Builder {
+            base_dir,
+            time0: self.camera.time0,
+            time1: self.camera.time1,
+            prototypes: HashMap::new(),
+        };
+        for prototype in &self.prototypes {
+            if prototype.objects.is_empty() {
+                return Err(SceneFileError::Empty);
+            }
+            let built = Prototype::new(
+                builder.build_objects(&prototype.objects)?,
+                builder.time0,
+                builder.time1,
+            );
+            builder.prototypes.insert(prototype.name.clone(), built);
+        }
+        Ok(builder)
+    }
+}
+
+/// Load a scene description file and construct its world in one step.
+pub fn load_world<P: AsRef<Path>>(path: P) -> Result<World, SceneFileError> {
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    SceneDescription::load(path)?.build_world(base_dir)
+}
+
+struct Builder<'a> {
+    base_dir: &'a Path,
+    time0: f64,
+    time1: f64,
+    prototypes: HashMap<String, Prototype>,
+}
+
+impl Builder<'_> {
+    fn build_group(&self, objects: &[ObjectDescription]) -> Result<BvhNode, SceneFileError> {
+        if objects.is_empty() {
+            return Err(SceneFileError::Empty);
+        }
+        Ok(BvhNode::new(
+            self.build_objects(objects)?,
+            self.time0,
+            self.time1,
+        ))
+    }
+
+    fn build_objects(
+        &self,
+        objects: &[ObjectDescription],
+    ) -> Result<Vec<Box<dyn Hittable>>, SceneFileError> {
+        objects.iter().map(|o| self.build_object(o)).collect()
+    }
+
+    /// Build a second copy of each object that gives off light, so the lights can be sampled
+    /// directly. Lights inside groups are included, since groups don't move their contents.
+    fn build_lights(
+        &self,
+        objects: &[ObjectDescription],
+        lights: &mut HittableList,
+    ) -> Result<(), SceneFileError> {
+        for desc in objects {
+            match desc {
+                ObjectDescription::Group { objects } => self.build_lights(objects, lights)?,
+                desc if desc.emits_light() => lights.add(self.build_object(desc)?),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn build_object(&self, desc: &ObjectDescription) -> Result<Box<dyn Hittable>, SceneFileError> {
+        Ok(match desc {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::stationary(
+                *center,
+                *radius,
+                self.build_material(material)?,
+            )),
+            ObjectDescription::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Box::new(Sphere::moving(
+                *center0,
+                *center1,
+                *time0,
+                *time1,
+                *radius,
+                self.build_material(material)?,
+            )),
+            ObjectDescription::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XyRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.build_material(material)?,
+            )),
+            ObjectDescription::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XzRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.build_material(material)?,
+            )),
+            ObjectDescription::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YzRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.build_material(material)?,
+            )),
+            ObjectDescription::Box3D { min, max, material } => {
+                Box::new(Box3D::new(*min, *max, self.build_material(material)?))
+            }
+            ObjectDescription::ConstantMedium {
+                boundary,
+                texture,
+                density,
+            } => Box::new(ConstantMedium::new(
+                self.build_object(boundary)?,
+                self.build_texture(texture)?,
+                *density,
+            )),
+            ObjectDescription::HeterogeneousMedium {
+                boundary,
+                texture,
+                density,
+            } => Box::new(HeterogeneousMedium::new(
+                self.build_object(boundary)?,
+                self.build_texture(texture)?,
+                self.build_density(density)?,
+            )),
+            ObjectDescription::Translate { offset, object } => {
+                Box::new(Translate::new(*offset, self.build_object(object)?))
+            }
+            ObjectDescription::RotateY { angle, object } => {
+                Box::new(RotateY::new(*angle, self.build_object(object)?))
+            }
+            ObjectDescription::Transform { steps, object } => {
+                check_scales(steps)?;
+                Box::new(steps.iter().fold(
+                    Transform::new(self.build_object(object)?),
+                    |transform, step| match step {
+                        TransformStep::Translate(offset) => transform.translate(*offset),
+                        TransformStep::Rotate { axis, angle } => transform.rotate(*axis, *angle),
+                        TransformStep::Quaternion(rotation) => {
+                            transform.rotate_quaternion(*rotation)
+                        }
+                        TransformStep::Scale(factors) => transform.scale(*factors),
+                    },
+                ))
+            }
+            ObjectDescription::Animated { keyframes, object } => {
+                let keyframes = keyframes
+                    .iter()
+                    .map(|k| {
+                        Keyframe::at(k.time)
+                            .translation(k.translation)
+                            .rotation(k.axis, k.angle)
+                            .scale(k.scale)
+                    })
+                    .collect();
+                Box::new(
+                    AnimatedTransform::new(self.build_object(object)?, keyframes)
+                        .ok_or(SceneFileError::Keyframes)?,
+                )
+            }
+            ObjectDescription::Instance {
+                prototype,
+                steps,
+                material,
+            } => {
+                let prototype = self
+                    .prototypes
+                    .get(prototype)
+                    .ok_or_else(|| SceneFileError::UnknownPrototype(prototype.clone()))?;
+                check_scales(steps)?;
+                let instance =
+                    steps
+                        .iter()
+                        .fold(prototype.instance(), |instance, step| match step {
+                            TransformStep::Translate(offset) => instance.translate(*offset),
+                            TransformStep::Rotate { axis, angle } => instance.rotate(*axis, *angle),
+                            TransformStep::Quaternion(rotation) => {
+                                instance.rotate_quaternion(*rotation)
+                            }
+                            TransformStep::Scale(factors) => instance.scale(*factors),
+                        });
+                Box::new(match material {
+                    Some(material) => instance.material(self.build_material(material)?.into()),
+                    None => instance,
+                })
+            }
+            ObjectDescription::Group { objects } => Box::new(self.build_group(objects)?),
+            ObjectDescription::Mesh { path } => Box::new(
+                load_obj(self.base_dir.join(path), self.time0, self.time1)
+                    .map_err(SceneFileError::Mesh)?,
+            ),
+        })
+    }
+
+    fn build_environment(
+        &self,
+        desc: &EnvironmentDescription,
+    ) -> Result<Environment, SceneFileError> {
+        Ok(match desc {
+            EnvironmentDescription::Color(color) => Environment::Color(*color),
+            EnvironmentDescription::Gradient { bottom, top } => Environment::Gradient {
+                bottom: *bottom,
+                top: *top,
+            },
+            EnvironmentDescription::Image {
+                path,
+                rotation,
+                intensity,
+            } => {
+                let image =
+                    load_image(self.base_dir.join(path)).map_err(SceneFileError::Environment)?;
+                Environment::Map(EnvironmentMap::new(image, *rotation, *intensity))
+            }
+            EnvironmentDescription::Sky(settings) => Environment::Sky(Sky::new(settings)),
+        })
+    }
+
+    fn build_material(
+        &self,
+        desc: &MaterialDescription,
+    ) -> Result<Box<dyn Material>, SceneFileError> {
+        Ok(match desc {
+            MaterialDescription::Lambertian { albedo } => {
+                Box::new(DiffuseLambertianTexture::new(self.build_texture(albedo)?))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => Box::new(Metal::new(*albedo, *fuzz)),
+            MaterialDescription::Conductor { ior, roughness } => {
+                Box::new(Conductor::new(*ior, self.build_texture(roughness)?))
+            }
+            MaterialDescription::Dielectric {
+                ir,
+                dispersion,
+                absorption,
+                priority,
+            } => {
+                let mut dielectric = Dielectric::new(*ir)
+                    .absorption(*absorption)
+                    .priority(*priority);
+                if let Some(dispersion) = dispersion {
+                    dielectric = dielectric.dispersion(*dispersion);
+                }
+                Box::new(dielectric)
+            }
+            MaterialDescription::DiffuseLight { emit } => {
+                Box::new(DiffuseLight::new(self.build_texture(emit)?))
+            }
+            MaterialDescription::Principled(desc) => {
+                let parameter = |desc: &ParameterDescription| -> Result<Parameter, SceneFileError> {
+                    Ok(match desc {
+                        ParameterDescription::Value(value) => Parameter::Value(*value),
+                        ParameterDescription::Texture(texture) => {
+                            Parameter::Texture(self.build_texture(texture)?)
+                        }
+                    })
+                };
+                Box::new(
+                    Principled::new(self.build_texture(&desc.base_color)?)
+                        .metallic(parameter(&desc.metallic)?)
+                        .roughness(parameter(&desc.roughness)?)
+                        .specular(parameter(&desc.specular)?)
+                        .specular_tint(parameter(&desc.specular_tint)?)
+                        .sheen(parameter(&desc.sheen)?)
+                        .clearcoat(parameter(&desc.clearcoat)?)
+                        .transmission(parameter(&desc.transmission)?)
+                        .ior(parameter(&desc.ior)?),
+                )
+            }
+        })
+    }
+
+    fn build_texture(&self, desc: &TextureDescription) -> Result<Box<dyn Texture>, SceneFileError> {
+        Ok(match desc {
+            TextureDescription::Color(color) => Box::new(ColorTexture::new(*color)),
+            TextureDescription::Checker { scale, odd, even } => Box::new(CheckerTexture::new(
+                *scale,
+                self.build_texture(odd)?,
+                self.build_texture(even)?,
+            )),
+            TextureDescription::Position => Box::new(PositionTexture::new()),
+            TextureDescription::Noise { scale } => {
+                Box::new(NoiseTexture::new(Perlin::new(), *scale))
+            }
+            TextureDescription::Turbulence { scale, depth } => {
+                Box::new(TurbulenceTexture::new(Perlin::new(), *scale, *depth))
+            }
+            TextureDescription::Marble { scale, depth } => {
+                Box::new(MarbleTexture::new(Perlin::new(), *scale, *depth))
+            }
+            TextureDescription::Image { path } => {
+                let path = self.base_dir.join(path);
+                Box::new(
+                    ImageTexture::load_from_png(path.to_string_lossy()).map_err(|error| {
+                        SceneFileError::Texture {
+                            path: path.clone(),
+                            error,
+                        }
+                    })?,
+                )
+            }
+        })
+    }
+
+    fn build_density(&self, desc: &DensityDescription) -> Result<Box<dyn Density>, SceneFileError> {
+        Ok(match desc {
+            DensityDescription::Grid {
+                min,
+                max,
+                resolution: (nx, ny, nz),
+                values,
+            } => Box::new(
+                DensityGrid::new(*min, *max, [*nx, *ny, *nz], values.clone()).ok_or(
+                    SceneFileError::DensityGrid {
+                        expected: nx * ny * nz,
+                        found: values.len(),
+                    },
+                )?,
+            ),
+            DensityDescription::Turbulence {
+                density,
+                scale,
+                depth,
+            } => Box::new(Turbulence::new(Perlin::new(), *density, *scale, *depth)),
+            DensityDescription::HeightFog {
+                density,
+                height,
+                falloff,
+            } => Box::new(HeightFog::new(*density, *height, *falloff)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    #[test]
+    fn round_trips_through_ron() {
+        let scene = SceneDescription {
+            environment: EnvironmentDescription::Image {
+                path: PathBuf::from("sky.hdr"),
+                rotation: 90.0,
+                intensity: 1.5,
+            },
+            camera: CameraSettings::default().vfov(40.0),
+            prototypes: vec![PrototypeDescription {
+                name: "ball".to_owned(),
+                objects: vec![ObjectDescription::Sphere {
+                    center: Point3::zero(),
+                    radius: 0.5,
+                    material: MaterialDescription::Dielectric {
+                        ir: 1.5,
+                        dispersion: Some(Dispersion::Cauchy { a: 1.5, b: 0.004 }),
+                        absorption: Color::new(0.0, 0.5, 1.0),
+                        priority: 1,
+                    },
+                }],
+            }],
+            objects: vec![
+                ObjectDescription::Instance {
+                    prototype: "ball".to_owned(),
+                    steps: vec![TransformStep::Translate(Vec3::new(0.0, 3.0, 0.0))],
+                    material: Some(MaterialDescription::Principled(PrincipledDescription {
+                        metallic: ParameterDescription::Value(1.0),
+                        roughness: ParameterDescription::Texture(TextureDescription::Noise {
+                            scale: 2.0,
+                        }),
+                        ..Default::default()
+                    })),
+                },
+                ObjectDescription::Transform {
+                    steps: vec![
+                        TransformStep::Scale(Vec3::new(1.0, 2.0, 1.0)),
+                        TransformStep::Rotate {
+                            axis: Vec3::new(1.0, 0.0, 0.0),
+                            angle: 30.0,
+                        },
+                        TransformStep::Translate(Vec3::new(1.0, 2.0, 3.0)),
+                    ],
+                    object: Box::new(ObjectDescription::Sphere {
+                        center: Point3::zero(),
+                        radius: 1.0,
+                        material: MaterialDescription::Lambertian {
+                            albedo: TextureDescription::Checker {
+                                scale: 10.0,
+                                odd: Box::new(TextureDescription::Color(Color::one())),
+                                even: Box::new(TextureDescription::Position),
+                            },
+                        },
+                    }),
+                },
+                ObjectDescription::HeterogeneousMedium {
+                    boundary: Box::new(ObjectDescription::Sphere {
+                        center: Point3::zero(),
+                        radius: 2.0,
+                        material: MaterialDescription::Lambertian {
+                            albedo: TextureDescription::Color(Color::one()),
+                        },
+                    }),
+                    texture: TextureDescription::Color(Color::one()),
+                    density: DensityDescription::Grid {
+                        min: Point3::new(-2.0, -2.0, -2.0),
+                        max: Point3::new(2.0, 2.0, 2.0),
+                        resolution: (2, 1, 1),
+                        values: vec![0.5, 1.0],
+                    },
+                },
+            ],
+        };
+
+        let parsed = SceneDescription::from_ron_str(&scene.to_ron_string()).unwrap();
+        assert_eq!(parsed, scene);
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(e) = load_world(&path) {
+                panic!("failed to load {}: {}", path.display(), e);
+            }
+            count += 1;
+        }
+        assert!(count > 0, "no example scenes found");
+    }
+
+    #[test]
+    fn instances_need_an_earlier_prototype() {
+        let scene = SceneDescription::from_ron_str(
+            r#"(
+                prototypes: [
+                    (name: "pair", objects: [
+                        Instance(prototype: "ball"),
+                        Instance(prototype: "ball", steps: [Translate((x: 2.0, y: 0.0, z: 0.0))]),
+                    ]),
+                    (name: "ball", objects: [Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5))]),
+                ],
+                objects: [Instance(prototype: "pair")],
+            )"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            scene.build_world(Path::new(".")),
+            Err(SceneFileError::UnknownPrototype(name)) if name == "ball"
+        ));
+
+        let mut reordered = scene.clone();
+        reordered.prototypes.reverse();
+        let world = reordered.build_world(Path::new(".")).unwrap();
+        let r = Ray::new(Point3::new(2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+        let rec = world.node.hit(r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(2.0, 1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn keyframes_at_nan_are_an_error() {
+        let scene = SceneDescription::from_ron_str(
+            r#"(objects: [Animated(
+                keyframes: [(time: 0.0), (time: NaN)],
+                object: Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5)),
+            )])"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            scene.build_world(Path::new(".")),
+            Err(SceneFileError::Keyframes)
+        ));
+    }
+
+    #[test]
+    fn scaling_by_zero_is_an_error() {
+        let scene = SceneDescription::from_ron_str(
+            r#"(objects: [Transform(
+                steps: [Translate((x: 1.0, y: 0.0, z: 0.0)), Scale((x: 1.0, y: 0.0, z: 1.0))],
+                object: Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5)),
+            )])"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            scene.build_world(Path::new(".")),
+            Err(SceneFileError::ZeroScale)
+        ));
+
+        let instanced = SceneDescription::from_ron_str(
+            r#"(
+                prototypes: [(name: "ball", objects: [Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5))])],
+                objects: [Instance(prototype: "ball", steps: [Scale((x: 0.0, y: 1.0, z: 1.0))])],
+            )"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            instanced.build_world(Path::new(".")),
+            Err(SceneFileError::ZeroScale)
+        ));
+    }
+
+    #[test]
+    fn ids_are_the_same_every_time_a_scene_is_built() {
+        let scene = SceneDescription::from_ron_str(
+            r#"(objects: [
+                Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5)),
+                Sphere(center: (x: 3.0, y: 0.0, z: 0.0), radius: 1.0, material: Metal(albedo: (x: 1.0, y: 1.0, z: 1.0), fuzz: 0.0)),
+                Sphere(center: (x: 6.0, y: 0.0, z: 0.0), radius: 1.0, material: DiffuseLight(emit: Color((x: 4.0, y: 4.0, z: 4.0)))),
+            ])"#,
+        )
+        .unwrap();
+        let ids = || {
+            let world = scene.build_world(Path::new(".")).unwrap();
+            [0.0, 3.0, 6.0].map(|x| {
+                let r = Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+                let rec = world.node.hit(r, 0.001, f64::INFINITY).unwrap();
+                (rec.object_id, rec.mat_ptr.material_id())
+            })
+        };
+        let first = ids();
+        // making other materials in between doesn't change the numbering
+        let _ = Dielectric::new(1.5);
+        assert_eq!(ids(), first);
+        assert_eq!(first, [(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn unreadable_scene_files_have_no_default_camera() {
+        let scene = crate::scenes::RenderScene::File("scenes/missing.ron".to_owned());
+        assert!(scene.default_camera_settings().is_err());
+    }
+
+    #[test]
+    fn empty_scene_is_an_error() {
+        let scene = SceneDescription::from_ron_str("(objects: [])").unwrap();
+        assert!(matches!(
+            scene.build_world(Path::new(".")),
+            Err(SceneFileError::Empty)
+        ));
+    }
+
+    #[test]
+    fn density_grids_need_a_value_per_voxel() {
+        let scene = SceneDescription::from_ron_str(
+            r#"(objects: [HeterogeneousMedium(
+                boundary: Sphere(center: (x: 0.0, y: 0.0, z: 0.0), radius: 1.0, material: Dielectric(ir: 1.5)),
+                texture: Color((x: 1.0, y: 1.0, z: 1.0)),
+                density: Grid(min: (x: -1.0, y: -1.0, z: -1.0), max: (x: 1.0, y: 1.0, z: 1.0), resolution: (2, 2, 2), values: [1.0, 0.5]),
+            )])"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            scene.build_world(Path::new(".")),
+            Err(SceneFileError::DensityGrid {
+                expected: 8,
+                found: 2
+            })
+        ));
+    }
+}
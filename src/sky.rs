@@ -0,0 +1,364 @@
+//! A physically based daylight sky with a sun, from Preetham, Shirley and Smits, "A Practical
+//! Analytic Model for Daylight" (1999).
+//!
+//! The sky's brightness and color come from the paper's fits to measured skies, which depend on
+//! the sun's position and on the turbidity (how hazy the air is: 2 is very clear, 10 is hazy).
+//! The sun is a small disk whose color comes from how much of each wavelength the atmosphere
+//! scatters away on the way down, so that it reddens towards the horizon.
+//!
+//! One unit of radiance stands for 20,000 cd/m², which makes the clear sky overhead about as
+//! bright as the default white-to-blue sky.
+
+use std::f64::consts::PI;
+
+use crate::{
+    onb::Onb,
+    util::{self, degrees_to_radians},
+    vec3::{Color, Vec3},
+};
+
+/// Radiance per kcd/m² of luminance.
+const RADIANCE_PER_KCD: f64 = 0.05;
+/// Luminance of the sun above the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 2.0e6;
+/// Angular radius of the sun's disk, in degrees.
+const SUN_RADIUS: f64 = 0.2665;
+/// The wavelengths (in micrometers) that the red, green and blue channels stand for when working
+/// out the sun's color.
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+/// Chance of sampling a direction towards the sun rather than anywhere in the sky.
+const SUN_SAMPLING: f64 = 0.5;
+
+/// Where the sun is in the sky.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SunPosition {
+    /// Degrees above the horizon, and degrees clockwise from north seen from above, where north
+    /// is -z and east is +x
+    Angles { elevation: f64, azimuth: f64 },
+    /// Where the sun is at `hour` (local solar time, so noon is 12) on day `day_of_year` (from 1
+    /// to 365) at `latitude` degrees north, with the same compass as [`SunPosition::Angles`]
+    Time {
+        day_of_year: u32,
+        hour: f64,
+        latitude: f64,
+    },
+}
+
+impl SunPosition {
+    /// The sun's elevation and azimuth, in degrees.
+    pub fn angles(&self) -> (f64, f64) {
+        match *self {
+            SunPosition::Angles { elevation, azimuth } => (elevation, azimuth),
+            SunPosition::Time {
+                day_of_year,
+                hour,
+                latitude,
+            } => {
+                // Cooper's approximation of the declination, and 15 degrees per hour from noon
+                let declination = degrees_to_radians(
+                    23.44 * (2.0 * PI * (284.0 + day_of_year as f64) / 365.0).sin(),
+                );
+                let hour_angle = degrees_to_radians(15.0 * (hour - 12.0));
+                let latitude = degrees_to_radians(latitude);
+
+                let sin_elevation = latitude.sin() * declination.sin()
+                    + latitude.cos() * declination.cos() * hour_angle.cos();
+                let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+                let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin())
+                    / (elevation.cos() * latitude.cos()).max(1e-9);
+                let azimuth = cos_azimuth.clamp(-1.0, 1.0).acos().to_degrees();
+                // the sun is in the east in the morning and the west in the afternoon
+                let azimuth = if hour_angle > 0.0 {
+                    360.0 - azimuth
+                } else {
+                    azimuth
+                };
+                (elevation.to_degrees(), azimuth)
+            }
+        }
+    }
+
+    /// Unit vector pointing at the sun.
+    pub fn direction(&self) -> Vec3 {
+        let (elevation, azimuth) = self.angles();
+        let (elevation, azimuth) = (degrees_to_radians(elevation), degrees_to_radians(azimuth));
+        Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        )
+    }
+}
+
+/// The settings of a [`Sky`], as they're stored in configs and scene files.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SkySettings {
+    /// How hazy the air is, from 1.7 (very clear) to 10 (hazy)
+    pub turbidity: f64,
+    pub sun: SunPosition,
+    /// Brightness multiplier for both the sky and the sun
+    pub intensity: f64,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            turbidity: 3.0,
+            sun: SunPosition::Angles {
+                elevation: 45.0,
+                azimuth: 135.0,
+            },
+            intensity: 1.0,
+        }
+    }
+}
+
+/// A Preetham sky, ready for looking up the light from any direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    /// Unit vector pointing at the sun
+    sun: Vec3,
+    /// The cosine of the sun's angular radius
+    sun_cos_radius: f64,
+    /// Radiance of the sun's disk, or zero if it's below the horizon
+    sun_radiance: Color,
+    /// Luminance (in kcd/m²) and x and y chromaticity straight up
+    zenith: [f64; 3],
+    /// Perez distribution coefficients A to E for luminance and the two chromaticities
+    perez: [[f64; 5]; 3],
+    /// The Perez function at the zenith, which the sky is normalized by
+    perez_zenith: [f64; 3],
+    intensity: f64,
+}
+
+impl Sky {
+    pub fn new(settings: &SkySettings) -> Self {
+        let t = settings.turbidity.clamp(1.7, 10.0);
+        let sun = settings.sun.direction();
+        // the model only covers suns above the horizon, so lower ones are treated as setting
+        let theta_s = sun.y.clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let ts = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| ts[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let perez_zenith = perez.map(|coefficients| perez_function(coefficients, 1.0, theta_s));
+
+        Self {
+            sun,
+            sun_cos_radius: degrees_to_radians(SUN_RADIUS).cos(),
+            sun_radiance: if sun.y > 0.0 {
+                sun_transmittance(t, sun.y) * SUN_LUMINANCE * RADIANCE_PER_KCD
+            } else {
+                Color::zero()
+            },
+            zenith: [zenith_luminance.max(0.0), zenith_x, zenith_y],
+            perez,
+            perez_zenith,
+            intensity: settings.intensity,
+        }
+    }
+
+    /// The light arriving from `direction`. Below the horizon, the sky just below the horizon
+    /// carries on, since there's no ground.
+    pub fn color(&self, direction: Vec3) -> Color {
+        let d = direction.to_unit();
+        let cos_gamma = d.dot(self.sun).clamp(-1.0, 1.0);
+        let mut color = self.sky_color(d.y.max(0.0), cos_gamma);
+        if cos_gamma >= self.sun_cos_radius {
+            color += self.sun_radiance;
+        }
+        self.intensity * color
+    }
+
+    fn sky_color(&self, cos_theta: f64, cos_gamma: f64) -> Color {
+        let gamma = cos_gamma.acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez_function(self.perez[i], cos_theta, gamma) / self.perez_zenith[i]
+        });
+        xyy_to_linear_srgb(x, y, luminance * RADIANCE_PER_KCD)
+    }
+
+    fn sun_cone_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.sun_cos_radius))
+    }
+
+    /// Probability density (per unit solid angle) of [`Sky::random_direction`] picking
+    /// `direction`: a mixture of aiming at the sun and picking any direction at all.
+    pub fn pdf_value(&self, direction: Vec3) -> f64 {
+        let uniform = 1.0 / (4.0 * PI);
+        if self.sun_radiance == Color::zero() {
+            return uniform;
+        }
+        let toward_sun = if direction.to_unit().dot(self.sun) >= self.sun_cos_radius {
+            self.sun_cone_pdf()
+        } else {
+            0.0
+        };
+        SUN_SAMPLING * toward_sun + (1.0 - SUN_SAMPLING) * uniform
+    }
+
+    pub fn random_direction(&self) -> Vec3 {
+        if self.sun_radiance == Color::zero() || util::random_double_unit() >= SUN_SAMPLING {
+            return Vec3::random_unit_vector();
+        }
+        // uniformly within the cone that the sun's disk covers
+        let (r1, r2) = util::random_pair();
+        let z = 1.0 + r2 * (self.sun_cos_radius - 1.0);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * r1;
+        Onb::from_w(self.sun).local(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+    }
+}
+
+/// The Perez et al. sky distribution, for a direction `gamma` radians from the sun whose angle
+/// from straight up has cosine `cos_theta`.
+fn perez_function([a, b, c, d, e]: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    // B is negative, so looking along the horizon this goes to 1 rather than infinity
+    let horizon = 1.0 + a * (b / cos_theta).exp();
+    horizon * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// How much of the sun's light makes it through the atmosphere at each wavelength, when it's
+/// `cos_zenith` from straight up: Rayleigh scattering by the air and Ångström's formula for
+/// scattering by haze, as in the paper's appendix.
+fn sun_transmittance(turbidity: f64, cos_zenith: f64) -> Color {
+    // Kasten and Young's relative air mass, which stays finite at the horizon
+    let zenith_degrees = cos_zenith.acos().to_degrees();
+    let air_mass = 1.0 / (cos_zenith + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let [r, g, b] = WAVELENGTHS.map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let haze = beta * lambda.powf(-1.3);
+        (-(rayleigh + haze) * air_mass).exp()
+    });
+    Color::new(r, g, b)
+}
+
+/// Convert a chromaticity and luminance to linear sRGB.
+fn xyy_to_linear_srgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::zero();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::luminance;
+
+    fn sky_with_sun_at(elevation: f64) -> Sky {
+        Sky::new(&SkySettings {
+            sun: SunPosition::Angles {
+                elevation,
+                azimuth: 90.0,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn noon_sky_is_blue_and_sun_is_much_brighter() {
+        let sky = sky_with_sun_at(60.0);
+        let up = sky.color(Vec3::new(0.0, 1.0, 0.0));
+        assert!(up.z > up.x, "the sky overhead is {}", up);
+        assert!((0.1..2.0).contains(&luminance(up)), "the sky is {}", up);
+
+        let sun = sky.color(sky.sun);
+        assert!(luminance(sun) > 1000.0 * luminance(up));
+        // the sky is brightest around the sun
+        let near_sun = sky.color(sky.sun + Vec3::new(0.0, 0.1, 0.0));
+        let opposite = sky.color(Vec3::new(-1.0, 0.3, 0.0));
+        assert!(luminance(near_sun) > luminance(opposite));
+    }
+
+    #[test]
+    fn low_sun_is_redder() {
+        let high = sky_with_sun_at(60.0).sun_radiance;
+        let low = sky_with_sun_at(3.0).sun_radiance;
+        assert!(low.z / low.x < high.z / high.x);
+        assert_eq!(sky_with_sun_at(-10.0).sun_radiance, Color::zero());
+    }
+
+    #[test]
+    fn sun_position_follows_the_time_of_day() {
+        let at = |hour| SunPosition::Time {
+            day_of_year: 172,
+            hour,
+            latitude: 50.0,
+        };
+        // midsummer noon at 50 degrees north is 50 - 23.44 degrees from straight up, due south
+        let (elevation, azimuth) = at(12.0).angles();
+        assert!((elevation - 63.44).abs() < 0.1, "elevation {}", elevation);
+        assert!((azimuth - 180.0).abs() < 0.1, "azimuth {}", azimuth);
+        assert!(at(9.0).direction().x > 0.0, "morning sun is in the east");
+        assert!(at(15.0).direction().x < 0.0, "afternoon sun is in the west");
+        assert!(
+            at(0.0).angles().0 < 0.0,
+            "midnight sun is below the horizon"
+        );
+    }
+
+    #[test]
+    fn sampling_density_integrates_to_one() {
+        let sky = sky_with_sun_at(30.0);
+        // split the integral into the sun's cone and the rest, since the cone is tiny
+        let cone = 2.0 * PI * (1.0 - sky.sun_cos_radius);
+        let inside = sky.pdf_value(sky.sun) * cone;
+        let outside = sky.pdf_value(-sky.sun) * (4.0 * PI - cone);
+        assert!((inside + outside - 1.0).abs() < 1e-9);
+
+        let toward_sun = util::with_seed(1, || {
+            (0..1000)
+                .filter(|_| sky.random_direction().dot(sky.sun) >= sky.sun_cos_radius)
+                .count()
+        });
+        assert!((400..600).contains(&toward_sun), "{}", toward_sun);
+    }
+}
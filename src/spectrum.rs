@@ -0,0 +1,307 @@
+//! Conversions between colors and spectra, for rendering one wavelength of light at a time (see
+//! [`crate::RayColorMode::Spectral`]).
+//!
+//! Colors are turned into smooth spectra with Smits' method ("An RGB to Spectrum Conversion for
+//! Reflectances", 1999), and light of a single wavelength is turned back into a color with the CIE
+//! 1931 color matching functions, as fitted by Wyman, Sloan and Shirley ("Simple Analytic
+//! Approximations to the CIE XYZ Color Matching Functions", 2013).
+
+use std::{
+    ops::{Add, Mul},
+    sync::OnceLock,
+};
+
+use crate::vec3::Color;
+
+/// Shortest wavelength rendered, in nanometres.
+pub const MIN_WAVELENGTH: f64 = 380.0;
+/// Longest wavelength rendered, in nanometres.
+pub const MAX_WAVELENGTH: f64 = 720.0;
+/// The yellow sodium line at 587.6 nm, where indices of refraction are usually quoted.
+pub const D_LINE: f64 = 587.56;
+
+/// Smits' basis spectra, sampled at 10 evenly spaced wavelengths from [`MIN_WAVELENGTH`] to
+/// [`MAX_WAVELENGTH`].
+const WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// A smooth spectrum with the color `c`, evaluated at `wavelength`. Colors with components above 1
+/// (like those of lights) give spectra scaled up to match.
+pub fn from_rgb(c: Color, wavelength: f64) -> f64 {
+    let position = (wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH) * 9.0;
+    let position = position.clamp(0.0, 9.0);
+    let i = (position as usize).min(8);
+    let t = position - i as f64;
+    let at = |basis: &[f64; 10]| basis[i] + t * (basis[i + 1] - basis[i]);
+
+    // the smallest component is white, the middle one adds a mix of two primaries and the largest
+    // adds one primary on its own
+    let (r, g, b) = (c.x, c.y, c.z);
+    if r <= g && r <= b {
+        if g <= b {
+            r * at(&WHITE) + (g - r) * at(&CYAN) + (b - g) * at(&BLUE)
+        } else {
+            r * at(&WHITE) + (b - r) * at(&CYAN) + (g - b) * at(&GREEN)
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            g * at(&WHITE) + (r - g) * at(&MAGENTA) + (b - r) * at(&BLUE)
+        } else {
+            g * at(&WHITE) + (b - g) * at(&MAGENTA) + (r - b) * at(&RED)
+        }
+    } else if r <= g {
+        b * at(&WHITE) + (r - b) * at(&YELLOW) + (g - r) * at(&GREEN)
+    } else {
+        b * at(&WHITE) + (g - b) * at(&YELLOW) + (r - g) * at(&RED)
+    }
+}
+
+/// A gaussian lobe centered on `mean`, with different widths either side of it.
+fn lobe(wavelength: f64, mean: f64, width_below: f64, width_above: f64) -> f64 {
+    let width = if wavelength < mean {
+        width_below
+    } else {
+        width_above
+    };
+    let x = (wavelength - mean) / width;
+    (-0.5 * x * x).exp()
+}
+
+/// The CIE XYZ color matching functions at `wavelength`.
+fn xyz_matching(wavelength: f64) -> [f64; 3] {
+    let l = wavelength;
+    [
+        1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(l, 501.1, 20.4, 26.2),
+        0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+        1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+    ]
+}
+
+/// How much light of `wavelength` adds to each linear sRGB component (which is negative outside
+/// the sRGB gamut), before normalizing.
+fn unnormalized_rgb_matching(wavelength: f64) -> Color {
+    let [x, y, z] = xyz_matching(wavelength);
+    Color::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+/// The integral of [`unnormalized_rgb_matching`] over the rendered wavelengths, which scales it
+/// so that a flat spectrum of 1 turns into white.
+fn rgb_matching_integral() -> Color {
+    static INTEGRAL: OnceLock<Color> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| {
+        let steps = 1000;
+        let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / steps as f64;
+        (0..steps)
+            .map(|i| unnormalized_rgb_matching(MIN_WAVELENGTH + (i as f64 + 0.5) * step) * step)
+            .sum()
+    })
+}
+
+/// How much light of `wavelength` adds to each linear sRGB component, per nanometre.
+pub fn rgb_matching(wavelength: f64) -> Color {
+    let integral = rgb_matching_integral();
+    let rgb = unnormalized_rgb_matching(wavelength);
+    Color::new(rgb.x / integral.x, rgb.y / integral.y, rgb.z / integral.z)
+}
+
+/// How many wavelengths each path carries light of.
+pub const PATH_WAVELENGTHS: usize = 4;
+
+/// An amount of light (or a fraction of it) at each of a path's wavelengths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spectrum(pub [f64; PATH_WAVELENGTHS]);
+
+impl Spectrum {
+    pub fn zero() -> Self {
+        Self([0.0; PATH_WAVELENGTHS])
+    }
+
+    pub fn one() -> Self {
+        Self([1.0; PATH_WAVELENGTHS])
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, rhs: Spectrum) -> Spectrum {
+        Spectrum(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, rhs: Spectrum) -> Spectrum {
+        Spectrum(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+/// The wavelengths a path carries light of. The first (the *hero* wavelength) is picked at random
+/// and the rest are spread evenly after it, wrapping round, so a path covers the whole spectrum
+/// ("Hero Wavelength Spectral Sampling", Wilkie et al. 2014).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    wavelengths: [f64; PATH_WAVELENGTHS],
+    /// Whether the path has been split up by wavelength, after which it only carries the hero
+    dispersed: bool,
+}
+
+impl Wavelengths {
+    /// Pick the hero wavelength with `u` from 0 to 1.
+    pub fn sample(u: f64) -> Self {
+        Self {
+            wavelengths: std::array::from_fn(|i| {
+                let u = (u + i as f64 / PATH_WAVELENGTHS as f64).fract();
+                MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+            }),
+            dispersed: false,
+        }
+    }
+
+    /// The wavelength that decides which way light goes when it's bent by a different amount
+    /// for each wavelength.
+    pub fn hero(&self) -> f64 {
+        self.wavelengths[0]
+    }
+
+    /// The spectrum of [`from_rgb`] at each wavelength.
+    pub fn from_rgb(&self, c: Color) -> Spectrum {
+        Spectrum(self.wavelengths.map(|wavelength| from_rgb(c, wavelength)))
+    }
+
+    /// Disperse the path, which from then on can only carry the hero wavelength: returns the
+    /// wavelengths it carries afterwards, and `s` with the hero wavelength standing in for the
+    /// others.
+    pub fn disperse(self, s: Spectrum) -> (Self, Spectrum) {
+        if self.dispersed {
+            return (self, s);
+        }
+        let mut hero_only = Spectrum::zero();
+        hero_only.0[0] = s.0[0] * PATH_WAVELENGTHS as f64;
+        (
+            Self {
+                dispersed: true,
+                ..self
+            },
+            hero_only,
+        )
+    }
+
+    /// The color of `light` at these wavelengths, divided by the probability density of picking
+    /// them.
+    pub fn to_rgb(&self, light: Spectrum) -> Color {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        self.wavelengths
+            .iter()
+            .zip(light.0)
+            .map(|(&wavelength, light)| rgb_matching(wavelength) * light)
+            .sum::<Color>()
+            * range
+            / PATH_WAVELENGTHS as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Turn `c` into a spectrum and back again.
+    fn round_trip(c: Color) -> Color {
+        let steps = 1000;
+        (0..steps)
+            .map(|i| {
+                let wavelengths = Wavelengths::sample((i as f64 + 0.5) / steps as f64);
+                wavelengths.to_rgb(wavelengths.from_rgb(c))
+            })
+            .sum::<Color>()
+            / steps as f64
+    }
+
+    #[test]
+    fn colors_survive_being_turned_into_spectra() {
+        let white = round_trip(Color::one());
+        assert!((white - Color::one()).length() < 0.01, "white is {}", white);
+        // lights are brighter, but the same color
+        let light = round_trip(Color::new(4.0, 4.0, 4.0));
+        assert!((light - 4.0 * white).length() < 1e-9, "{}", light);
+
+        for c in [
+            Color::new(0.8, 0.2, 0.1),
+            Color::new(0.2, 0.6, 0.3),
+            Color::new(0.1, 0.2, 0.7),
+            Color::new(0.9, 0.8, 0.3),
+        ] {
+            let result = round_trip(c);
+            assert!((result - c).length() < 0.1, "{} came back as {}", c, result);
+        }
+    }
+
+    #[test]
+    fn paths_carry_wavelengths_across_the_whole_spectrum() {
+        let wavelengths = Wavelengths::sample(0.9);
+        assert!((wavelengths.hero() - (MIN_WAVELENGTH + 0.9 * 340.0)).abs() < 1e-9);
+        let mut sorted = wavelengths.wavelengths;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for pair in sorted.windows(2) {
+            assert!((pair[1] - pair[0] - 85.0).abs() < 1e-9, "{:?}", sorted);
+        }
+
+        // the hero wavelength stands in for all of them once a path is dispersed, however many
+        // times that happens
+        let (dispersed, spectrum) = wavelengths.disperse(Spectrum::one());
+        assert_eq!(spectrum.0, [4.0, 0.0, 0.0, 0.0]);
+        assert_eq!(dispersed.disperse(spectrum).1, spectrum);
+        let steps = 1000;
+        let dispersed_white = (0..steps)
+            .map(|i| {
+                let wavelengths = Wavelengths::sample((i as f64 + 0.5) / steps as f64);
+                let (wavelengths, spectrum) = wavelengths.disperse(Spectrum::one());
+                wavelengths.to_rgb(spectrum)
+            })
+            .sum::<Color>()
+            / steps as f64;
+        assert!(
+            (dispersed_white - Color::one()).length() < 0.01,
+            "{}",
+            dispersed_white
+        );
+    }
+
+    #[test]
+    fn spectra_of_colors_are_between_their_components() {
+        let c = Color::new(0.8, 0.2, 0.1);
+        for i in 0..=100 {
+            let wavelength = MIN_WAVELENGTH + (MAX_WAVELENGTH - MIN_WAVELENGTH) * i as f64 / 100.0;
+            let value = from_rgb(c, wavelength);
+            assert!((0.0..=0.82).contains(&value), "{} at {}", value, wavelength);
+        }
+        // red at the red end, and not at the blue end
+        assert!(from_rgb(c, 700.0) > 0.75);
+        assert!(from_rgb(c, 450.0) < 0.15);
+    }
+}
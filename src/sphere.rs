@@ -2,12 +2,14 @@ use crate::{
     aabb::Aabb,
     hittable::{HitRecord, Hittable},
     material::Material,
+    onb::Onb,
     ray::Ray,
+    util::random_pair,
     vec3::{Point3, Vec3},
 };
 
 #[derive(Debug)]
-pub(crate) struct Sphere {
+pub struct Sphere {
     pub center0: Point3,
     pub center1: Point3,
     pub time0: f64,
@@ -17,7 +19,7 @@ pub(crate) struct Sphere {
 }
 
 impl Sphere {
-    pub(crate) fn moving(
+    pub fn moving(
         center0: Point3,
         center1: Point3,
         time0: f64,
@@ -35,7 +37,7 @@ impl Sphere {
         }
     }
 
-    pub(crate) fn stationary(
+    pub fn stationary(
         center: Point3,
         radius: f64,
         material: Box<dyn Material + Send + Sync>,
@@ -50,7 +52,7 @@ impl Sphere {
         }
     }
 
-    pub(crate) fn get_sphere_uv(p: Point3) -> (f64, f64) {
+    pub fn get_sphere_uv(p: Point3) -> (f64, f64) {
         let theta = (-p.y).acos();
         let phi = (-p.z).atan2(p.x) + std::f64::consts::PI;
         let u = phi / (2.0 * std::f64::consts::PI);
@@ -58,7 +60,7 @@ impl Sphere {
         (u, v)
     }
 
-    pub(crate) fn center(&self, time: f64) -> Point3 {
+    pub fn center(&self, time: f64) -> Point3 {
         if (self.time0 - self.time1).abs() < f64::EPSILON {
             self.center0
         } else {
@@ -69,7 +71,7 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin() - self.center(r.time());
         let a = r.direction().length_squared();
         let half_b = oc.dot(r.direction());
@@ -113,4 +115,49 @@ impl Hittable for Sphere {
 
         Some(Aabb::surrounding_box(box0, box1))
     }
+
+    fn pdf_value(&self, r: Ray) -> f64 {
+        if self.hit(r, 0.001, f64::INFINITY).is_none() {
+            return 0.0;
+        }
+        match self.cos_theta_max(r.origin(), r.time()) {
+            Some(cos_theta_max) => 1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_theta_max)),
+            None => 1.0 / (4.0 * std::f64::consts::PI),
+        }
+    }
+
+    /// Picks directions uniformly within the cone that the sphere subtends, or in any direction if
+    /// `origin` is inside the sphere.
+    fn random_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        let direction = self.center(time) - origin;
+        match self.cos_theta_max(origin, time) {
+            Some(cos_theta_max) => Onb::from_w(direction).local(random_to_sphere(cos_theta_max)),
+            None => Vec3::random_unit_vector(),
+        }
+    }
+}
+
+impl Sphere {
+    /// Cosine of the angle between the center of the sphere and its edge as seen from `origin`, or
+    /// `None` if `origin` is inside the sphere.
+    fn cos_theta_max(&self, origin: Point3, time: f64) -> Option<f64> {
+        let distance_squared = (self.center(time) - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            None
+        } else {
+            Some((1.0 - radius_squared / distance_squared).sqrt())
+        }
+    }
+}
+
+/// A random direction around the z axis, no more than `acos(cos_theta_max)` away from it.
+fn random_to_sphere(cos_theta_max: f64) -> Vec3 {
+    let (r1, r2) = random_pair();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+    Vec3::new(x, y, z)
 }
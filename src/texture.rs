@@ -6,18 +6,18 @@ use crate::{
 use derive_more::Constructor;
 use rgb::RGB8;
 
-pub(crate) trait Texture: std::fmt::Debug + Sync + Send {
+pub trait Texture: std::fmt::Debug + Sync + Send {
     fn value(&self, u: f64, v: f64, p: Vec3) -> Color;
 }
 
 /// A texture that is a single solid color.
 #[derive(Clone, Copy, Debug, Constructor)]
-pub(crate) struct ColorTexture {
+pub struct ColorTexture {
     pub color_value: Color,
 }
 
 impl ColorTexture {
-    pub(crate) fn from_rgb(r: f64, g: f64, b: f64) -> Self {
+    pub fn from_rgb(r: f64, g: f64, b: f64) -> Self {
         Self {
             color_value: Color::new(r, g, b),
         }
@@ -32,14 +32,14 @@ impl Texture for ColorTexture {
 
 /// A texture that is checkered; looks cool, but also useful for partially overlaying a debug texture.
 #[derive(Debug, Clone, Constructor)]
-pub(crate) struct CheckerTexture<O: Texture, E: Texture> {
+pub struct CheckerTexture<O: Texture + ?Sized, E: Texture + ?Sized> {
     scale: f64,
     odd: Box<O>,
     even: Box<E>,
 }
 
 impl CheckerTexture<ColorTexture, ColorTexture> {
-    pub(crate) fn from_colors(scale: f64, a: Color, b: Color) -> Self {
+    pub fn from_colors(scale: f64, a: Color, b: Color) -> Self {
         Self {
             scale,
             odd: Box::new(ColorTexture::new(a)),
@@ -48,7 +48,7 @@ impl CheckerTexture<ColorTexture, ColorTexture> {
     }
 }
 
-impl<O: Texture, E: Texture> Texture for CheckerTexture<O, E> {
+impl<O: Texture + ?Sized, E: Texture + ?Sized> Texture for CheckerTexture<O, E> {
     fn value(&self, u: f64, v: f64, p: Vec3) -> Color {
         let sines = (self.scale * p.x).sin() * (self.scale * p.y).sin() * (self.scale * p.z).sin();
         if sines < 0.0 {
@@ -61,7 +61,7 @@ impl<O: Texture, E: Texture> Texture for CheckerTexture<O, E> {
 
 /// A texture that is colored based on the position that it is struck by a ray (in world coordinates).
 #[derive(Debug, Constructor, Clone, Copy)]
-pub(crate) struct PositionTexture {}
+pub struct PositionTexture {}
 
 impl Texture for PositionTexture {
     fn value(&self, _u: f64, _v: f64, p: Vec3) -> Color {
@@ -71,7 +71,7 @@ impl Texture for PositionTexture {
 
 /// A texture which is colored based on a provided noise source.
 #[derive(Debug, Constructor, Clone)]
-pub(crate) struct NoiseTexture {
+pub struct NoiseTexture {
     noise: Perlin,
     scale: f64,
 }
@@ -85,7 +85,7 @@ impl Texture for NoiseTexture {
 /// A texture which is colored based on a provided noise source providing turbulence (multiple
 /// layers of noise).
 #[derive(Debug, Constructor, Clone)]
-pub(crate) struct TurbulenceTexture {
+pub struct TurbulenceTexture {
     noise: Perlin,
     scale: f64,
     depth: i32,
@@ -99,7 +99,7 @@ impl Texture for TurbulenceTexture {
 
 /// A procedural marble texture based on turbulated noise.
 #[derive(Debug, Constructor, Clone)]
-pub(crate) struct MarbleTexture {
+pub struct MarbleTexture {
     noise: Perlin,
     scale: f64,
     depth: i32,
@@ -118,14 +118,14 @@ impl Texture for MarbleTexture {
 
 /// A texture based on an image.
 #[derive(Debug, Constructor, Clone)]
-pub(crate) struct ImageTexture {
+pub struct ImageTexture {
     data: Vec<RGB8>,
     width: usize,
     height: usize,
 }
 
 impl ImageTexture {
-    pub(crate) fn load_from_png<S: AsRef<str>>(path: S) -> lodepng::Result<Self> {
+    pub fn load_from_png<S: AsRef<str>>(path: S) -> lodepng::Result<Self> {
         lodepng::decode24_file(path.as_ref()).map(|bitmap| ImageTexture {
             data: bitmap.buffer,
             width: bitmap.width,
@@ -0,0 +1,186 @@
+//! Turning the linear radiance that the renderer computes into colors that can be displayed.
+
+use rgb::RGB8;
+
+use crate::{
+    color::{linear_to_srgb, luminance},
+    vec3::Color,
+};
+
+/// How to squeeze radiance values of any brightness into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ToneMapOperator {
+    /// Cut off anything brighter than white, which loses all detail in bright areas
+    #[default]
+    Clamp,
+    /// Reinhard's `L / (1 + L)` curve applied to luminance, which never quite reaches white
+    Reinhard,
+    /// John Hable's filmic curve from Uncharted 2, with a toe that deepens shadows
+    Filmic,
+    /// Krzysztof Narkowicz's fit of the ACES reference rendering transform
+    Aces,
+}
+
+impl ToneMapOperator {
+    /// Map a linear value (after exposure) to a displayable linear value between 0 and 1.
+    fn map(self, c: Color) -> Color {
+        match self {
+            ToneMapOperator::Clamp => c,
+            ToneMapOperator::Reinhard => {
+                let l = luminance(c);
+                if l > 0.0 {
+                    c * (1.0 / (1.0 + l))
+                } else {
+                    c
+                }
+            }
+            ToneMapOperator::Filmic => {
+                const WHITE_POINT: f64 = 11.2;
+                let white_scale = 1.0 / hable(WHITE_POINT);
+                // Hable's suggested exposure bias, so that the default exposure looks similar to
+                // the other operators
+                let c = 2.0 * c;
+                Color::new(hable(c.x), hable(c.y), hable(c.z)) * white_scale
+            }
+            ToneMapOperator::Aces => Color::new(aces(c.x), aces(c.y), aces(c.z)),
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+/// Settings for turning a high dynamic range render into a displayable image.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Brightness adjustment in stops, applied before the operator: each +1 doubles the brightness
+    pub exposure: f64,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::default(),
+            exposure: 0.0,
+        }
+    }
+}
+
+impl std::str::FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "filmic" => Ok(ToneMapOperator::Filmic),
+            "aces" => Ok(ToneMapOperator::Aces),
+            _ => Err(format!(
+                "unknown tone mapping operator '{}', expected one of: clamp, reinhard, filmic, aces",
+                s
+            )),
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Tone map a linear radiance value to a linear displayable color between 0 and 1.
+    pub fn map(&self, radiance: Color) -> Color {
+        let exposed = radiance * 2f64.powf(self.exposure);
+        let mapped = self.operator.map(exposed);
+        Color::new(
+            mapped.x.clamp(0.0, 1.0),
+            mapped.y.clamp(0.0, 1.0),
+            mapped.z.clamp(0.0, 1.0),
+        )
+    }
+
+    /// Tone map a linear radiance value to an 8 bit sRGB color.
+    pub fn to_rgb8(&self, radiance: Color) -> RGB8 {
+        let mapped = self.map(radiance);
+        let encode = |v: f64| (255.0 * linear_to_srgb(v) + 0.5) as u8;
+        RGB8 {
+            r: encode(mapped.x),
+            g: encode(mapped.y),
+            b: encode(mapped.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 4] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::Filmic,
+        ToneMapOperator::Aces,
+    ];
+
+    #[test]
+    fn black_stays_black_and_bright_values_saturate() {
+        for operator in OPERATORS {
+            let tone_mapping = ToneMapping {
+                operator,
+                exposure: 0.0,
+            };
+            assert_eq!(
+                tone_mapping.to_rgb8(Color::zero()),
+                RGB8 { r: 0, g: 0, b: 0 },
+                "{:?}",
+                operator
+            );
+            let bright = tone_mapping.to_rgb8(Color::new(1000.0, 1000.0, 1000.0));
+            assert!(
+                bright.r >= 250,
+                "{:?} mapped bright to {:?}",
+                operator,
+                bright
+            );
+        }
+    }
+
+    #[test]
+    fn operators_preserve_brightness_ordering() {
+        for operator in OPERATORS {
+            let tone_mapping = ToneMapping {
+                operator,
+                exposure: 0.0,
+            };
+            let levels = [0.01, 0.1, 0.5, 1.0, 4.0]
+                .iter()
+                .map(|&v| tone_mapping.map(Color::new(v, v, v)).x)
+                .collect::<Vec<_>>();
+            assert!(
+                levels
+                    .windows(2)
+                    .all(|pair| pair[0] < pair[1] || pair[1] == 1.0),
+                "{:?} gave {:?}",
+                operator,
+                levels
+            );
+        }
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tone_mapping = ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: 1.0,
+        };
+        assert_eq!(
+            tone_mapping.map(Color::new(0.25, 0.25, 0.25)),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+}
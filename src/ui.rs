@@ -1,4 +1,4 @@
-use std::{collections::HashMap, io::Write, ops::Rem};
+use std::{collections::HashMap, io::Write, ops::Rem, path::Path};
 
 use eframe::{
     egui::{self, TextureId},
@@ -6,46 +6,156 @@ use eframe::{
 };
 use rgb::RGB8;
 
-use crate::{
-    color::rgb8_as_terminal_char,
+use raytracer::{
+    aov::Aov,
+    camera::CameraSettings,
+    color::{rgb8_as_terminal_char, rgb_to_color},
+    denoise::{denoise, Denoising, MAX_ITERATIONS},
+    image_file::{aov_path, save_image, ExrPrecision, ImageFileError, ImageFormat},
+    sampler::SamplerKind,
+    scenes::RenderScene,
+    sky::{SkySettings, SunPosition},
+    tone_map::{ToneMapOperator, ToneMapping},
     vec3::{Color, Vec3},
-    CameraSettings, RayColorMode, RenderCommand, RenderConfig, RenderResult, RenderScene,
+    HdrImage, RayColorMode, RenderConfig,
 };
 
+use crate::{RenderCommand, RenderResult};
+
 #[derive(Debug, Default)]
-struct UiData {
+pub(crate) struct UiData {
     last_render_width: usize,
     last_render_height: usize,
     last_render_lines_received: usize,
-    last_render_pixels: Vec<RGB8>,
+    /// Passes completed and passes in total, when rendering progressively
+    last_render_passes: Option<(u32, u32)>,
+    /// Linear radiance of each pixel, and the AOVs if any were rendered
+    last_render: HdrImage,
+    /// `last_render` after tone mapping, ready for display
+    last_render_display_pixels: Vec<RGB8>,
+    tone_mapping: ToneMapping,
+    /// Settings for denoising `last_render`, if it should be
+    denoising: Option<Denoising>,
+    /// `last_render` after denoising, once there is a complete image (or pass) to denoise
+    last_render_denoised: Option<HdrImage>,
+    /// `last_render_denoised` after tone mapping
+    last_render_denoised_display_pixels: Vec<RGB8>,
+    view: ImageView,
     last_render_tex: Option<TextureId>,
+    last_render_error: Option<String>,
+    last_render_stopped: bool,
 
     terminal_initial_render_done: bool,
 }
 
 impl UiData {
-    fn new(width: usize, height: usize) -> Self {
+    pub(crate) fn new(
+        width: usize,
+        height: usize,
+        tone_mapping: ToneMapping,
+        denoising: Option<Denoising>,
+    ) -> Self {
         Self {
             last_render_width: width,
             last_render_height: height,
-            last_render_pixels: vec![RGB8 { r: 0, g: 0, b: 0 }; width * height],
+            last_render: HdrImage::new(width, height),
+            last_render_display_pixels: vec![RGB8 { r: 0, g: 0, b: 0 }; width * height],
+            tone_mapping,
+            denoising,
             ..Default::default()
         }
     }
 
+    /// Tone map the image again with new settings, returning whether anything changed.
+    fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) -> bool {
+        if tone_mapping == self.tone_mapping {
+            return false;
+        }
+        self.tone_mapping = tone_mapping;
+        self.last_render_display_pixels = tone_map_pixels(&self.last_render, &tone_mapping);
+        if let Some(ref denoised) = self.last_render_denoised {
+            self.last_render_denoised_display_pixels = tone_map_pixels(denoised, &tone_mapping);
+        }
+        true
+    }
+
+    /// Switch between showing the noisy and denoised images, returning whether anything changed.
+    fn set_view(&mut self, view: ImageView) -> bool {
+        let changed = view != self.view;
+        self.view = view;
+        changed
+    }
+
+    /// Denoise the image again with new settings (or stop denoising it), returning whether
+    /// anything changed.
+    fn set_denoising(&mut self, denoising: Option<Denoising>) -> bool {
+        if denoising == self.denoising {
+            return false;
+        }
+        self.denoising = denoising;
+        self.last_render_denoised = None;
+        if self.usable() {
+            self.update_denoised();
+        }
+        true
+    }
+
+    /// Denoise the image received so far, if denoising is on.
+    fn update_denoised(&mut self) {
+        if let Some(ref denoising) = self.denoising {
+            let denoised = denoise(&self.last_render, denoising);
+            self.set_denoised(denoised);
+        }
+    }
+
+    fn set_denoised(&mut self, denoised: HdrImage) {
+        self.last_render_denoised_display_pixels = tone_map_pixels(&denoised, &self.tone_mapping);
+        self.last_render_denoised = Some(denoised);
+    }
+
+    /// Width of the image shown for the current view.
+    fn display_width(&self) -> usize {
+        match self.view {
+            ImageView::SideBySide if self.last_render_denoised.is_some() => {
+                2 * self.last_render_width
+            }
+            _ => self.last_render_width,
+        }
+    }
+
+    /// The tone mapped pixels to show for the current view, and how wide the image they make is.
+    fn displayed_pixels(&self) -> (usize, Vec<RGB8>) {
+        let width = self.last_render_width;
+        if self.last_render_denoised.is_none() {
+            return (width, self.last_render_display_pixels.clone());
+        }
+        match self.view {
+            ImageView::Noisy => (width, self.last_render_display_pixels.clone()),
+            ImageView::Denoised => (width, self.last_render_denoised_display_pixels.clone()),
+            ImageView::SideBySide => {
+                let pixels = self
+                    .last_render_display_pixels
+                    .chunks(width)
+                    .zip(self.last_render_denoised_display_pixels.chunks(width))
+                    .flat_map(|(noisy, denoised)| noisy.iter().chain(denoised).copied())
+                    .collect();
+                (2 * width, pixels)
+            }
+        }
+    }
+
     fn rebuild_texture(&mut self, tex_allocator: &mut dyn eframe::epi::TextureAllocator) {
         if let Some(existing_tex) = self.last_render_tex {
             tex_allocator.free(existing_tex);
         }
-        let tex_pixels = self
-            .last_render_pixels
+        let (width, pixels) = self.displayed_pixels();
+        let tex_pixels = pixels
             .iter()
             .map(|rgb| egui::Color32::from_rgba_premultiplied(rgb.r, rgb.g, rgb.b, 255))
             .collect::<Vec<_>>();
-        self.last_render_tex = Some(tex_allocator.alloc_srgba_premultiplied(
-            (self.last_render_width, self.last_render_height),
-            &tex_pixels,
-        ));
+        self.last_render_tex = Some(
+            tex_allocator.alloc_srgba_premultiplied((width, self.last_render_height), &tex_pixels),
+        );
     }
 
     fn clear_texture(&mut self, tex_allocator: &mut dyn eframe::epi::TextureAllocator) {
@@ -55,28 +165,55 @@ impl UiData {
         }
     }
 
-    fn store_pixel_line(&mut self, line_num: usize, line_pixels: Vec<RGB8>) {
-        assert_eq!(line_pixels.len(), self.last_render_width);
+    pub(crate) fn store_pixel_line(&mut self, line_num: usize, line: HdrImage) {
+        assert_eq!(line.width, self.last_render_width);
         assert!(self.last_render_lines_received < self.last_render_height);
         self.last_render_lines_received += 1;
 
         // update the image buffer
         let line_num = self.last_render_height - line_num - 1;
-        let offset_start = line_num as usize * self.last_render_width;
+        self.last_render.set_row(line_num, &line);
+        let offset_start = line_num * self.last_render_width;
         let offset_end = offset_start + self.last_render_width;
-        self.last_render_pixels[offset_start..offset_end].copy_from_slice(line_pixels.as_slice());
-    }
+        for (display, &pixel) in self.last_render_display_pixels[offset_start..offset_end]
+            .iter_mut()
+            .zip(line.pixels.iter())
+        {
+            *display = self.tone_mapping.to_rgb8(rgb_to_color(pixel));
+        }
 
-    fn render_terminal_progress_indicator(&mut self, settings: &TerminalSettings, line_num: usize) {
-        use std::fmt::Write; // needed to use write! with strings
+        if self.complete() {
+            self.update_denoised();
+        }
+    }
 
-        let TerminalSettings {
-            desired_width,
-            desired_height,
-        } = *settings;
+    pub(crate) fn store_frame(
+        &mut self,
+        image: HdrImage,
+        denoised: Option<(Denoising, HdrImage)>,
+        passes_completed: u32,
+        total_passes: u32,
+    ) {
+        assert_eq!(image.pixels.len(), self.last_render.pixels.len());
+        self.last_render_display_pixels = tone_map_pixels(&image, &self.tone_mapping);
+        self.last_render = image;
+        self.last_render_passes = Some((passes_completed, total_passes));
+        // every pass covers the whole image, so each one is denoised, which the render thread
+        // has already done unless the settings have changed since the render started
+        match denoised {
+            Some((settings, denoised)) if self.denoising == Some(settings) => {
+                self.set_denoised(denoised)
+            }
+            _ => self.update_denoised(),
+        }
+    }
 
-        let height_ratio = self.last_render_height as f64 / desired_height as f64;
-        let width_ratio = self.last_render_width as f64 / desired_width as f64;
+    pub(crate) fn render_terminal_progress_indicator(
+        &mut self,
+        settings: &TerminalSettings,
+        line_num: usize,
+    ) {
+        let height_ratio = self.last_render_height as f64 / settings.desired_height as f64;
 
         // Terminals are slow, so if we output every line to stdout then our app will end up blocking
         // writing on stdout, which will cause the UI thread to hang. Therefore we only output a line
@@ -89,67 +226,128 @@ impl UiData {
         let should_rerender =
             (height_ratio * 0.99999999999 + line_num as f64 + 1.0).rem(height_ratio) < 1.0;
         if should_rerender {
-            // string sizing note: width + 1 char for newline on each line, plus an arbitrary 10 bytes
-            // for the "move cursor up" terminal escape code we might have
-            let mut output = String::with_capacity((desired_width + 1) * desired_height + 10);
+            self.render_terminal_frame(settings);
+        }
+    }
 
-            if self.terminal_initial_render_done {
-                write!(output, "{}", termion::cursor::Up(desired_height as u16)).unwrap();
-            }
-            for j in 0..desired_height {
-                let y = (j as f64 * height_ratio) as usize;
-                for i in 0..desired_width {
-                    let x = (i as f64 * width_ratio) as usize;
-                    let pixel = self.last_render_pixels[y * self.last_render_width + x];
-                    write!(output, "{}", rgb8_as_terminal_char(pixel)).unwrap();
-                }
-                writeln!(output).unwrap();
-            }
+    /// Draw the whole image so far in the terminal, replacing the previous drawing.
+    pub(crate) fn render_terminal_frame(&mut self, settings: &TerminalSettings) {
+        use std::fmt::Write; // needed to use write! with strings
 
-            std::io::stdout()
-                .lock()
-                .write_all(output.as_bytes())
-                .unwrap();
+        let TerminalSettings {
+            desired_width,
+            desired_height,
+        } = *settings;
 
-            self.terminal_initial_render_done = true;
+        let height_ratio = self.last_render_height as f64 / desired_height as f64;
+        let width_ratio = self.last_render_width as f64 / desired_width as f64;
+
+        // string sizing note: width + 1 char for newline on each line, plus an arbitrary 10 bytes
+        // for the "move cursor up" terminal escape code we might have
+        let mut output = String::with_capacity((desired_width + 1) * desired_height + 10);
+
+        if self.terminal_initial_render_done {
+            write!(output, "{}", termion::cursor::Up(desired_height as u16)).unwrap();
         }
+        for j in 0..desired_height {
+            let y = (j as f64 * height_ratio) as usize;
+            for i in 0..desired_width {
+                let x = (i as f64 * width_ratio) as usize;
+                let pixel = self.last_render_display_pixels[y * self.last_render_width + x];
+                write!(output, "{}", rgb8_as_terminal_char(pixel)).unwrap();
+            }
+            writeln!(output).unwrap();
+        }
+
+        std::io::stdout()
+            .lock()
+            .write_all(output.as_bytes())
+            .unwrap();
+
+        self.terminal_initial_render_done = true;
     }
 
-    fn save_output_to_file(&self, output_filename: &str) {
+    /// Save the image (denoised, if denoising is on) with the AOVs that `config` asks for.
+    pub(crate) fn save_output_to_file(&self, config: &RenderConfig) -> Result<(), ImageFileError> {
         // make sure we got all the data we should have
         assert_eq!(
-            self.last_render_pixels.len(),
+            self.last_render.pixels.len(),
             self.last_render_width * self.last_render_height
         );
 
+        let output_filename = &config.output_filename;
+        let format = ImageFormat::from_path(Path::new(output_filename))?;
         print!(
-            "Saving completed image to disk at {} in PNG format...",
-            output_filename
+            "Saving completed image to disk at {} in {} format...",
+            output_filename,
+            format!("{:?}", format).to_uppercase()
         );
-        lodepng::encode_file(
+        let mut image = self
+            .last_render_denoised
+            .as_ref()
+            .unwrap_or(&self.last_render)
+            .clone();
+        // the denoiser's guides are only saved if they were asked for
+        image.aovs.retain(|layer| config.aovs.contains(&layer.aov));
+        save_image(
             output_filename,
-            &self.last_render_pixels,
-            self.last_render_width,
-            self.last_render_height,
-            lodepng::ColorType::RGB,
-            8,
-        )
-        .expect("Encoding result and saving to disk failed");
-
-        println!(" done saving.");
+            &image,
+            &self.tone_mapping,
+            config.exr_precision,
+        )?;
+
+        match (format, image.aovs.len()) {
+            (_, 0) => println!(" done saving."),
+            (ImageFormat::Exr, layers) => println!(" done saving, with {} AOV layers.", layers),
+            (_, layers) => println!(
+                " done saving, with {} AOV layers in {}.",
+                layers,
+                aov_path(Path::new(output_filename), "*").display()
+            ),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn complete(&self) -> bool {
+        match self.last_render_passes {
+            Some((completed, total)) => completed == total,
+            None => self.last_render_lines_received == self.last_render_height,
+        }
     }
 
-    fn complete(&self) -> bool {
-        self.last_render_lines_received == self.last_render_height
+    /// Whether there's an image worth saving, even if the render isn't complete.
+    fn usable(&self) -> bool {
+        self.complete() || self.last_render_passes.is_some()
     }
 
     fn percent_complete(&self) -> f32 {
-        self.last_render_lines_received as f32 / self.last_render_height as f32
+        match self.last_render_passes {
+            Some((completed, total)) => completed as f32 / total as f32,
+            None => self.last_render_lines_received as f32 / self.last_render_height as f32,
+        }
     }
 }
 
+fn tone_map_pixels(image: &HdrImage, tone_mapping: &ToneMapping) -> Vec<RGB8> {
+    image
+        .pixels
+        .iter()
+        .map(|&p| tone_mapping.to_rgb8(rgb_to_color(p)))
+        .collect()
+}
+
+/// Which image to show when denoising.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ImageView {
+    Noisy,
+    #[default]
+    Denoised,
+    /// The noisy image on the left and the denoised one on the right
+    SideBySide,
+}
+
 #[derive(Clone, Copy, Debug)]
-struct TerminalSettings {
+pub(crate) struct TerminalSettings {
     desired_width: usize,
     desired_height: usize,
 }
@@ -167,8 +365,12 @@ impl Default for TerminalSettings {
 pub struct TemplateApp {
     config: RenderConfig,
     data: Option<UiData>,
+    /// Which image to show when the render is denoised
+    view: ImageView,
 
     scene_to_camera: HashMap<RenderScene, CameraSettings>,
+    /// Whether the settings came from the command line, so the saved ones shouldn't replace them
+    keep_initial_settings: bool,
 
     terminal_display: Option<TerminalSettings>,
 
@@ -177,17 +379,27 @@ pub struct TemplateApp {
 }
 
 impl TemplateApp {
+    /// Create the app, starting from `initial_settings` if there are any, or else from the settings
+    /// saved when it last quit.
     pub(crate) fn new(
         render_command_tx: flume::Sender<RenderCommand>,
         render_result_rx: flume::Receiver<RenderResult>,
+        initial_settings: Option<(RenderConfig, CameraSettings)>,
     ) -> Self {
-        let config = RenderConfig {
-            ..Default::default()
+        let mut scene_to_camera = HashMap::new();
+        let config = match initial_settings {
+            Some((config, cam_settings)) => {
+                scene_to_camera.insert(config.scene.clone(), cam_settings);
+                config
+            }
+            None => RenderConfig::default(),
         };
         TemplateApp {
             config,
             data: Default::default(),
-            scene_to_camera: HashMap::new(),
+            view: ImageView::default(),
+            keep_initial_settings: !scene_to_camera.is_empty(),
+            scene_to_camera,
             terminal_display: Some(TerminalSettings::default()),
             render_command_tx,
             render_result_rx,
@@ -196,11 +408,12 @@ impl TemplateApp {
 
     fn trigger_render(&self) {
         println!(
-            "Triggering render of {width}x{height} image (total {count} pixels), with {samples} samples per pixel",
+            "Triggering render of {width}x{height} image (total {count} pixels), with {samples} samples per pixel and seed {seed}",
             width =self. config.image_width,
             height =self. config.image_height,
             count = self.config.image_pixel_count(),
             samples =self. config.samples_per_pixel,
+            seed = self.config.seed,
         );
 
         self.render_command_tx
@@ -209,12 +422,29 @@ impl TemplateApp {
                     .scene_to_camera
                     .get(&self.config.scene)
                     .to_owned()
-                    .unwrap_or(&self.config.scene.default_camera_settings()),
+                    .unwrap_or(&default_camera_settings(&self.config.scene)),
                 config: self.config.clone(),
             })
             .ok()
             .expect("render command send should succeed");
     }
+
+    /// Stop rendering, saving the image if enough of it has been rendered to be useful.
+    fn stop_render(&mut self) {
+        self.render_command_tx
+            .send(RenderCommand::Stop)
+            .ok()
+            .expect("render command send should succeed");
+
+        if let Some(ref mut data) = self.data {
+            data.last_render_stopped = true;
+            if data.usable() && !data.complete() {
+                if let Err(e) = data.save_output_to_file(&self.config) {
+                    eprintln!(" saving failed: {}", e);
+                }
+            }
+        }
+    }
 }
 
 impl epi::App for TemplateApp {
@@ -230,7 +460,7 @@ impl epi::App for TemplateApp {
         _storage: Option<&dyn epi::Storage>,
     ) {
         // Load previous app state (if any).
-        if let Some(storage) = _storage {
+        if let Some(storage) = _storage.filter(|_| !self.keep_initial_settings) {
             self.config = epi::get_value(storage, epi::APP_KEY).unwrap_or_default()
         }
 
@@ -257,28 +487,64 @@ impl epi::App for TemplateApp {
                     if let Some(ref mut d) = self.data {
                         d.clear_texture(frame.tex_allocator());
                     }
-                    self.data = Some(UiData::new(image_width, image_height));
+                    self.data = Some(UiData::new(
+                        image_width,
+                        image_height,
+                        self.config.tone_mapping,
+                        self.config.denoising,
+                    ));
                 }
-                Ok(RenderResult::ImageLine {
-                    line_num,
-                    line_pixels,
-                }) => {
+                Ok(RenderResult::ImageLine { line_num, line }) => {
                     let data = self
                         .data
                         .as_mut()
                         .expect("ui data must be present for storing pixels");
 
-                    data.store_pixel_line(line_num, line_pixels);
+                    data.store_pixel_line(line_num, line);
 
                     if let Some(settings) = self.terminal_display {
                         data.render_terminal_progress_indicator(&settings, line_num);
                     }
 
                     if data.complete() {
-                        data.save_output_to_file(self.config.output_filename.as_ref());
+                        if let Err(e) = data.save_output_to_file(&self.config) {
+                            eprintln!(" saving failed: {}", e);
+                        }
                     }
                     data.rebuild_texture(frame.tex_allocator());
                 }
+                Ok(RenderResult::Frame {
+                    image,
+                    denoised,
+                    passes_completed,
+                    total_passes,
+                }) => {
+                    let data = self
+                        .data
+                        .as_mut()
+                        .expect("ui data must be present for storing pixels");
+
+                    data.store_frame(image, denoised, passes_completed, total_passes);
+
+                    if let Some(settings) = self.terminal_display {
+                        data.render_terminal_frame(&settings);
+                    }
+
+                    if data.complete() {
+                        if let Err(e) = data.save_output_to_file(&self.config) {
+                            eprintln!(" saving failed: {}", e);
+                        }
+                    }
+                    data.rebuild_texture(frame.tex_allocator());
+                }
+                // the GUI has nowhere to show these, so they're only printed when headless
+                Ok(RenderResult::Built { .. }) => {}
+                Ok(RenderResult::Failed { message }) => {
+                    eprintln!("{}", message);
+                    if let Some(ref mut data) = self.data {
+                        data.last_render_error = Some(message);
+                    }
+                }
                 Err(flume::TryRecvError::Empty) => break,
                 Err(flume::TryRecvError::Disconnected) => {
                     panic!("Rendering thread seems to have exited before UI!")
@@ -344,14 +610,63 @@ impl epi::App for TemplateApp {
                                 RenderScene::FinalScene,
                                 "Final Scene",
                             );
+                            ui.radio_value(
+                                &mut self.config.scene,
+                                RenderScene::Instances,
+                                "Instances",
+                            );
+                            let is_file = matches!(self.config.scene, RenderScene::File(_));
+                            if ui.radio(is_file, "Scene file").clicked() && !is_file {
+                                self.config.scene =
+                                    RenderScene::File("scenes/cornel-box.ron".to_owned());
+                            }
+                            if let RenderScene::File(ref mut path) = self.config.scene {
+                                ui.text_edit_singleline(path);
+                            }
                         });
                     });
                     ui.end_row();
 
+                    let mut sky = self.config.sky.is_some();
+                    ui.checkbox(
+                        &mut sky,
+                        "Light with a sun and sky instead of the scene's background",
+                    );
+                    if sky {
+                        let settings = self.config.sky.get_or_insert(Default::default());
+                        ui.collapsing("Sun and sky settings", |ui| sky_editor(ui, settings));
+                    } else {
+                        self.config.sky = None;
+                    }
+                    ui.end_row();
+
                     ui.horizontal(|ui| {
                         ui.label("Save as");
                         ui.text_edit_singleline(&mut self.config.output_filename);
                     });
+                    let saving_exr =
+                        ImageFormat::from_path(Path::new(&self.config.output_filename))
+                            .is_ok_and(|format| format == ImageFormat::Exr);
+                    if saving_exr {
+                        let mut half = self.config.exr_precision == ExrPrecision::Half;
+                        ui.checkbox(&mut half, "Save EXR channels as half floats");
+                        self.config.exr_precision = if half {
+                            ExrPrecision::Half
+                        } else {
+                            ExrPrecision::Float
+                        };
+                    }
+                    ui.collapsing("AOVs (auxiliary images) to save", |ui| {
+                        let mut aovs = Vec::new();
+                        for aov in Aov::ALL {
+                            let mut enabled = self.config.aovs.contains(&aov);
+                            ui.checkbox(&mut enabled, aov.name());
+                            if enabled {
+                                aovs.push(aov);
+                            }
+                        }
+                        self.config.aovs = aovs;
+                    });
                     ui.end_row();
 
                     ui.vertical_centered_justified(|ui| {
@@ -359,6 +674,15 @@ impl epi::App for TemplateApp {
                         if ui.add(button).clicked() {
                             self.trigger_render();
                         }
+                        let rendering = self
+                            .data
+                            .as_ref()
+                            .is_some_and(|d| !d.complete() && !d.last_render_stopped);
+                        let button =
+                            egui::widgets::Button::new("Stop rendering").enabled(rendering);
+                        if ui.add(button).clicked() {
+                            self.stop_render();
+                        }
                     });
                     ui.end_row();
 
@@ -367,11 +691,47 @@ impl epi::App for TemplateApp {
                             &mut self.config.display_actual_size,
                             "Display render at actual 1:1 size",
                         );
+
+                        let tone_mapping = &mut self.config.tone_mapping;
+                        egui::ComboBox::from_label("Tone mapping")
+                            .selected_text(format!("{:?}", tone_mapping.operator))
+                            .show_ui(ui, |ui| {
+                                for operator in [
+                                    ToneMapOperator::Clamp,
+                                    ToneMapOperator::Reinhard,
+                                    ToneMapOperator::Filmic,
+                                    ToneMapOperator::Aces,
+                                ] {
+                                    ui.selectable_value(
+                                        &mut tone_mapping.operator,
+                                        operator,
+                                        format!("{:?}", operator),
+                                    );
+                                }
+                            });
+                        ui.add(
+                            egui::Slider::new(&mut tone_mapping.exposure, -8.0..=8.0)
+                                .suffix(" stops")
+                                .text("Exposure"),
+                        );
+
+                        if self.config.denoising.is_some() {
+                            ui.horizontal(|ui| {
+                                ui.label("Show");
+                                ui.radio_value(&mut self.view, ImageView::Noisy, "Noisy");
+                                ui.radio_value(&mut self.view, ImageView::Denoised, "Denoised");
+                                ui.radio_value(
+                                    &mut self.view,
+                                    ImageView::SideBySide,
+                                    "Side by side",
+                                );
+                            });
+                        }
                     });
 
                     ui.collapsing("Terminal display options", |ui| {
                         let mut terminal_progress = self.terminal_display.is_some();
-                        ui.checkbox(&mut &mut terminal_progress, "Render progress in terminal");
+                        ui.checkbox(&mut terminal_progress, "Render progress in terminal");
 
                         if terminal_progress {
                             let settings = self.terminal_display.get_or_insert(Default::default());
@@ -423,12 +783,121 @@ impl epi::App for TemplateApp {
                         );
                         ui.end_row();
 
+                        ui.horizontal(|ui| {
+                            ui.label("Random seed");
+                            ui.add(egui::widgets::DragValue::new(&mut self.config.seed).speed(1));
+                            if ui.button("Randomize").clicked() {
+                                // stay well within what the drag value can show exactly
+                                self.config.seed = rand::random::<u32>() as u64;
+                            }
+                        });
+                        ui.end_row();
+
+                        egui::ComboBox::from_label("Sampler")
+                            .selected_text(format!("{:?}", self.config.sampler))
+                            .show_ui(ui, |ui| {
+                                for kind in [
+                                    SamplerKind::Independent,
+                                    SamplerKind::Stratified,
+                                    SamplerKind::Halton,
+                                    SamplerKind::Sobol,
+                                ] {
+                                    ui.selectable_value(
+                                        &mut self.config.sampler,
+                                        kind,
+                                        format!("{:?}", kind),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.checkbox(
+                            &mut self.config.progressive,
+                            "Progressive (refine the whole image a pass at a time)",
+                        );
+                        ui.end_row();
+
+                        let mut adaptive = self.config.adaptive_sampling.is_some();
+                        ui.checkbox(
+                            &mut adaptive,
+                            "Adaptive sampling (more samples for noisy pixels)",
+                        );
+                        if adaptive {
+                            let settings = self
+                                .config
+                                .adaptive_sampling
+                                .get_or_insert(Default::default());
+                            ui.collapsing("Adaptive sampling settings", |ui| {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.noise_threshold, 0.001..=0.5)
+                                        .logarithmic(true)
+                                        .text("Noise threshold"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.min_samples, 1..=100)
+                                        .text("Min samples per pixel"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.max_samples, 1..=2000)
+                                        .logarithmic(true)
+                                        .text("Max samples per pixel"),
+                                );
+                                ui.checkbox(
+                                    &mut settings.show_sample_counts,
+                                    "Show sample count per pixel",
+                                );
+                            });
+                        } else {
+                            self.config.adaptive_sampling = None;
+                        }
+                        ui.end_row();
+
+                        let mut denoise = self.config.denoising.is_some();
+                        ui.checkbox(
+                            &mut denoise,
+                            "Denoise (guided by the albedo, normal, depth and variance AOVs)",
+                        );
+                        if denoise {
+                            let settings = self.config.denoising.get_or_insert(Default::default());
+                            ui.collapsing("Denoising settings", |ui| {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.iterations, 1..=MAX_ITERATIONS)
+                                        .text("Filter passes"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.luminance_sigma, 0.5..=32.0)
+                                        .logarithmic(true)
+                                        .text("Brightness tolerance"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.normal_power, 1.0..=256.0)
+                                        .logarithmic(true)
+                                        .text("Normal sharpness"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.depth_sigma, 0.001..=1.0)
+                                        .logarithmic(true)
+                                        .text("Depth tolerance"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.albedo_sigma, 0.01..=1.0)
+                                        .logarithmic(true)
+                                        .text("Albedo tolerance"),
+                                );
+                            });
+                        } else {
+                            self.config.denoising = None;
+                        }
+                        ui.end_row();
+
                         egui::ComboBox::from_label("Render mode")
                             .selected_text(match self.config.render_mode {
                                 RayColorMode::BlockColor { .. } => "Block color",
                                 RayColorMode::ShadeNormal => "Normals",
                                 RayColorMode::Depth { .. } => "Depth test",
                                 RayColorMode::Material { .. } => "Material",
+                                RayColorMode::ImportanceSampled { .. } => "Importance sampled",
+                                RayColorMode::Spectral { .. } => "Spectral",
                             })
                             .show_ui(ui, |ui| {
                                 ui.selectable_value(
@@ -453,6 +922,16 @@ impl epi::App for TemplateApp {
                                     RayColorMode::Material { depth: 50 },
                                     "Material",
                                 );
+                                ui.selectable_value(
+                                    &mut self.config.render_mode,
+                                    RayColorMode::ImportanceSampled { depth: 50 },
+                                    "Importance sampled",
+                                );
+                                ui.selectable_value(
+                                    &mut self.config.render_mode,
+                                    RayColorMode::Spectral { depth: 50 },
+                                    "Spectral",
+                                );
                             });
                         ui.end_row();
 
@@ -469,7 +948,9 @@ impl epi::App for TemplateApp {
                                     ui.add(egui::Slider::new(max_t, 0.0..=20.0).text("Distance"));
                                 });
                             }
-                            RayColorMode::Material { ref mut depth } => {
+                            RayColorMode::Material { ref mut depth }
+                            | RayColorMode::ImportanceSampled { ref mut depth }
+                            | RayColorMode::Spectral { ref mut depth } => {
                                 ui.collapsing(sub_heading, |ui| {
                                     ui.add(
                                         egui::Slider::new(depth, 1..=100)
@@ -482,14 +963,14 @@ impl epi::App for TemplateApp {
                     });
 
                     ui.collapsing("Camera options", |ui| {
-                        let current_scene = self.config.scene;
+                        let current_scene = self.config.scene.clone();
                         let cam = self
                             .scene_to_camera
-                            .entry(self.config.scene)
-                            .or_insert_with(|| current_scene.default_camera_settings());
+                            .entry(self.config.scene.clone())
+                            .or_insert_with(|| default_camera_settings(&current_scene));
                         ui.collapsing("Reset to default", |ui| {
                             if ui.button("Load default camera settings").clicked() {
-                                *cam = current_scene.default_camera_settings();
+                                *cam = default_camera_settings(&current_scene);
                             }
                         });
 
@@ -538,23 +1019,37 @@ impl epi::App for TemplateApp {
                 })
             });
 
+        // tone mapping is cheap, so changes to it are shown without re-rendering
+        if let Some(ref mut data) = self.data {
+            let view_changed = data.set_view(self.view);
+            let denoising_changed = data.set_denoising(self.config.denoising);
+            if data.set_tone_mapping(self.config.tone_mapping) || view_changed || denoising_changed
+            {
+                data.rebuild_texture(frame.tex_allocator());
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(ref mut data) = self.data {
+                if let Some(ref error) = data.last_render_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
                 ui.add(
                     egui::ProgressBar::new(data.percent_complete())
-                        .animate(!data.complete())
+                        .animate(
+                            !data.complete()
+                                && data.last_render_error.is_none()
+                                && !data.last_render_stopped,
+                        )
                         .desired_width(if self.config.display_actual_size {
-                            data.last_render_width as f32
+                            data.display_width() as f32
                         } else {
                             ui.available_width()
                         }),
                 );
 
                 let image_sizing = if self.config.display_actual_size {
-                    egui::Vec2::new(
-                        data.last_render_width as f32,
-                        data.last_render_height as f32,
-                    )
+                    egui::Vec2::new(data.display_width() as f32, data.last_render_height as f32)
                 } else {
                     let mut available = ui.available_size();
                     available.y -= 25.0;
@@ -571,6 +1066,62 @@ impl epi::App for TemplateApp {
     }
 }
 
+fn sky_editor(ui: &mut egui::Ui, settings: &mut SkySettings) {
+    ui.add(egui::Slider::new(&mut settings.turbidity, 1.7..=10.0).text("Haziness (turbidity)"));
+    ui.add(egui::Slider::new(&mut settings.intensity, 0.0..=4.0).text("Brightness"));
+
+    let by_time = matches!(settings.sun, SunPosition::Time { .. });
+    ui.horizontal(|ui| {
+        if ui.radio(!by_time, "Sun angles").clicked() && by_time {
+            let (elevation, azimuth) = settings.sun.angles();
+            settings.sun = SunPosition::Angles { elevation, azimuth };
+        }
+        if ui.radio(by_time, "Time of day").clicked() && !by_time {
+            settings.sun = SunPosition::Time {
+                day_of_year: 172,
+                hour: 15.0,
+                latitude: 45.0,
+            };
+        }
+    });
+    match settings.sun {
+        SunPosition::Angles {
+            ref mut elevation,
+            ref mut azimuth,
+        } => {
+            ui.add(
+                egui::Slider::new(elevation, -10.0..=90.0)
+                    .suffix("°")
+                    .text("Sun elevation"),
+            );
+            ui.add(
+                egui::Slider::new(azimuth, 0.0..=360.0)
+                    .suffix("°")
+                    .text("Sun azimuth (clockwise from north, -z)"),
+            );
+        }
+        SunPosition::Time {
+            ref mut day_of_year,
+            ref mut hour,
+            ref mut latitude,
+        } => {
+            ui.add(egui::Slider::new(day_of_year, 1..=365).text("Day of the year"));
+            ui.add(egui::Slider::new(hour, 0.0..=24.0).text("Hour (solar time)"));
+            ui.add(
+                egui::Slider::new(latitude, -90.0..=90.0)
+                    .suffix("°")
+                    .text("Latitude (north)"),
+            );
+        }
+    }
+}
+
+/// The camera that `scene` starts with. If its file can't be read, rendering it fails and shows
+/// why, so until it's fixed any camera will do.
+fn default_camera_settings(scene: &RenderScene) -> CameraSettings {
+    scene.default_camera_settings().unwrap_or_default()
+}
+
 fn vec3_editor(ui: &mut egui::Ui, label: &str, v: &mut Vec3) {
     let speed = 0.1;
 
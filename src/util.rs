@@ -1,14 +1,62 @@
+use std::cell::RefCell;
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::sampler;
+
+thread_local! {
+    /// Where all of the random numbers below come from. Each thread has its own, so that seeding
+    /// it with `with_seed` makes a piece of work reproducible no matter which thread runs it.
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Run `f` with this thread's random numbers coming from a stream picked by `seed`, then go back
+/// to the stream that was in use before.
+pub(crate) fn with_seed<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    let previous = RNG.with(|rng| rng.replace(SmallRng::seed_from_u64(seed)));
+    let result = f();
+    RNG.with(|rng| rng.replace(previous));
+    result
+}
+
+/// Combine `seed` with `values` into a new seed, so that e.g. every sample of every pixel can get
+/// its own stream of random numbers.
+pub(crate) fn mix_seed(seed: u64, values: &[u64]) -> u64 {
+    // splitmix64's finalizer, which spreads out small differences in its input
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    values.iter().fold(mix(seed), |acc, &v| {
+        mix(acc ^ v.wrapping_add(0x9e3779b97f4a7c15))
+    })
+}
+
 pub(crate) fn random_int(min: i32, max: i32) -> i32 {
     // +1 because the max is inclusive!
-    min + ((max + 1 - min) as f64 * rand::random::<f64>()) as i32
+    min + ((max + 1 - min) as f64 * random_double_unit()) as i32
 }
 
 pub(crate) fn random_double(min: f64, max: f64) -> f64 {
-    min + (max - min) * rand::random::<f64>()
+    min + (max - min) * random_double_unit()
 }
 
+/// A random number in [0, 1), taken from the sampler if a pixel sample is being traced.
 pub(crate) fn random_double_unit() -> f64 {
-    random_double(0.0, 1.0)
+    sampler::next_1d().unwrap_or_else(|| RNG.with(|rng| rng.borrow_mut().gen::<f64>()))
+}
+
+/// A random point in [0, 1)², for anything that picks a point on a 2D domain (like a disk or a
+/// sphere's surface); taking both numbers together lets samplers spread the points out evenly.
+pub(crate) fn random_pair() -> (f64, f64) {
+    sampler::next_2d().unwrap_or_else(|| {
+        RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (rng.gen::<f64>(), rng.gen::<f64>())
+        })
+    })
 }
 
 pub(crate) fn degrees_to_radians(degrees: f64) -> f64 {
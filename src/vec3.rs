@@ -2,7 +2,7 @@ use std::ops::{Div, DivAssign, Index, IndexMut, Mul, MulAssign};
 
 use derive_more::{Add, AddAssign, Constructor, Display, Neg, Sub, SubAssign, Sum};
 
-use crate::util::{random_double, random_double_unit};
+use crate::util::{random_double, random_double_unit, random_pair};
 
 #[derive(
     Debug,
@@ -22,22 +22,22 @@ use crate::util::{random_double, random_double_unit};
     Neg,
 )]
 #[display(fmt = "{} {} {}", x, y, z)]
-pub(crate) struct Vec3 {
+pub struct Vec3 {
     pub x: f64,
     pub y: f64,
     pub z: f64,
 }
 
-pub(crate) type Point3 = Vec3;
-pub(crate) type Color = Vec3;
+pub type Point3 = Vec3;
+pub type Color = Vec3;
 
 impl Vec3 {
     #[allow(dead_code)]
-    pub(crate) fn zero() -> Self {
+    pub fn zero() -> Self {
         Default::default()
     }
 
-    pub(crate) fn one() -> Self {
+    pub fn one() -> Self {
         Vec3 {
             x: 1.0,
             y: 1.0,
@@ -45,7 +45,7 @@ impl Vec3 {
         }
     }
 
-    pub(crate) fn random(min: f64, max: f64) -> Self {
+    pub fn random(min: f64, max: f64) -> Self {
         Vec3 {
             x: random_double(min, max),
             y: random_double(min, max),
@@ -54,7 +54,7 @@ impl Vec3 {
     }
 
     #[allow(dead_code)]
-    pub(crate) fn random_unit() -> Self {
+    pub fn random_unit() -> Self {
         Vec3 {
             x: random_double_unit(),
             y: random_double_unit(),
@@ -62,20 +62,21 @@ impl Vec3 {
         }
     }
 
-    pub(crate) fn random_in_unit_sphere() -> Self {
-        loop {
-            let p: Point3 = Vec3::random(-1.0, 1.0);
-            if p.length_squared() < 1.0 {
-                break p;
-            }
-        }
+    pub fn random_in_unit_sphere() -> Self {
+        // the cube root spreads points evenly through the volume rather than bunching them at the
+        // center
+        Self::random_unit_vector() * random_double_unit().cbrt()
     }
 
-    pub(crate) fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().to_unit()
+    pub fn random_unit_vector() -> Self {
+        let (r1, r2) = random_pair();
+        let z = 1.0 - 2.0 * r1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * r2;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
-    pub(crate) fn random_in_hemisphere(normal: Vec3) -> Self {
+    pub fn random_in_hemisphere(normal: Vec3) -> Self {
         let in_unit_sphere = Self::random_in_unit_sphere();
         if in_unit_sphere.dot(normal) > 0.0 {
             in_unit_sphere
@@ -84,45 +85,55 @@ impl Vec3 {
         }
     }
 
-    pub(crate) fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(random_double(-1.0, 1.0), random_double(-1.0, 1.0), 0.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
+    pub fn random_in_unit_disk() -> Vec3 {
+        // Shirley's concentric mapping from the square, which keeps evenly spread samples evenly
+        // spread
+        let (r1, r2) = random_pair();
+        let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::zero();
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, std::f64::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (
+                b,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+            )
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 
-    pub(crate) fn reflect(v: Vec3, n: Vec3) -> Vec3 {
+    pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
         v - 2.0 * v.dot(n) * n
     }
 
-    pub(crate) fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
+    pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
         let cos_theta = f64::min((-uv).dot(n), 1.0);
         let r_out_perp = etai_over_etat * (uv + cos_theta * n);
         let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
         r_out_perp + r_out_parallel
     }
 
-    pub(crate) fn near_zero(&self) -> bool {
+    pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         self.x.abs() < s && self.y.abs() < s && self.z.abs() < s
     }
 
-    pub(crate) fn length_squared(&self) -> f64 {
+    pub fn length_squared(&self) -> f64 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub(crate) fn length(&self) -> f64 {
+    pub fn length(&self) -> f64 {
         self.length_squared().sqrt()
     }
 
-    pub(crate) fn dot(&self, other: Vec3) -> f64 {
+    pub fn dot(&self, other: Vec3) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
     #[allow(dead_code)]
-    pub(crate) fn cross(&self, other: Vec3) -> Vec3 {
+    pub fn cross(&self, other: Vec3) -> Vec3 {
         Vec3 {
             x: self.y * other.z - self.z * other.y,
             y: self.z * other.x - self.x * other.z,
@@ -130,7 +141,7 @@ impl Vec3 {
         }
     }
 
-    pub(crate) fn to_unit(self) -> Vec3 {
+    pub fn to_unit(self) -> Vec3 {
         self / self.length()
     }
 }
@@ -219,7 +230,7 @@ impl IndexMut<usize> for Vec3 {
     }
 }
 
-pub(crate) fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+pub fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
     (1.0 - t) * a + t * b
 }
 
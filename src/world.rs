@@ -0,0 +1,82 @@
+use crate::{
+    bvh_node::BvhNode,
+    environment::Environment,
+    hittable::{Hittable, HittableList},
+    ray::Ray,
+    util,
+    vec3::{Point3, Vec3},
+};
+
+/// Everything that can be seen in a render.
+#[derive(Debug)]
+pub struct World {
+    /// What rays that hit nothing see.
+    pub environment: Environment,
+    pub node: BvhNode,
+    /// The objects in `node` that give off light (or copies of them), so that they can be sampled
+    /// directly when rendering with [`crate::RayColorMode::ImportanceSampled`].
+    pub lights: HittableList,
+}
+
+impl World {
+    pub fn new(node: BvhNode, environment: Environment) -> Self {
+        Self {
+            environment,
+            node,
+            lights: HittableList::new(),
+        }
+    }
+
+    pub fn with_lights(self, lights: HittableList) -> Self {
+        Self { lights, ..self }
+    }
+
+    /// Whether there's any light to sample directly, in [`World::lights`] or the environment.
+    pub fn has_sampled_lights(&self) -> bool {
+        !self.lights.objects.is_empty() || self.environment.is_sampled()
+    }
+
+    /// The chance that [`World::random_light_direction`] samples the environment rather than
+    /// [`World::lights`].
+    fn environment_share(&self) -> f64 {
+        match (
+            self.lights.objects.is_empty(),
+            self.environment.is_sampled(),
+        ) {
+            (_, false) => 0.0,
+            (true, true) => 1.0,
+            (false, true) => 0.5,
+        }
+    }
+
+    /// Probability density (per unit solid angle) of [`World::random_light_direction`] picking
+    /// the direction of `r`.
+    pub fn light_pdf_value(&self, r: Ray) -> f64 {
+        let share = self.environment_share();
+        let mut pdf = 0.0;
+        if share > 0.0 {
+            pdf += share * self.environment.pdf_value(r.direction());
+        }
+        if share < 1.0 {
+            pdf += (1.0 - share) * self.lights.pdf_value(r);
+        }
+        pdf
+    }
+
+    /// Pick a random direction from `origin` towards one of the lights or the environment at
+    /// `time`. Only meaningful if [`World::has_sampled_lights`].
+    pub fn random_light_direction(&self, origin: Point3, time: f64) -> Vec3 {
+        let share = self.environment_share();
+        if share >= 1.0 || (share > 0.0 && util::random_double_unit() < share) {
+            self.environment.random_direction()
+        } else {
+            self.lights.random_direction(origin, time)
+        }
+    }
+}
+
+impl From<BvhNode> for World {
+    fn from(node: BvhNode) -> Self {
+        Self::new(node, Default::default())
+    }
+}